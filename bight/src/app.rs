@@ -1,3 +1,35 @@
+use std::collections::VecDeque;
+
+use crate::key::Key;
+
+/// How many keys mappings may produce before the next key typed by the user. Exceeding it means
+/// that a mapping (indirectly) maps to itself
+pub const MAX_MAPPED_KEYS: usize = 1000;
+
 pub struct AppState {
     pub run: bool,
+    /// Keys to handle before reading the next event, e.g. the right-hand side of a mapping
+    pub input: VecDeque<Key>,
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        Self {
+            run: true,
+            input: VecDeque::new(),
+        }
+    }
+
+    /// Makes the keys be handled next, before the keys that are already pending
+    pub fn feed_keys(&mut self, keys: &[Key]) {
+        for key in keys.iter().rev() {
+            self.input.push_front(key.clone());
+        }
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
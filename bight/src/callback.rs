@@ -19,10 +19,33 @@ impl<S> StateCallback<S> {
 pub type EditorStateCallback = StateCallback<EditorState>;
 pub type AppStateCallback = StateCallback<AppState>;
 
+type FullStateFn = dyn Fn(&mut AppState, &mut EditorState);
+
+/// A callback that needs both the application and the editor state (e.g. `:wq`)
+#[derive(Clone)]
+pub struct FullStateCallback(pub Rc<FullStateFn>);
+
+impl FullStateCallback {
+    pub fn new(f: impl Fn(&mut AppState, &mut EditorState) + 'static) -> Self {
+        Self(Rc::new(f))
+    }
+}
+
 #[derive(Clone)]
 pub enum OnKeyEventCallback {
     EditorStateChanage(EditorStateCallback),
     AppStateChange(AppStateCallback),
+    FullStateChange(FullStateCallback),
+}
+
+impl OnKeyEventCallback {
+    pub fn call(&self, app: &mut AppState, editor: &mut EditorState) {
+        match self {
+            Self::EditorStateChanage(cb) => (cb.0)(editor),
+            Self::AppStateChange(cb) => (cb.0)(app),
+            Self::FullStateChange(cb) => (cb.0)(app, editor),
+        }
+    }
 }
 
 impl From<EditorStateCallback> for OnKeyEventCallback {
@@ -37,4 +60,10 @@ impl From<AppStateCallback> for OnKeyEventCallback {
     }
 }
 
+impl From<FullStateCallback> for OnKeyEventCallback {
+    fn from(value: FullStateCallback) -> Self {
+        Self::FullStateChange(value)
+    }
+}
+
 pub type EventHandlers = Vec<OnKeyEventCallback>;
@@ -26,9 +26,11 @@ impl ClipboardProvider for ArboardProvider {
     }
 }
 
+#[derive(Default)]
 pub struct Clipboard {
     copied_val: Option<(Arc<str>, u64)>,
-    inner: Box<dyn ClipboardProvider + Send + Sync>,
+    /// The system clipboard is only connected to on first use
+    inner: Option<Box<dyn ClipboardProvider + Send + Sync>>,
 }
 
 impl Debug for Clipboard {
@@ -37,40 +39,38 @@ impl Debug for Clipboard {
     }
 }
 
-impl Default for Clipboard {
-    fn default() -> Self {
-        Self {
-            copied_val: None,
-            inner: Box::new(ArboardProvider {
-                inner: Mutex::new(
-                    arboard::Clipboard::new().expect("Failed to initialize clipboard"),
-                ),
-            }),
-        }
-    }
-}
-
 impl Clipboard {
     pub fn with_provider(p: impl ClipboardProvider + Send + Sync + 'static) -> Self {
         Self {
             copied_val: None,
-            inner: Box::new(p),
+            inner: Some(Box::new(p)),
         }
     }
     pub fn new() -> Self {
         Self::default()
     }
+    fn provider(&mut self) -> &mut (dyn ClipboardProvider + Send + Sync) {
+        self.inner
+            .get_or_insert_with(|| {
+                Box::new(ArboardProvider {
+                    inner: Mutex::new(
+                        arboard::Clipboard::new().expect("Failed to initialize clipboard"),
+                    ),
+                })
+            })
+            .as_mut()
+    }
     pub fn set(&mut self, v: Arc<str>) {
         let hash = {
             let mut hasher: hash::DefaultHasher = DefaultHasher::new();
             v.hash(&mut hasher);
             hasher.finish()
         };
-        self.inner.set_str(&v);
+        self.provider().set_str(&v);
         self.copied_val = Some((v, hash));
     }
     pub fn get(&mut self) -> Option<Arc<str>> {
-        let cb_text = self.inner.get_str()?;
+        let cb_text = self.provider().get_str()?;
         let cb_hash = {
             let mut hasher: hash::DefaultHasher = DefaultHasher::new();
             cb_text.hash(&mut hasher);
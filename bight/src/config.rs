@@ -0,0 +1,200 @@
+use std::{
+    cell::RefCell,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use mlua::{Either, Function, Lua, Value};
+
+use crate::{
+    callback::{AppStateCallback, OnKeyEventCallback as Callback},
+    editor::{EditorState, bindings::EditorBindings, command::UserCommand},
+    key::{Key, sequence::parse_key_sequence_with_leader},
+    plugin::LuaCallback,
+};
+
+/// The right-hand side of a mapping or a command: keys or a command line, or a Lua function
+type Rhs = Either<String, Function>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+    #[error(transparent)]
+    LuaError(#[from] mlua::Error),
+}
+
+/// Returns the path of the config file: `$XDG_CONFIG_HOME/bight/init.lua`, falling back to
+/// `~/.config/bight/init.lua`
+pub fn config_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("bight").join("init.lua"))
+}
+
+/// Runs the config file at `path`. A missing file is not an error
+pub fn load(
+    path: &Path,
+    bindings: &mut EditorBindings,
+    editor: &mut EditorState,
+) -> Result<(), ConfigError> {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    run(&source, &path.display().to_string(), bindings, editor)
+}
+
+/// Runs a config. It can change the bindings and the options through the global `bight` table:
+///
+/// - `bight.map(modes, lhs, rhs)` makes typing `lhs` in the modes act as typing `rhs`, or call
+///   `rhs` if it is a function (see [LuaCallback])
+/// - `bight.unmap(modes, lhs)` removes the bindings of `lhs`
+/// - `bight.leader(key)` sets the key `<leader>` stands for in the following mappings
+/// - `bight.set(name[, value])` sets an option like `:set` does
+/// - `bight.command(name, rhs)` defines the command `:{name}`. It runs the command line `rhs` with
+///   `<args>` replaced by the command's argument, or calls `rhs` with the argument if it is a
+///   function
+pub fn run(
+    source: &str,
+    name: &str,
+    bindings: &mut EditorBindings,
+    editor: &mut EditorState,
+) -> Result<(), ConfigError> {
+    let lua = Lua::new();
+    let bindings = RefCell::new(bindings);
+    let editor = RefCell::new(editor);
+
+    lua.scope(|scope| {
+        let api = lua.create_table()?;
+        api.set(
+            "map",
+            scope.create_function(|lua, (modes, lhs, rhs): (String, String, Rhs)| {
+                let mut bindings = bindings.borrow_mut();
+                let cb: Callback = match rhs {
+                    Either::Left(rhs) => {
+                        let keys = parse_key_sequence_with_leader(&rhs, &bindings.leader())
+                            .map_err(mlua::Error::external)?;
+                        AppStateCallback::new(move |app| app.feed_keys(&keys)).into()
+                    }
+                    Either::Right(f) => LuaCallback::new(lua.clone(), f).into_callback().into(),
+                };
+                bindings
+                    .add_callback_bindings_str(&modes, &lhs, cb)
+                    .map_err(mlua::Error::external)
+            })?,
+        )?;
+        api.set(
+            "unmap",
+            scope.create_function(|_, (modes, lhs): (String, String)| {
+                bindings
+                    .borrow_mut()
+                    .remove_bindings_str(&modes, &lhs)
+                    .map_err(mlua::Error::external)
+            })?,
+        )?;
+        api.set(
+            "leader",
+            scope.create_function(|_, key: String| {
+                let key = key.parse::<Key>().map_err(mlua::Error::external)?;
+                bindings.borrow_mut().set_leader(key);
+                Ok(())
+            })?,
+        )?;
+        api.set(
+            "set",
+            scope.create_function(|_, (name, value): (String, Value)| {
+                let arg = match value {
+                    Value::Nil | Value::Boolean(true) => name,
+                    Value::Boolean(false) => format!("no{name}"),
+                    value => format!("{name}={}", value.to_string()?),
+                };
+                editor
+                    .borrow_mut()
+                    .set_option(&arg)
+                    .map_err(mlua::Error::external)
+            })?,
+        )?;
+        api.set(
+            "command",
+            scope.create_function(|lua, (name, rhs): (String, Rhs)| {
+                if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+                    return Err(mlua::Error::runtime(format!(
+                        "User command names must start with an uppercase letter: {name}"
+                    )));
+                }
+                let cmd = match rhs {
+                    Either::Left(line) => UserCommand::Line(line),
+                    Either::Right(f) => UserCommand::Lua(LuaCallback::new(lua.clone(), f)),
+                };
+                editor.borrow_mut().user_commands.insert(name, cmd);
+                Ok(())
+            })?,
+        )?;
+        lua.globals().set("bight", api)?;
+
+        lua.load(source).set_name(name).exec()
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{app::AppState, editor::mode::Mode, key::sequence::parse_key_sequence};
+
+    use super::*;
+
+    #[test]
+    fn config() {
+        let mut bindings = EditorBindings::default();
+        let mut editor = EditorState::default();
+        let config = r#"
+            bight.leader("<Space>")
+            bight.map("n", "<leader>w", ":w<CR>")
+            bight.set("undofile")
+            bight.set("colwidth", 12)
+            bight.command("Save", "w <args>")
+        "#;
+        run(config, "test", &mut bindings, &mut editor).unwrap();
+
+        let mut sequence = parse_key_sequence("<Space>w").unwrap();
+        let cb = bindings
+            .handle_sequence(&mut sequence, Mode::Normal)
+            .unwrap();
+        let mut app = AppState::new();
+        cb.call(&mut app, &mut editor);
+        assert_eq!(app.input, parse_key_sequence(":w<CR>").unwrap());
+
+        assert!(editor.options.undofile);
+        assert_eq!(editor.layout.width(0), 12);
+        assert!(matches!(
+            &editor.user_commands["Save"],
+            UserCommand::Line(line) if line == "w <args>"
+        ));
+
+        run(
+            r#"bight.unmap("n", "<Space>w")"#,
+            "test",
+            &mut bindings,
+            &mut editor,
+        )
+        .unwrap();
+        let mut sequence = parse_key_sequence("<Space>w").unwrap();
+        assert!(
+            bindings
+                .handle_sequence(&mut sequence, Mode::Normal)
+                .is_none()
+        );
+
+        let err = run(r#"bight.set("nope")"#, "test", &mut bindings, &mut editor);
+        assert!(
+            err.unwrap_err()
+                .to_string()
+                .contains("Unknown option: nope")
+        );
+    }
+}
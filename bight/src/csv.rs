@@ -6,8 +6,8 @@ pub fn write_slice_to_csv(
     slice: TableSlice<'_, impl Table<Item: Display>>,
     writer: &mut csv::Writer<impl Write>,
 ) -> Result<(), csv::Error> {
-    for x in slice.col_indexes() {
-        writer.write_record(slice.row_indexes().map(|y| {
+    for y in slice.row_indexes() {
+        writer.write_record(slice.col_indexes().map(|x| {
             slice
                 .get((x, y))
                 .expect("The position is inside the slice")
@@ -18,9 +18,12 @@ pub fn write_slice_to_csv(
     Ok(())
 }
 
-pub fn slice_to_csv_string(slice: TableSlice<'_, impl Table<Item: Display>>) -> String {
+fn slice_to_string(
+    slice: TableSlice<'_, impl Table<Item: Display>>,
+    builder: &csv::WriterBuilder,
+) -> String {
     let mut s = Vec::<u8>::new();
-    let mut v = csv::WriterBuilder::new().from_writer(&mut s);
+    let mut v = builder.from_writer(&mut s);
 
     write_slice_to_csv(slice, &mut v).expect("The writer configuration was valid");
     v.flush().expect("The writer configuration is valid");
@@ -29,9 +32,33 @@ pub fn slice_to_csv_string(slice: TableSlice<'_, impl Table<Item: Display>>) ->
     String::from_utf8(s).expect("No non-utf8 data was written")
 }
 
+pub fn slice_to_csv_string(slice: TableSlice<'_, impl Table<Item: Display>>) -> String {
+    slice_to_string(slice, &csv::WriterBuilder::new())
+}
+
+/// Writes the slice as tab separated values, the format spreadsheets use for the clipboard
+pub fn slice_to_tsv_string(slice: TableSlice<'_, impl Table<Item: Display>>) -> String {
+    slice_to_string(slice, csv::WriterBuilder::new().delimiter(b'\t'))
+}
+
+/// Parses tab separated values into rows of fields. Rows may have different lengths
+pub fn parse_tsv(s: &str) -> Result<Vec<Vec<String>>, csv::Error> {
+    csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(s.as_bytes())
+        .records()
+        .map(|record| Ok(record?.iter().map(String::from).collect()))
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
-    use crate::table::{DataTable, TableMut};
+    use crate::{
+        evaluator::{EvaluatorTable, NumberMode},
+        table::{DataTable, TableMut},
+    };
 
     use super::*;
 
@@ -56,4 +83,86 @@ mod test {
 
         assert_eq!(csv, "\"Привет, \",\n,мир!\n");
     }
+
+    #[test]
+    fn tsv_round_trip() {
+        let mut table = DataTable::new();
+        table.set((1, 0).into(), Some("tab\there"));
+        table.set((0, 1).into(), Some("=\"quoted\""));
+        table.set((2, 1).into(), Some("plain"));
+
+        let tsv = slice_to_tsv_string(table.full_slice());
+
+        assert_eq!(tsv, "\t\"tab\there\"\t\n\"=\"\"quoted\"\"\"\t\tplain\n");
+        assert_eq!(
+            parse_tsv(&tsv).unwrap(),
+            vec![vec!["", "tab\there", ""], vec!["=\"quoted\"", "", "plain"]]
+        );
+    }
+
+    #[test]
+    fn offset_slice() {
+        let mut table = DataTable::new();
+        table.set((0, 0).into(), Some("outside"));
+        table.set((2, 3).into(), Some("inside"));
+
+        let csv = slice_to_csv_string(TableSlice::new(((1, 2), (3, 4)), &table));
+
+        assert_eq!(csv, ",\n,inside\n");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn typed_values() {
+        let mut table = EvaluatorTable::default();
+        let sources = [
+            "42",
+            "=1/4",
+            "=1 > 2",
+            "2024-01-31",
+            "=DURATION(0, 90)",
+            "=NA()",
+        ];
+        for (x, source) in sources.into_iter().enumerate() {
+            table.set_source((x, 0), Some(source));
+        }
+        table.evaluate();
+
+        let csv = slice_to_csv_string(TableSlice::new(((0, 0), table.extent()), &table));
+
+        assert_eq!(csv, "42,0.25,false,2024-01-31,1:30:00,#N/A\n");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn decimal_round_trip() {
+        let mut table = EvaluatorTable::default();
+        table.set_number_mode(NumberMode::Decimal);
+        let sources = [
+            "0.1",
+            "=A0 + 0.2",
+            "1234567890123456789.123456789",
+            "=SUM(A0_C0)",
+        ];
+        for (x, source) in sources.into_iter().enumerate() {
+            table.set_source((x, 0), Some(source));
+        }
+        table.evaluate();
+
+        let csv = slice_to_csv_string(TableSlice::new(((0, 0), table.extent()), &table));
+        assert_eq!(
+            csv,
+            "0.1,0.3,1234567890123456789.123456789,1234567890123456789.523456789\n"
+        );
+
+        let mut imported = EvaluatorTable::default();
+        imported.set_number_mode(NumberMode::Decimal);
+        for (x, value) in csv.trim_end().split(',').enumerate() {
+            imported.set_source((x, 0), Some(value));
+        }
+        imported.evaluate();
+        for x in 0..sources.len() {
+            assert_eq!(imported.get((x, 0).into()), table.get((x, 0).into()));
+        }
+    }
 }
@@ -1,13 +1,31 @@
 pub mod bindings;
+pub mod command;
+pub mod fill;
+pub mod history;
+pub mod line;
 pub mod mode;
+pub mod options;
+
+use std::{borrow::Cow, io::ErrorKind, path::PathBuf, sync::Arc};
+
+use hashbrown::HashMap;
 
 use crate::{
     clipboard::{Clipboard, ClipboardProvider},
-    evaluator::EvaluatorTable,
+    csv,
+    evaluator::{EvaluatorTable, NumberMode, lua::references},
+    file::{self, BightFile, FileLoadError},
     key::Key,
-    table::cell::CellPos,
+    table::{
+        Axis, LineChange, Table, cell::CellPos, layout::Layout, slice::SlicePos,
+        slice::table::TableSlice,
+    },
 };
+use command::UserCommand;
+use history::{Change, History};
+use line::LineEditor;
 use mode::Mode;
+use options::{OptionError, Options};
 
 #[derive(Debug, Default)]
 pub struct EditorState {
@@ -15,7 +33,38 @@ pub struct EditorState {
     pub mode: Mode,
     pub table: EvaluatorTable,
     pub cursor: CellPos,
+    /// The corner of the selection opposite to the cursor in the visual modes
+    pub anchor: CellPos,
     pub clipboard: Clipboard,
+    /// The text last yanked and the position it was yanked from, used to shift the references in
+    /// the formulas when it is pasted
+    pub yanked: Option<(Arc<str>, CellPos)>,
+    /// The cells last cut, which are moved with the formulas reading them when the text yanked
+    /// from them is pasted. Forgotten once a cell in their area changes
+    pub cut: Option<SlicePos>,
+    /// The file the table was loaded from and will be saved to
+    pub path: Option<PathBuf>,
+    /// Whether the table was changed since it was last loaded or saved
+    pub dirty: bool,
+    /// A message (usually an error) displayed in the status line
+    pub message: Option<String>,
+    pub command_line: LineEditor,
+    /// The formula bar used to edit the source of the cell under the cursor in Cell mode
+    pub cell_line: LineEditor,
+    pub history: History,
+    pub options: Options,
+    /// The top-left visible cell
+    pub scroll: CellPos,
+    /// Column widths and row heights
+    pub layout: Layout,
+    /// The number of characters the cells can take horizontally, updated by the view
+    pub view_width: usize,
+    /// The number of lines the cells can take vertically, updated by the view
+    pub view_height: usize,
+    /// Commands defined by the user
+    pub user_commands: HashMap<String, UserCommand>,
+    /// The count typed before the keys of the binding being run, like `3` in `3dd`
+    pub count: Option<usize>,
 }
 
 impl EditorState {
@@ -25,6 +74,477 @@ impl EditorState {
             ..Default::default()
         }
     }
+
+    /// Sets the source of a cell, marking the table as changed and recording the change in the
+    /// history
+    pub fn set_source<S>(&mut self, pos: impl Into<CellPos>, src: Option<S>)
+    where
+        Arc<str>: From<S>,
+    {
+        let pos = pos.into();
+        let after: Option<Arc<str>> = src.map(Arc::from);
+        let before = self.table.get_source(pos).cloned();
+        if before == after {
+            return;
+        }
+        self.history.record(Change {
+            pos,
+            before,
+            after: after.clone(),
+        });
+        self.table.set_source::<Arc<str>>(pos, after);
+        self.forget_changed_cut(pos);
+        self.dirty = true;
+    }
+
+    /// Forgets the cut cells if the cell in their area changed since they were cut, so that
+    /// pasting them doesn't put them back over the new source
+    fn forget_changed_cut(&mut self, pos: CellPos) {
+        if self.cut.is_some_and(|area| area.is_inside(pos)) {
+            self.cut = None;
+        }
+    }
+
+    /// Runs `f` as a single undoable step
+    pub fn transaction<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.history.begin();
+        let res = f(self);
+        self.history.commit();
+        res
+    }
+
+    fn apply_history(&mut self, changes: Vec<(CellPos, Option<Arc<str>>)>) {
+        if let Some(&(pos, _)) = changes.first() {
+            self.cursor = pos;
+        }
+        for (pos, source) in changes {
+            self.table.set_source::<Arc<str>>(pos, source);
+            self.forget_changed_cut(pos);
+        }
+        self.dirty = true;
+    }
+
+    pub fn undo(&mut self) {
+        match self.history.undo().map(Iterator::collect) {
+            Some(changes) => self.apply_history(changes),
+            None => self.message = Some(String::from("Already at oldest change")),
+        }
+    }
+
+    pub fn redo(&mut self) {
+        match self.history.redo().map(Iterator::collect) {
+            Some(changes) => self.apply_history(changes),
+            None => self.message = Some(String::from("Already at newest change")),
+        }
+    }
+
+    /// Applies a `:set` argument, see [Options::set]
+    pub fn set_option(&mut self, arg: &str) -> Result<(), OptionError> {
+        self.options.set(arg)?;
+        self.layout.set_default_width(self.options.colwidth);
+        let mode = self.number_mode();
+        if mode != self.table.number_mode() {
+            self.table.set_number_mode(mode);
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// The number mode selected by the `decimal` option
+    fn number_mode(&self) -> NumberMode {
+        if self.options.decimal {
+            NumberMode::Decimal
+        } else {
+            NumberMode::Float
+        }
+    }
+
+    /// Returns the visible part of the table
+    pub fn viewport(&self) -> SlicePos {
+        let cols = self.layout.fit_columns(self.scroll.x, self.view_width);
+        let rows = self.layout.fit_rows(self.scroll.y, self.view_height);
+        SlicePos::new(self.scroll, (self.scroll.x + cols, self.scroll.y + rows))
+    }
+
+    /// Scrolls the least amount needed for the cursor to be visible
+    pub fn scroll_to_cursor(&mut self) {
+        let view = self.viewport();
+        if self.cursor.x < view.start.x {
+            self.scroll.x = self.cursor.x;
+        } else if self.cursor.x >= view.end.x {
+            self.scroll.x = self
+                .layout
+                .first_column_fitting(self.cursor.x, self.view_width);
+        }
+        if self.cursor.y < view.start.y {
+            self.scroll.y = self.cursor.y;
+        } else if self.cursor.y >= view.end.y {
+            self.scroll.y = self
+                .layout
+                .first_row_fitting(self.cursor.y, self.view_height);
+        }
+    }
+
+    /// Moves both the cursor and the viewport half a screen down
+    pub fn scroll_half_page_down(&mut self) {
+        let half = (self.viewport().height() / 2).max(1);
+        self.cursor.y = self.cursor.y.saturating_add(half);
+        self.scroll.y = self.scroll.y.saturating_add(half);
+    }
+
+    /// Moves both the cursor and the viewport half a screen up
+    pub fn scroll_half_page_up(&mut self) {
+        let half = (self.viewport().height() / 2).max(1);
+        self.cursor.y = self.cursor.y.saturating_sub(half);
+        self.scroll.y = self.scroll.y.saturating_sub(half);
+    }
+
+    /// Scrolls so that the cursor's row is at the top of the screen
+    pub fn scroll_cursor_to_top(&mut self) {
+        self.scroll.y = self.cursor.y;
+    }
+
+    /// Scrolls so that the cursor's row is in the middle of the screen
+    pub fn scroll_cursor_to_center(&mut self) {
+        let row = self.layout.height(self.cursor.y) + 1;
+        let above = self.view_height.saturating_sub(row) / 2;
+        self.scroll.y = self.layout.first_row_fitting(self.cursor.y, above + row);
+    }
+
+    /// Scrolls so that the cursor's row is at the bottom of the screen
+    pub fn scroll_cursor_to_bottom(&mut self) {
+        self.scroll.y = self
+            .layout
+            .first_row_fitting(self.cursor.y, self.view_height);
+    }
+
+    /// Inserts or deletes columns or rows as a single undoable step, see
+    /// [EvaluatorTable::line_changes]. The sizes of the lines move with them
+    pub fn change_lines(&mut self, change: LineChange) {
+        let changes = self.table.line_changes(change);
+        self.transaction(|state| {
+            for (pos, source) in changes {
+                state.set_source(pos, source);
+            }
+        });
+        self.layout.change_lines(change);
+        self.dirty = true;
+    }
+
+    /// Changes the width of the selected columns by `delta` characters
+    pub fn resize_columns(&mut self, delta: isize) {
+        let sel = self.selection();
+        for x in sel.start.x..sel.end.x {
+            let width = self.layout.width(x).saturating_add_signed(delta);
+            self.layout.set_width(x, width);
+        }
+        self.dirty = true;
+    }
+
+    /// Changes the height of the selected rows by `delta` lines
+    pub fn resize_rows(&mut self, delta: isize) {
+        let sel = self.selection();
+        for y in sel.start.y..sel.end.y {
+            let height = self.layout.height(y).saturating_add_signed(delta);
+            self.layout.set_height(y, height);
+        }
+        self.dirty = true;
+    }
+
+    /// Makes each selected column just wide enough for its widest value. Empty columns get the
+    /// default width
+    pub fn fit_columns(&mut self) {
+        let sel = self.selection();
+        for x in sel.start.x..sel.end.x {
+            let width = self
+                .table
+                .source_table()
+                .keys()
+                .filter(|pos| pos.x == x)
+                .filter_map(|&pos| self.table.get(pos))
+                .flat_map(|value| {
+                    value
+                        .to_string()
+                        .lines()
+                        .map(|l| l.chars().count())
+                        .collect::<Vec<_>>()
+                })
+                .max();
+            match width {
+                Some(width) => self.layout.set_width(x, width),
+                None => self.layout.reset_width(x),
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Enters a visual mode with the selection anchored at the cursor
+    pub fn start_visual(&mut self, mode: Mode) {
+        self.anchor = self.cursor;
+        self.mode = mode;
+    }
+
+    /// Returns the selected range. Visual-line mode selects whole rows and Visual-column mode
+    /// selects whole columns, bounded by the table's extent. Outside of the visual modes only the
+    /// cell under the cursor is selected
+    pub fn selection(&self) -> SlicePos {
+        let start: CellPos = (
+            self.anchor.x.min(self.cursor.x),
+            self.anchor.y.min(self.cursor.y),
+        )
+            .into();
+        let end: CellPos = (
+            self.anchor.x.max(self.cursor.x) + 1,
+            self.anchor.y.max(self.cursor.y) + 1,
+        )
+            .into();
+        let extent = self.table.extent();
+        match self.mode {
+            Mode::Visual => SlicePos::new(start, end),
+            Mode::VisualLine => SlicePos::new((0, start.y), (end.x.max(extent.x), end.y)),
+            Mode::VisualColumn => SlicePos::new((start.x, 0), (end.x, end.y.max(extent.y))),
+            _ => SlicePos::new(self.cursor, (self.cursor.x + 1, self.cursor.y + 1)),
+        }
+    }
+
+    /// Puts the sources of the selected cells on the clipboard as TSV
+    pub fn yank_selection(&mut self) {
+        let selection = self.selection();
+        let slice = TableSlice::new(selection, self.table.source_table());
+        let text: Arc<str> = csv::slice_to_tsv_string(slice).into();
+        self.clipboard.set(text.clone());
+        self.yanked = Some((text, selection.start));
+        self.cut = None;
+    }
+
+    /// Yanks and clears the selected cells. Pasting them moves them, see
+    /// [EvaluatorTable::move_cells]
+    pub fn cut_selection(&mut self) {
+        // The selection of the line modes shrinks with the extent once the cells are cleared
+        let selection = self.selection();
+        self.yank_selection();
+        self.delete_selection();
+        self.cut = Some(selection);
+    }
+
+    /// Clears the selected cells
+    pub fn delete_selection(&mut self) {
+        self.transaction(|state| {
+            for pos in state.selection().positions() {
+                state.set_source(pos, None::<Arc<str>>);
+            }
+        });
+    }
+
+    /// Pastes TSV from the clipboard at the start of the selection. If the selection's size is a
+    /// multiple of the pasted block's size, the block is repeated to fill the whole selection.
+    /// The references in the formulas yanked from the table are shifted by the distance between
+    /// the cells they were yanked from and the ones they are pasted to
+    pub fn paste_selection(&mut self) {
+        let Some(text) = self.clipboard.get() else {
+            return;
+        };
+        let origin = self
+            .yanked
+            .as_ref()
+            .filter(|(yanked, _)| *yanked == text)
+            .map(|&(_, origin)| origin);
+        let rows = match csv::parse_tsv(&text) {
+            Ok(rows) => rows,
+            Err(e) => {
+                self.message = Some(e.to_string());
+                return;
+            }
+        };
+        if let Some(area) = self.cut.take().filter(|_| origin.is_some()) {
+            self.paste_cut(area, &rows);
+            return;
+        }
+        let block_width = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let block_height = rows.len();
+        if block_width == 0 {
+            return;
+        }
+
+        let selection = self.selection();
+        let (width, height) = if selection.width().is_multiple_of(block_width)
+            && selection.height().is_multiple_of(block_height)
+        {
+            (selection.width(), selection.height())
+        } else {
+            (block_width, block_height)
+        };
+
+        self.transaction(|state| {
+            for y in 0..height {
+                for x in 0..width {
+                    let pos: CellPos = (selection.start.x + x, selection.start.y + y).into();
+                    let (block_x, block_y) = (x % block_width, y % block_height);
+                    let source = rows[block_y].get(block_x).filter(|s| !s.is_empty());
+                    let source = match (source, origin) {
+                        (Some(source), Some(origin)) if source.starts_with('=') => {
+                            let dx = pos.x as isize - (origin.x + block_x) as isize;
+                            let dy = pos.y as isize - (origin.y + block_y) as isize;
+                            Some(references::shift(source, dx, dy))
+                        }
+                        (source, _) => source.map(|s| Cow::Borrowed(s.as_str())),
+                    };
+                    state.set_source(pos, source.as_deref());
+                }
+            }
+        });
+    }
+
+    /// Fills each selected column below its first cells, see [fill](Self::fill)
+    pub fn fill_down(&mut self) {
+        self.fill(Axis::Row);
+    }
+
+    /// Fills each selected row to the right of its first cells, see [fill](Self::fill)
+    pub fn fill_right(&mut self) {
+        self.fill(Axis::Column);
+    }
+
+    /// Fills each selected line along `axis` starting with its first cells: the cells up to the
+    /// first empty one (or only the first cell if none are empty). The rest of the line continues
+    /// the series they start (see [fill::extend]), or repeats them otherwise, with the references
+    /// in the formulas shifted like pasting does
+    fn fill(&mut self, axis: Axis) {
+        let selection = self.selection();
+        let (lines, cells) = match axis {
+            Axis::Row => (selection.start.x..selection.end.x, selection.height()),
+            Axis::Column => (selection.start.y..selection.end.y, selection.width()),
+        };
+        let pos = |line: usize, i: usize| -> CellPos {
+            match axis {
+                Axis::Row => (line, selection.start.y + i).into(),
+                Axis::Column => (selection.start.x + i, line).into(),
+            }
+        };
+        self.transaction(|state| {
+            for line in lines {
+                let mut seed: Vec<Arc<str>> = (0..cells)
+                    .map_while(|i| state.table.get_source(pos(line, i)).cloned())
+                    .collect();
+                if seed.len() == cells {
+                    seed.truncate(1);
+                }
+                let count = cells - seed.len();
+                if seed.is_empty() || count == 0 {
+                    continue;
+                }
+                if let Some(series) = fill::extend(&seed, count) {
+                    for (i, source) in series.into_iter().enumerate() {
+                        state.set_source(pos(line, seed.len() + i), Some(source));
+                    }
+                    continue;
+                }
+                for i in seed.len()..cells {
+                    let (from, to) = (pos(line, i % seed.len()), pos(line, i));
+                    let source = &seed[i % seed.len()];
+                    let source = if source.starts_with('=') {
+                        let dx = to.x as isize - from.x as isize;
+                        let dy = to.y as isize - from.y as isize;
+                        references::shift(source, dx, dy).into()
+                    } else {
+                        source.clone()
+                    };
+                    state.set_source(to, Some(source));
+                }
+            }
+        });
+    }
+
+    /// Moves the cells cut from `area`, whose sources are `rows`, to the start of the selection
+    fn paste_cut(&mut self, area: SlicePos, rows: &[Vec<String>]) {
+        let to = self.selection().start;
+        self.transaction(|state| {
+            // The cells are cleared when they are cut, so they are put back to be moved
+            for (y, row) in rows.iter().enumerate() {
+                for (x, source) in row.iter().enumerate() {
+                    let source = Some(source.as_str()).filter(|s| !s.is_empty());
+                    state.set_source((area.start.x + x, area.start.y + y), source);
+                }
+            }
+            for (pos, source) in state.table.move_changes(area, to) {
+                state.set_source(pos, source);
+            }
+        });
+    }
+
+    /// Enters Cell mode with the formula bar containing the source of the cell under the cursor
+    pub fn start_cell_edit(&mut self) {
+        let source = self
+            .table
+            .get_source(self.cursor)
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+        self.cell_line.set_text(source);
+        self.mode = Mode::Cell;
+    }
+
+    /// Sets the source of the cell under the cursor to the formula bar's text and leaves Cell
+    /// mode. An empty text clears the cell
+    pub fn commit_cell_edit(&mut self) {
+        let source = self.cell_line.commit();
+        self.mode = Mode::Normal;
+        let source = (!source.is_empty()).then_some(source);
+        self.set_source(self.cursor, source);
+    }
+
+    /// Replaces the table with the one stored at `path`. A nonexistent file is opened as an empty
+    /// table and will be created on save
+    pub fn open_file(&mut self, path: impl Into<PathBuf>) -> Result<(), FileLoadError> {
+        let path = path.into();
+        let data = match file::load(&path) {
+            Ok(data) => data,
+            Err(FileLoadError::IoErrror(e)) if e.kind() == ErrorKind::NotFound => BightFile {
+                number_mode: self.number_mode(),
+                ..Default::default()
+            },
+            Err(e) => return Err(e),
+        };
+        self.table = EvaluatorTable::new(data.source);
+        self.table.set_number_mode(data.number_mode);
+        self.options.decimal = data.number_mode == NumberMode::Decimal;
+        self.history = data.history.map(History::with_tree).unwrap_or_default();
+        self.layout = data.layout;
+        self.layout.set_default_width(self.options.colwidth);
+        self.path = Some(path);
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Saves the table to `path`, or to the current file if `path` is None. Saving to a new path
+    /// makes it the current file. The undo history is saved too if the `undofile` option is set
+    pub fn save_file(&mut self, path: Option<PathBuf>) -> Result<(), command::CommandError> {
+        let path = path
+            .or_else(|| self.path.clone())
+            .ok_or(command::CommandError::NoFileName)?;
+        let data = BightFile {
+            source: self.table.source_table().clone(),
+            history: self.options.undofile.then(|| self.history.tree().clone()),
+            layout: self.layout.clone(),
+            number_mode: self.table.number_mode(),
+        };
+        file::save(&path, &data)?;
+        self.message = Some(format!("\"{}\" written", path.display()));
+        self.path = Some(path);
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Handles a key that was not bound to anything in a text mode
+    pub fn handle_text_key(&mut self, key: &Key) {
+        let Some(c) = key.char() else {
+            return;
+        };
+        match self.mode {
+            Mode::Command => self.command_line.insert(c),
+            Mode::Cell => self.cell_line.insert(c),
+            _ => {}
+        }
+    }
 }
 
 pub fn display_sequence(seq: &[Key]) -> String {
@@ -34,3 +554,276 @@ pub fn display_sequence(seq: &[Key]) -> String {
     }
     s
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct TestProvider(Arc<Mutex<Option<String>>>);
+
+    impl ClipboardProvider for TestProvider {
+        fn set_str(&mut self, v: &str) {
+            *self.0.lock().unwrap() = Some(v.to_owned());
+        }
+        fn get_str(&mut self) -> Option<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    fn source(state: &EditorState, pos: (usize, usize)) -> Option<&str> {
+        state.table.get_source(pos).map(|s| s.as_ref())
+    }
+
+    #[test]
+    fn paste_shifts_references() {
+        let provider = TestProvider::default();
+        let mut state = EditorState::with_clipboard(provider.clone());
+        state.set_source((2, 1), Some("=A0 + $B$1 + SUM(A0:A)"));
+        state.set_source((3, 1), Some("text A0"));
+
+        state.cursor = (2, 1).into();
+        state.start_visual(Mode::Visual);
+        state.cursor = (3, 1).into();
+        state.yank_selection();
+        state.mode = Mode::Normal;
+        state.cursor = (3, 3).into();
+        state.paste_selection();
+        assert_eq!(source(&state, (3, 3)), Some("=B2 + $B$1 + SUM(B2:B)"));
+        assert_eq!(source(&state, (4, 3)), Some("text A0"));
+
+        state.cursor = (0, 0).into();
+        state.paste_selection();
+        assert_eq!(source(&state, (0, 0)), Some("=#REF! + $B$1 + SUM(#REF!)"));
+
+        // Text that wasn't yanked from the table is pasted as it is
+        *provider.0.lock().unwrap() = Some(String::from("=A0"));
+        state.cursor = (5, 5).into();
+        state.paste_selection();
+        assert_eq!(source(&state, (5, 5)), Some("=A0"));
+    }
+
+    #[test]
+    fn cut_and_paste_moves_cells() {
+        let mut state = EditorState::with_clipboard(TestProvider::default());
+        state.set_source((0, 0), Some("1"));
+        state.set_source((1, 0), Some("=A0 + 1"));
+        state.set_source((2, 2), Some("=A0 * B0"));
+
+        state.start_visual(Mode::Visual);
+        state.cursor = (1, 0).into();
+        state.cut_selection();
+        assert_eq!(source(&state, (0, 0)), None);
+        state.mode = Mode::Normal;
+        state.cursor = (0, 4).into();
+        state.paste_selection();
+        assert_eq!(source(&state, (0, 4)), Some("1"));
+        assert_eq!(source(&state, (1, 4)), Some("=A4 + 1"));
+        assert_eq!(source(&state, (2, 2)), Some("=A4 * B4"));
+
+        state.undo();
+        assert_eq!(source(&state, (2, 2)), Some("=A0 * B0"));
+        assert_eq!(source(&state, (0, 4)), None);
+        state.undo();
+        assert_eq!(source(&state, (0, 0)), Some("1"));
+    }
+
+    #[test]
+    fn cut_and_paste_whole_row() {
+        let mut state = EditorState::with_clipboard(TestProvider::default());
+        state.set_source((0, 0), Some("a"));
+        state.set_source((5, 0), Some("b"));
+        state.set_source((0, 1), Some("=F0"));
+
+        state.start_visual(Mode::VisualLine);
+        state.cut_selection();
+        state.mode = Mode::Normal;
+        state.cursor = (0, 3).into();
+        state.paste_selection();
+        assert_eq!(source(&state, (0, 3)), Some("a"));
+        assert_eq!(source(&state, (5, 3)), Some("b"));
+        assert_eq!(source(&state, (5, 0)), None);
+        assert_eq!(source(&state, (0, 1)), Some("=F3"));
+    }
+
+    #[test]
+    fn cut_then_edit_then_paste() {
+        let mut state = EditorState::with_clipboard(TestProvider::default());
+        state.set_source((0, 0), Some("moved"));
+        state.set_source((0, 1), Some("=A0"));
+        state.cut_selection();
+        state.set_source((0, 0), Some("typed later"));
+        state.cursor = (2, 0).into();
+        state.paste_selection();
+        // Pasted like yanked text, keeping the new source
+        assert_eq!(source(&state, (2, 0)), Some("moved"));
+        assert_eq!(source(&state, (0, 0)), Some("typed later"));
+        assert_eq!(source(&state, (0, 1)), Some("=A0"));
+    }
+
+    #[test]
+    fn fill_selection() {
+        let mut state = EditorState::default();
+        state.set_source((0, 0), Some("Item 1"));
+        state.set_source((1, 0), Some("=REL(-1, 0) + A$0"));
+        state.set_source((2, 0), Some("2024-01-15"));
+        state.set_source((2, 1), Some("2024-02-15"));
+
+        state.start_visual(Mode::Visual);
+        state.cursor = (2, 3).into();
+        state.fill_down();
+        assert_eq!(source(&state, (0, 3)), Some("Item 4"));
+        assert_eq!(source(&state, (1, 3)), Some("=REL(-1, 0) + A$0"));
+        assert_eq!(source(&state, (2, 3)), Some("2024-04-15"));
+
+        state.set_source((0, 5), Some("=A0"));
+        state.set_source((1, 5), Some("x"));
+        state.anchor = (0, 5).into();
+        state.cursor = (4, 5).into();
+        state.fill_right();
+        assert_eq!(source(&state, (2, 5)), Some("=C0"));
+        assert_eq!(source(&state, (3, 5)), Some("x"));
+
+        // A whole filled line is filled from its first cell, like spreadsheets do
+        state.set_source((2, 5), Some("5"));
+        state.fill_right();
+        assert_eq!(source(&state, (2, 5)), Some("=C0"));
+        assert_eq!(source(&state, (1, 5)), Some("=B0"));
+    }
+
+    #[test]
+    fn yank_and_paste_range() {
+        let provider = TestProvider::default();
+        let mut state = EditorState::with_clipboard(provider.clone());
+        state.set_source((0, 0), Some("a"));
+        state.set_source((1, 0), Some("b"));
+        state.set_source((1, 1), Some("=1\t2"));
+
+        state.start_visual(Mode::Visual);
+        state.cursor = (1, 1).into();
+        state.yank_selection();
+        assert_eq!(
+            provider.0.lock().unwrap().as_deref(),
+            Some("a\tb\n\t\"=1\t2\"\n")
+        );
+
+        state.mode = Mode::Normal;
+        state.cursor = (3, 3).into();
+        state.paste_selection();
+        assert_eq!(source(&state, (3, 3)), Some("a"));
+        assert_eq!(source(&state, (4, 3)), Some("b"));
+        assert_eq!(source(&state, (3, 4)), None);
+        assert_eq!(source(&state, (4, 4)), Some("=1\t2"));
+    }
+
+    #[test]
+    fn paste_fills_selection() {
+        let mut state = EditorState::with_clipboard(TestProvider::default());
+        state.set_source((0, 0), Some("x"));
+        state.yank_selection();
+
+        state.cursor = (1, 1).into();
+        state.start_visual(Mode::Visual);
+        state.cursor = (2, 3).into();
+        state.paste_selection();
+        for pos in SlicePos::new((1, 1), (3, 4)).positions() {
+            assert_eq!(state.table.get_source(pos).map(|s| s.as_ref()), Some("x"));
+        }
+        assert_eq!(source(&state, (3, 3)), None);
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut state = EditorState::with_clipboard(TestProvider::default());
+        state.set_source((0, 0), Some("1"));
+        state.set_source((0, 0), Some("2"));
+        state.transaction(|state| {
+            state.set_source((1, 0), Some("a"));
+            state.set_source((1, 1), Some("b"));
+        });
+
+        state.undo();
+        assert_eq!(source(&state, (1, 0)), None);
+        assert_eq!(source(&state, (1, 1)), None);
+        state.undo();
+        assert_eq!(source(&state, (0, 0)), Some("1"));
+        state.redo();
+        assert_eq!(source(&state, (0, 0)), Some("2"));
+
+        // A new edit starts a new branch, redo follows the most recent one
+        state.set_source((2, 2), Some("c"));
+        state.undo();
+        state.redo();
+        assert_eq!(source(&state, (2, 2)), Some("c"));
+        assert_eq!(source(&state, (1, 0)), None);
+
+        state.undo();
+        state.undo();
+        state.undo();
+        assert_eq!(source(&state, (0, 0)), None);
+        state.undo();
+        assert_eq!(state.message.as_deref(), Some("Already at oldest change"));
+    }
+
+    #[test]
+    fn visual_line_selection() {
+        let mut state = EditorState::with_clipboard(TestProvider::default());
+        state.set_source((4, 0), Some("far"));
+        state.set_source((0, 2), Some("x"));
+        state.set_source((1, 3), Some("y"));
+        state.cursor = (1, 2).into();
+        state.start_visual(Mode::VisualLine);
+        state.cursor = (1, 3).into();
+        assert_eq!(state.selection(), SlicePos::new((0, 2), (5, 4)));
+
+        state.delete_selection();
+        assert_eq!(source(&state, (0, 2)), None);
+        assert_eq!(source(&state, (1, 3)), None);
+        assert_eq!(source(&state, (4, 0)), Some("far"));
+    }
+
+    #[test]
+    fn scroll_follows_cursor() {
+        let mut state = EditorState {
+            view_width: 40,
+            view_height: 20,
+            ..Default::default()
+        };
+        state.cursor = (5, 12).into();
+        state.scroll_to_cursor();
+        assert_eq!(state.viewport(), SlicePos::new((2, 3), (6, 13)));
+
+        state.cursor = (0, 7).into();
+        state.scroll_to_cursor();
+        assert_eq!(state.scroll, (0, 3).into());
+
+        state.scroll_cursor_to_center();
+        assert_eq!(state.scroll, (0, 3).into());
+        state.scroll_cursor_to_top();
+        assert_eq!(state.scroll, (0, 7).into());
+
+        state.scroll_half_page_up();
+        assert_eq!((state.cursor, state.scroll), ((0, 2).into(), (0, 2).into()));
+
+        state.layout.set_width(5, 19);
+        state.cursor = (5, 2).into();
+        state.scroll_to_cursor();
+        assert_eq!(state.viewport(), SlicePos::new((3, 2), (6, 12)));
+    }
+
+    #[test]
+    fn fit_column() {
+        let mut state = EditorState::default();
+        state.set_source((1, 0), Some("a long value"));
+        state.set_source((1, 3), Some("short"));
+        state.table.evaluate();
+        state.cursor = (1, 5).into();
+        state.fit_columns();
+        assert_eq!(state.layout.width(1), 12);
+
+        state.resize_columns(-20);
+        assert_eq!(state.layout.width(1), 1);
+    }
+}
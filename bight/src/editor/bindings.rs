@@ -4,7 +4,10 @@ use crate::{
     callback::OnKeyEventCallback as Callback,
     editor::mode::{Mode, ModeParseError, parse_modes},
     key::Key,
-    key::sequence::{MatchSequence, SequenceBinding, SequenceMatchError, SequenceParseError},
+    key::sequence::{
+        DEFAULT_LEADER, MatchSequence, SequenceBinding, SequenceMatchError, SequenceParseError,
+        parse_key_sequence_with_leader,
+    },
 };
 
 #[derive(Default)]
@@ -16,9 +19,14 @@ impl KeyBindings {
     pub fn push(&mut self, binding: Box<dyn MatchSequence<Output = Callback>>) {
         self.bindings.push(binding);
     }
+    /// Removes all the bindings of exactly the given sequence
+    pub fn remove(&mut self, sequence: &[Key]) {
+        self.bindings.retain(|b| !b.is_bound_to(sequence));
+    }
+    /// Finds the binding matching the sequence. Bindings added later take precedence
     pub fn find(&self, sequence: &[Key]) -> Result<Callback, SequenceMatchError> {
         let mut found_hint = None;
-        for binding in self.bindings.iter() {
+        for binding in self.bindings.iter().rev() {
             let res = binding.try_match(sequence);
             match res {
                 Ok(cb) => return Ok(cb),
@@ -37,6 +45,12 @@ impl KeyBindings {
 pub struct EditorBindings {
     pub normal: KeyBindings,
     pub insert: KeyBindings,
+    pub cell: KeyBindings,
+    pub command: KeyBindings,
+    /// Shared by all the visual modes
+    pub visual: KeyBindings,
+    /// The key `<leader>` stands for in the bindings added after it is set
+    leader: Option<Key>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -49,28 +63,65 @@ pub enum BindingParseError {
 
 impl EditorBindings {
     pub fn handle_sequence(&self, sequence: &mut Vec<Key>, mode: Mode) -> Option<Callback> {
+        self.handle_counted_sequence(sequence, mode)
+            .map(|(cb, _)| cb)
+    }
+
+    /// Like [Self::handle_sequence], but outside of the text modes the sequence may start with a
+    /// count (like `3` in `3dd`), which is returned with the callback
+    pub fn handle_counted_sequence(
+        &self,
+        sequence: &mut Vec<Key>,
+        mode: Mode,
+    ) -> Option<(Callback, Option<usize>)> {
         let bindings = match mode {
             Mode::Normal => &self.normal,
             Mode::Insert => &self.insert,
-            Mode::Cell => todo!(),
+            Mode::Cell => &self.cell,
+            Mode::Command => &self.command,
+            Mode::Visual | Mode::VisualLine | Mode::VisualColumn => &self.visual,
         };
-        let cb = loop {
-            let cb = bindings.find(sequence);
-            if cb.is_ok() || sequence.is_empty() || cb.as_ref().is_err_and(|e| e.can_be_continued())
-            {
-                break cb;
+        let (cb, count) = loop {
+            let count_len = if mode.is_text() {
+                0
+            } else {
+                count_len(sequence)
+            };
+            if sequence.len() == count_len {
+                return None;
+            }
+            let cb = bindings.find(&sequence[count_len..]);
+            if cb.is_ok() || cb.as_ref().is_err_and(|e| e.can_be_continued()) {
+                let count = sequence[..count_len]
+                    .iter()
+                    .filter_map(Key::char)
+                    .collect::<String>()
+                    .parse()
+                    .ok();
+                break (cb, count);
             }
             sequence.remove(0);
         };
         match cb {
             Ok(cb) => {
                 sequence.clear();
-                Some(cb)
+                Some((cb, count))
             }
             Err(_) => None,
         }
     }
 
+    pub fn leader(&self) -> Key {
+        self.leader
+            .clone()
+            .unwrap_or_else(|| Key::from_char(DEFAULT_LEADER))
+    }
+
+    /// Sets the key `<leader>` stands for. Only affects the bindings added afterwards
+    pub fn set_leader(&mut self, leader: Key) {
+        self.leader = Some(leader);
+    }
+
     pub fn add_callback_bindings_str(
         &mut self,
         modes: &str,
@@ -78,7 +129,8 @@ impl EditorBindings {
         cb: impl Into<Callback>,
     ) -> Result<(), BindingParseError> {
         let cb: Callback = cb.into();
-        let binding = SequenceBinding::bind_str(sequence, cb)?;
+        let sequence = parse_key_sequence_with_leader(sequence, &self.leader())?;
+        let binding = SequenceBinding::new(sequence, cb);
 
         for mode in parse_modes(modes)? {
             self.add_sequence_handler(mode, Box::new(binding.clone()));
@@ -87,6 +139,19 @@ impl EditorBindings {
         Ok(())
     }
 
+    /// Removes the bindings of the sequence in the given modes
+    pub fn remove_bindings_str(
+        &mut self,
+        modes: &str,
+        sequence: &str,
+    ) -> Result<(), BindingParseError> {
+        let sequence = parse_key_sequence_with_leader(sequence, &self.leader())?;
+        for mode in parse_modes(modes)? {
+            self.bindings_mut(mode).remove(&sequence);
+        }
+        Ok(())
+    }
+
     pub fn add_callback_binding(&mut self, mode: Mode, sequence: &[Key], cb: impl Into<Callback>) {
         let cb: Callback = cb.into();
         let binding = SequenceBinding::new(sequence.to_vec(), cb);
@@ -97,10 +162,77 @@ impl EditorBindings {
         mode: Mode,
         binding: Box<dyn MatchSequence<Output = Callback>>,
     ) {
+        self.bindings_mut(mode).push(binding);
+    }
+
+    fn bindings_mut(&mut self, mode: Mode) -> &mut KeyBindings {
         match mode {
-            Mode::Normal => self.normal.push(binding),
-            Mode::Insert => self.insert.push(binding),
-            Mode::Cell => todo!(),
+            Mode::Normal => &mut self.normal,
+            Mode::Insert => &mut self.insert,
+            Mode::Cell => &mut self.cell,
+            Mode::Command => &mut self.command,
+            Mode::Visual | Mode::VisualLine | Mode::VisualColumn => &mut self.visual,
+        }
+    }
+}
+
+/// Returns the number of the keys at the start of the sequence that are a count: digits not
+/// starting with 0, which is a key of its own
+fn count_len(sequence: &[Key]) -> usize {
+    let is_digit = |key: &Key, digits: &str| key.char().is_some_and(|c| digits.contains(c));
+    match sequence.first() {
+        Some(first) if is_digit(first, "123456789") => {
+            1 + sequence[1..]
+                .iter()
+                .take_while(|key| is_digit(key, "0123456789"))
+                .count()
         }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{app::AppState, editor::EditorState, key::sequence::parse_key_sequence};
+
+    #[test]
+    fn counted_line_changes() {
+        let mut bindings = EditorBindings::default();
+        vim_default::add_line_bindings(&mut bindings);
+        let mut editor = EditorState::default();
+        for y in 0..5 {
+            editor.set_source((0, y), Some(y.to_string()));
+        }
+        editor.layout.set_height(4, 3);
+        editor.cursor = (0, 1).into();
+
+        let mut sequence = parse_key_sequence("3d").unwrap();
+        assert!(
+            bindings
+                .handle_counted_sequence(&mut sequence, Mode::Normal)
+                .is_none()
+        );
+        sequence.push(Key::from_char('d'));
+        let (cb, count) = bindings
+            .handle_counted_sequence(&mut sequence, Mode::Normal)
+            .unwrap();
+        assert_eq!(count, Some(3));
+        editor.count = count;
+        cb.call(&mut AppState::new(), &mut editor);
+        assert_eq!(editor.table.get_source((0, 1)).unwrap().as_ref(), "4");
+        assert_eq!(editor.table.get_source((0, 2)), None);
+        assert_eq!(editor.layout.height(1), 3);
+
+        let mut sequence = parse_key_sequence("10o").unwrap();
+        let (_, count) = bindings
+            .handle_counted_sequence(&mut sequence, Mode::Normal)
+            .unwrap();
+        assert_eq!(count, Some(10));
+        let mut sequence = parse_key_sequence("o").unwrap();
+        let (_, count) = bindings
+            .handle_counted_sequence(&mut sequence, Mode::Normal)
+            .unwrap();
+        assert_eq!(count, None);
     }
 }
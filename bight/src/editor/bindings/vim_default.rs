@@ -1,38 +1,148 @@
-use std::{path::Path, sync::Arc};
-
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-
 use crate::{
-    callback::{AppStateCallback, EditorStateCallback},
-    clipboard::{get_clipboard, set_clipboard},
-    editor::mode::Mode,
-    evaluator::EvaluatorTable,
-    file,
+    callback::{EditorStateCallback, FullStateCallback},
+    editor::{EditorState, command, line::LineEditor, mode::Mode},
+    key::Key,
     key::sequence::parse_key_sequence,
+    table::{Axis, LineChange},
 };
 
 use super::EditorBindings;
 
+/// Makes a callback that runs an ex command, reporting errors in the status line
+pub fn command_callback(line: &'static str) -> FullStateCallback {
+    FullStateCallback::new(move |app, editor| {
+        if let Err(e) = command::run(line, app, editor) {
+            editor.message = Some(e.to_string());
+        }
+    })
+}
+
 pub fn add_io_bindings(bindings: &mut EditorBindings) {
+    bindings
+        .add_callback_bindings_str("n", "s", command_callback("w"))
+        .unwrap();
+    bindings
+        .add_callback_bindings_str("n", "S", command_callback("e"))
+        .unwrap();
+}
+
+fn seq(notation: &str) -> Vec<Key> {
+    parse_key_sequence(notation).expect("The default bindings are valid")
+}
+
+/// Adds readline-like editing bindings for a mode whose input goes to a [LineEditor]
+fn add_line_editing_bindings(
+    bindings: &mut EditorBindings,
+    mode: Mode,
+    line: fn(&mut EditorState) -> &mut LineEditor,
+) {
+    type Edit = fn(&mut LineEditor);
+    let edits: [(&str, Edit); 16] = [
+        ("<Del>", LineEditor::delete),
+        ("<Left>", LineEditor::move_left),
+        ("<Right>", LineEditor::move_right),
+        ("<Home>", LineEditor::move_home),
+        ("<End>", LineEditor::move_end),
+        ("<C-a>", LineEditor::move_home),
+        ("<C-e>", LineEditor::move_end),
+        ("<C-b>", LineEditor::move_left),
+        ("<C-f>", LineEditor::move_right),
+        ("<C-Left>", LineEditor::word_left),
+        ("<C-Right>", LineEditor::word_right),
+        ("<C-w>", LineEditor::delete_word_backward),
+        ("<C-u>", LineEditor::delete_to_start),
+        ("<C-k>", LineEditor::delete_to_end),
+        ("<Up>", LineEditor::history_prev),
+        ("<Down>", LineEditor::history_next),
+    ];
+    for (notation, edit) in edits {
+        bindings.add_callback_binding(
+            mode,
+            &seq(notation),
+            EditorStateCallback::new(move |state| edit(line(state))),
+        );
+    }
+
+    bindings.add_callback_binding(
+        mode,
+        &seq("<BS>"),
+        EditorStateCallback::new(move |state| {
+            if line(state).is_empty() {
+                state.mode = Mode::Normal;
+            } else {
+                line(state).backspace();
+            }
+        }),
+    );
+    bindings.add_callback_binding(
+        mode,
+        &seq("<Esc>"),
+        EditorStateCallback::new(move |state| {
+            line(state).clear();
+            state.mode = Mode::Normal;
+        }),
+    );
+}
+
+pub fn add_command_line_bindings(bindings: &mut EditorBindings) {
+    bindings
+        .add_callback_bindings_str(
+            "n",
+            ":",
+            EditorStateCallback::new(|state| {
+                state.command_line.clear();
+                state.mode = Mode::Command;
+            }),
+        )
+        .unwrap();
+    bindings.add_callback_binding(
+        Mode::Command,
+        &seq("<CR>"),
+        FullStateCallback::new(|app, editor| {
+            let line = editor.command_line.commit();
+            editor.mode = Mode::Normal;
+            if let Err(e) = command::run(&line, app, editor) {
+                editor.message = Some(e.to_string());
+            }
+        }),
+    );
+    add_line_editing_bindings(bindings, Mode::Command, |state| &mut state.command_line);
+}
+
+pub fn add_cell_edit_bindings(bindings: &mut EditorBindings) {
     bindings
         .add_callback_bindings_str(
             "n",
-            "s",
+            "i",
             EditorStateCallback::new(|state| {
-                file::save(Path::new("test.bight"), state.table.source_table().clone()).unwrap();
+                state.start_cell_edit();
+                state.cell_line.move_home();
             }),
         )
         .unwrap();
     bindings
         .add_callback_bindings_str(
             "n",
-            "S",
+            "a",
+            EditorStateCallback::new(EditorState::start_cell_edit),
+        )
+        .unwrap();
+    bindings
+        .add_callback_bindings_str(
+            "n",
+            "cc",
             EditorStateCallback::new(|state| {
-                let table = file::load(Path::new("test.bight")).unwrap();
-                state.table = EvaluatorTable::new(table);
+                state.start_cell_edit();
+                state.cell_line.clear();
             }),
         )
         .unwrap();
+    bindings.add_callback_binding(
+        Mode::Cell,
+        &seq("<CR>"),
+        EditorStateCallback::new(EditorState::commit_cell_edit),
+    );
+    add_line_editing_bindings(bindings, Mode::Cell, |state| &mut state.cell_line);
 }
 
 pub fn add_clipboard_binding(bindings: &mut EditorBindings) {
@@ -40,57 +150,106 @@ pub fn add_clipboard_binding(bindings: &mut EditorBindings) {
         .add_callback_bindings_str(
             "n",
             "yy",
-            EditorStateCallback::new(|state| {
-                let pos = state.cursor;
-                let v = state.table.get_source(pos);
-                let v: Arc<str> = if let Some(v) = v {
-                    v.clone()
-                } else {
-                    Arc::from("")
-                };
-                set_clipboard(v);
-            }),
+            EditorStateCallback::new(EditorState::yank_selection),
         )
         .unwrap();
     bindings
         .add_callback_bindings_str(
             "n",
             "p",
-            EditorStateCallback::new(|state| {
-                let pos = state.cursor;
-                if let Some(v) = get_clipboard() {
-                    state.table.set_source(pos, Some(v));
-                }
-            }),
+            EditorStateCallback::new(EditorState::paste_selection),
         )
         .unwrap();
-}
 
-pub fn add_mode_bindings(bindings: &mut EditorBindings) {
-    let esc_seq = vec![KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE).into()];
+    type Op = fn(&mut EditorState);
+    let range_ops: [(&str, Op); 4] = [
+        ("y", EditorState::yank_selection),
+        ("d", EditorState::cut_selection),
+        ("x", EditorState::cut_selection),
+        ("p", EditorState::paste_selection),
+    ];
+    for (sequence, op) in range_ops {
+        bindings
+            .add_callback_bindings_str(
+                "v",
+                sequence,
+                EditorStateCallback::new(move |state| {
+                    op(state);
+                    state.cursor = state.selection().start;
+                    state.mode = Mode::Normal;
+                }),
+            )
+            .unwrap();
+    }
+}
 
+pub fn add_history_bindings(bindings: &mut EditorBindings) {
+    bindings
+        .add_callback_bindings_str("n", "u", EditorStateCallback::new(EditorState::undo))
+        .unwrap();
     bindings.add_callback_binding(
         Mode::Normal,
-        &parse_key_sequence("q").unwrap(),
-        AppStateCallback::new(|state| state.run = false),
+        &seq("<C-r>"),
+        EditorStateCallback::new(EditorState::redo),
     );
+}
+
+pub fn add_visual_bindings(bindings: &mut EditorBindings) {
+    let modes = [
+        (Mode::Visual, seq("v")),
+        (Mode::VisualLine, seq("V")),
+        (Mode::VisualColumn, seq("<C-v>")),
+    ];
+    for (mode, sequence) in modes {
+        bindings.add_callback_binding(
+            Mode::Normal,
+            &sequence,
+            EditorStateCallback::new(move |state| state.start_visual(mode)),
+        );
+        for visual in [Mode::Visual, Mode::VisualLine, Mode::VisualColumn] {
+            bindings.add_callback_binding(
+                visual,
+                &sequence,
+                EditorStateCallback::new(move |state| {
+                    state.mode = if state.mode == mode {
+                        Mode::Normal
+                    } else {
+                        mode
+                    }
+                }),
+            );
+        }
+    }
     bindings.add_callback_binding(
-        Mode::Insert,
-        &esc_seq,
+        Mode::Visual,
+        &seq("<Esc>"),
         EditorStateCallback::new(|state| state.mode = Mode::Normal),
     );
     bindings
         .add_callback_bindings_str(
-            "n",
+            "v",
+            "o",
+            EditorStateCallback::new(|state| std::mem::swap(&mut state.anchor, &mut state.cursor)),
+        )
+        .unwrap();
+}
+
+pub fn add_mode_bindings(bindings: &mut EditorBindings) {
+    bindings
+        .add_callback_bindings_str("n", "q", command_callback("q"))
+        .unwrap();
+    bindings
+        .add_callback_bindings_str(
             "i",
-            EditorStateCallback::new(|state| state.mode = Mode::Insert),
+            "<Esc>",
+            EditorStateCallback::new(|state| state.mode = Mode::Normal),
         )
         .unwrap();
 }
 pub fn add_move_callbacks(bindings: &mut EditorBindings) {
     bindings
         .add_callback_bindings_str(
-            "n",
+            "nv",
             "l",
             EditorStateCallback::new(|state| {
                 state.cursor.x = state.cursor.x.saturating_add(1);
@@ -99,7 +258,7 @@ pub fn add_move_callbacks(bindings: &mut EditorBindings) {
         .unwrap();
     bindings
         .add_callback_bindings_str(
-            "n",
+            "nv",
             "h",
             EditorStateCallback::new(|state| {
                 state.cursor.x = state.cursor.x.saturating_sub(1);
@@ -108,7 +267,7 @@ pub fn add_move_callbacks(bindings: &mut EditorBindings) {
         .unwrap();
     bindings
         .add_callback_bindings_str(
-            "n",
+            "nv",
             "j",
             EditorStateCallback::new(|state| {
                 state.cursor.y = state.cursor.y.saturating_add(1);
@@ -117,7 +276,7 @@ pub fn add_move_callbacks(bindings: &mut EditorBindings) {
         .unwrap();
     bindings
         .add_callback_bindings_str(
-            "n",
+            "nv",
             "k",
             EditorStateCallback::new(|state| {
                 state.cursor.y = state.cursor.y.saturating_sub(1);
@@ -125,3 +284,123 @@ pub fn add_move_callbacks(bindings: &mut EditorBindings) {
         )
         .unwrap();
 }
+
+pub fn add_scroll_bindings(bindings: &mut EditorBindings) {
+    type Scroll = fn(&mut EditorState);
+    let seqs: [(&str, Scroll); 5] = [
+        ("<C-d>", EditorState::scroll_half_page_down),
+        ("<C-u>", EditorState::scroll_half_page_up),
+        ("zt", EditorState::scroll_cursor_to_top),
+        ("zz", EditorState::scroll_cursor_to_center),
+        ("zb", EditorState::scroll_cursor_to_bottom),
+    ];
+    for (seq, scroll) in seqs {
+        bindings
+            .add_callback_bindings_str("nv", seq, EditorStateCallback::new(scroll))
+            .unwrap();
+    }
+}
+
+/// Adds bindings that change the sizes of the selected columns and rows
+pub fn add_layout_bindings(bindings: &mut EditorBindings) {
+    type Resize = fn(&mut EditorState);
+    let resize: [(&str, Resize); 5] = [
+        (">", |state| state.resize_columns(1)),
+        ("<lt>", |state| state.resize_columns(-1)),
+        ("=", EditorState::fit_columns),
+        ("+", |state| state.resize_rows(1)),
+        ("-", |state| state.resize_rows(-1)),
+    ];
+    for (seq, cb) in resize {
+        bindings
+            .add_callback_bindings_str("nv", seq, EditorStateCallback::new(cb))
+            .unwrap();
+    }
+}
+
+/// Adds bindings that insert rows below (`o`) and above (`O`) the cursor and delete its row
+/// (`dd`), and the same for columns to the right (`go`) and to the left (`gO`) of it (`dc`). A
+/// count (`3dd`) changes that many lines
+pub fn add_line_bindings(bindings: &mut EditorBindings) {
+    type Change = fn(&mut EditorState);
+    let changes: [(&str, Change); 6] = [
+        ("o", |state| {
+            let at = state.cursor.y + 1;
+            state.change_lines(LineChange::Insert {
+                axis: Axis::Row,
+                at,
+                count: state.count.unwrap_or(1),
+            });
+            state.cursor.y = at;
+        }),
+        ("O", |state| {
+            let at = state.cursor.y;
+            state.change_lines(LineChange::Insert {
+                axis: Axis::Row,
+                at,
+                count: state.count.unwrap_or(1),
+            });
+        }),
+        ("dd", |state| {
+            let at = state.cursor.y;
+            state.change_lines(LineChange::Delete {
+                axis: Axis::Row,
+                at,
+                count: state.count.unwrap_or(1),
+            });
+        }),
+        ("go", |state| {
+            let at = state.cursor.x + 1;
+            state.change_lines(LineChange::Insert {
+                axis: Axis::Column,
+                at,
+                count: state.count.unwrap_or(1),
+            });
+            state.cursor.x = at;
+        }),
+        ("gO", |state| {
+            let at = state.cursor.x;
+            state.change_lines(LineChange::Insert {
+                axis: Axis::Column,
+                at,
+                count: state.count.unwrap_or(1),
+            });
+        }),
+        ("dc", |state| {
+            let at = state.cursor.x;
+            state.change_lines(LineChange::Delete {
+                axis: Axis::Column,
+                at,
+                count: state.count.unwrap_or(1),
+            });
+        }),
+    ];
+    for (seq, cb) in changes {
+        bindings
+            .add_callback_bindings_str("n", seq, EditorStateCallback::new(cb))
+            .unwrap();
+    }
+}
+
+/// Adds bindings that fill the selection down (`gj`) and to the right (`gl`) from its first
+/// cells, see [EditorState::fill_down]
+pub fn add_fill_bindings(bindings: &mut EditorBindings) {
+    type Fill = fn(&mut EditorState);
+    let fills: [(&str, Fill); 2] = [
+        ("gj", EditorState::fill_down),
+        ("gl", EditorState::fill_right),
+    ];
+    for (sequence, fill) in fills {
+        bindings
+            .add_callback_bindings_str(
+                "v",
+                sequence,
+                EditorStateCallback::new(move |state| {
+                    fill(state);
+                    state.cursor = state.selection().start;
+                    state.mode = Mode::Normal;
+                }),
+            )
+            .unwrap();
+    }
+}
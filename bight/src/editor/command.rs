@@ -0,0 +1,221 @@
+use std::{path::PathBuf, str::FromStr};
+
+use crate::{
+    app::AppState,
+    editor::{EditorState, options::OptionError},
+    file::FileLoadError,
+    plugin::LuaCallback,
+};
+
+/// An ex-style command entered on the `:` command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// `:w [file]`, `:wq [file]`
+    Write { path: Option<PathBuf>, quit: bool },
+    /// `:e[!] [file]`
+    Edit { path: Option<PathBuf>, force: bool },
+    /// `:q[!]`
+    Quit { force: bool },
+    /// `:set {option}...`
+    Set(Vec<String>),
+    /// `:u[ndo]`
+    Undo,
+    /// `:red[o]`
+    Redo,
+    /// A command defined by the user. Their names start with an uppercase letter
+    User { name: String, arg: Option<String> },
+}
+
+/// A command defined by the user
+#[derive(Debug, Clone)]
+pub enum UserCommand {
+    /// Runs a command line with `<args>` replaced by the command's argument
+    Line(String),
+    /// Calls a Lua function with the editor API and the command's argument
+    Lua(LuaCallback),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("Not an editor command: {0}")]
+    UnknownCommand(String),
+    #[error("Trailing characters: {0}")]
+    TrailingCharacters(String),
+    #[error("No file name")]
+    NoFileName,
+    #[error("No write since last change (add ! to override)")]
+    UnsavedChanges,
+    #[error(transparent)]
+    OptionError(#[from] OptionError),
+    #[error(transparent)]
+    LoadError(#[from] FileLoadError),
+    #[error(transparent)]
+    SaveError(#[from] std::io::Error),
+    #[error(transparent)]
+    LuaError(#[from] mlua::Error),
+}
+
+impl FromStr for Command {
+    type Err = CommandError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, arg) = match s.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, Some(arg.trim())),
+            None => (s, None),
+        };
+        let (name, force) = match name.strip_suffix('!') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let path = arg.filter(|a| !a.is_empty()).map(PathBuf::from);
+
+        let cmd = match name {
+            "w" | "write" => Self::Write { path, quit: false },
+            "wq" | "x" | "xit" => Self::Write { path, quit: true },
+            "e" | "edit" => Self::Edit { path, force },
+            "q" | "quit" => {
+                if let Some(path) = path {
+                    return Err(CommandError::TrailingCharacters(
+                        path.to_string_lossy().into_owned(),
+                    ));
+                }
+                Self::Quit { force }
+            }
+            "se" | "set" => Self::Set(
+                arg.unwrap_or_default()
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect(),
+            ),
+            "u" | "undo" => Self::Undo,
+            "red" | "redo" => Self::Redo,
+            _ if name.starts_with(|c: char| c.is_ascii_uppercase()) => Self::User {
+                name: name.to_owned(),
+                arg: arg.filter(|a| !a.is_empty()).map(String::from),
+            },
+            _ => return Err(CommandError::UnknownCommand(name.to_owned())),
+        };
+        Ok(cmd)
+    }
+}
+
+impl Command {
+    pub fn execute(self, app: &mut AppState, editor: &mut EditorState) -> Result<(), CommandError> {
+        match self {
+            Self::Write { path, quit } => {
+                editor.save_file(path)?;
+                if quit {
+                    app.run = false;
+                }
+            }
+            Self::Edit { path, force } => {
+                if editor.dirty && !force {
+                    return Err(CommandError::UnsavedChanges);
+                }
+                let path = path
+                    .or_else(|| editor.path.clone())
+                    .ok_or(CommandError::NoFileName)?;
+                editor.open_file(path)?;
+            }
+            Self::Quit { force } => {
+                if editor.dirty && !force {
+                    return Err(CommandError::UnsavedChanges);
+                }
+                app.run = false;
+            }
+            Self::Set(args) => {
+                for arg in args {
+                    editor.set_option(&arg)?;
+                }
+            }
+            Self::Undo => editor.undo(),
+            Self::Redo => editor.redo(),
+            Self::User { name, arg } => {
+                // The command is taken out while it runs, so that it can't call itself
+                let cmd = editor
+                    .user_commands
+                    .remove(&name)
+                    .ok_or_else(|| CommandError::UnknownCommand(name.clone()))?;
+                let res = match &cmd {
+                    UserCommand::Line(line) => run(
+                        &line.replace("<args>", arg.as_deref().unwrap_or_default()),
+                        app,
+                        editor,
+                    ),
+                    UserCommand::Lua(cb) => Ok(cb.call(app, editor, arg.as_deref())?),
+                };
+                editor.user_commands.insert(name, cmd);
+                res?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses and executes a command line. Blank lines are ignored
+pub fn run(line: &str, app: &mut AppState, editor: &mut EditorState) -> Result<(), CommandError> {
+    if line.trim().is_empty() {
+        return Ok(());
+    }
+    line.parse::<Command>()?.execute(app, editor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "w".parse::<Command>().unwrap(),
+            Command::Write {
+                path: None,
+                quit: false
+            }
+        );
+        assert_eq!(
+            "w other.bight".parse::<Command>().unwrap(),
+            Command::Write {
+                path: Some("other.bight".into()),
+                quit: false
+            }
+        );
+        assert_eq!(
+            "wq".parse::<Command>().unwrap(),
+            Command::Write {
+                path: None,
+                quit: true
+            }
+        );
+        assert_eq!(
+            "e! file.bight".parse::<Command>().unwrap(),
+            Command::Edit {
+                path: Some("file.bight".into()),
+                force: true
+            }
+        );
+        assert_eq!(
+            "q!".parse::<Command>().unwrap(),
+            Command::Quit { force: true }
+        );
+        assert_eq!(
+            "set undofile noundofile".parse::<Command>().unwrap(),
+            Command::Set(vec!["undofile".into(), "noundofile".into()])
+        );
+        assert!(matches!(
+            "frobnicate".parse::<Command>(),
+            Err(CommandError::UnknownCommand(_))
+        ));
+        assert_eq!(
+            "Save now".parse::<Command>().unwrap(),
+            Command::User {
+                name: "Save".into(),
+                arg: Some("now".into())
+            }
+        );
+        assert!(matches!(
+            "q file".parse::<Command>(),
+            Err(CommandError::TrailingCharacters(_))
+        ));
+    }
+}
@@ -0,0 +1,239 @@
+//! Series that filling cells continues: numbers (`1, 2, 3`), dates (by days, or by months if
+//! they fall on the same day of the month), text ending with a number (`Item 1, Item 2`) and
+//! weekday names (`Monday`, `Tue`), all with the same step between the values. A single number
+//! isn't a series, so it is copied like spreadsheets do, but a single date, numbered text or
+//! weekday counts up by one
+
+use std::sync::Arc;
+
+use jiff::{SignedDuration, Span, civil::DateTime};
+use rust_decimal::Decimal;
+
+use crate::evaluator::TableValue;
+
+const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// Returns the sources of the `count` cells continuing the series the sources in `seed` start,
+/// or None if they don't start one (so that they are repeated instead). Formulas never do
+pub fn extend(seed: &[Arc<str>], count: usize) -> Option<Vec<String>> {
+    if seed.is_empty() || seed.iter().any(|source| source.starts_with('=')) {
+        return None;
+    }
+    numbers(seed, count)
+        .or_else(|| dates(seed, count))
+        .or_else(|| numbered_text(seed, count))
+        .or_else(|| weekdays(seed, count))
+}
+
+/// Returns the step of the integers if it is the same between all of them, or 1 if there is a
+/// single one
+fn int_step(values: &[i64]) -> Option<i64> {
+    let [first, second, ..] = *values else {
+        return Some(1);
+    };
+    let step = second.checked_sub(first)?;
+    values
+        .windows(2)
+        .all(|pair| pair[1].checked_sub(pair[0]) == Some(step))
+        .then_some(step)
+}
+
+fn numbers(seed: &[Arc<str>], count: usize) -> Option<Vec<String>> {
+    if seed.len() < 2 {
+        return None;
+    }
+    let values = seed
+        .iter()
+        .map(|source| match TableValue::parse_literal(source) {
+            TableValue::Int(_) | TableValue::Number(_) => Decimal::from_str_exact(source)
+                .or_else(|_| Decimal::from_scientific(source))
+                .ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<Decimal>>>()?;
+    let last = *values.last()?;
+    let step = values[1].checked_sub(values[0])?;
+    let evenly = values
+        .windows(2)
+        .all(|pair| pair[1].checked_sub(pair[0]) == Some(step));
+    if !evenly {
+        return None;
+    }
+    (1..=count)
+        .map(|i| {
+            let value = last.checked_add(step.checked_mul(Decimal::from(i))?)?;
+            Some(value.normalize().to_string())
+        })
+        .collect()
+}
+
+fn dates(seed: &[Arc<str>], count: usize) -> Option<Vec<String>> {
+    let values = seed
+        .iter()
+        .map(|source| match TableValue::parse_literal(source) {
+            TableValue::DateTime(dt) => Some(dt),
+            _ => None,
+        })
+        .collect::<Option<Vec<DateTime>>>()?;
+    let first = values[0];
+    let last = *values.last()?;
+    let format = |dt: DateTime| TableValue::DateTime(dt).to_string();
+
+    let months: Vec<i64> = values
+        .iter()
+        .map(|dt| i64::from(dt.year()) * 12 + i64::from(dt.month()))
+        .collect();
+    let same_day = values
+        .iter()
+        .all(|dt| dt.day() == first.day() && dt.time() == first.time());
+    if values.len() > 1
+        && same_day
+        && let Some(step) = int_step(&months).filter(|&step| step != 0)
+    {
+        return (1..=count as i64)
+            .map(|i| {
+                let span = Span::new().try_months(step.checked_mul(i)?).ok()?;
+                last.checked_add(span).ok().map(format)
+            })
+            .collect();
+    }
+
+    let step = match values.len() {
+        1 => SignedDuration::from_hours(24),
+        len => {
+            let step = last.duration_since(first) / (len as i32 - 1);
+            let evenly = values
+                .windows(2)
+                .all(|pair| pair[1].duration_since(pair[0]) == step);
+            evenly.then_some(step)?
+        }
+    };
+    (1..=count as i32)
+        .map(|i| last.checked_add(step.checked_mul(i)?).ok().map(format))
+        .collect()
+}
+
+fn numbered_text(seed: &[Arc<str>], count: usize) -> Option<Vec<String>> {
+    let parts = seed
+        .iter()
+        .map(|source| {
+            let prefix = source.trim_end_matches(|c: char| c.is_ascii_digit());
+            let digits = &source[prefix.len()..];
+            if prefix.is_empty() || digits.is_empty() {
+                return None;
+            }
+            Some((prefix, digits.len(), digits.parse::<i64>().ok()?))
+        })
+        .collect::<Option<Vec<(&str, usize, i64)>>>()?;
+    let (prefix, len, _) = parts[0];
+    if parts.iter().any(|&(other, _, _)| other != prefix) {
+        return None;
+    }
+    // Numbers with leading zeros keep their length
+    let width = if seed[0][prefix.len()..].starts_with('0') {
+        len
+    } else {
+        0
+    };
+    let numbers: Vec<i64> = parts.iter().map(|&(_, _, n)| n).collect();
+    let step = int_step(&numbers)?;
+    let last = *numbers.last()?;
+    (1..=count as i64)
+        .map(|i| {
+            let n = last.checked_add(step.checked_mul(i)?)?;
+            (n >= 0).then(|| format!("{prefix}{n:0width$}"))
+        })
+        .collect()
+}
+
+fn weekdays(seed: &[Arc<str>], count: usize) -> Option<Vec<String>> {
+    let days = seed
+        .iter()
+        .map(|source| {
+            let lower = source.to_lowercase();
+            WEEKDAYS
+                .iter()
+                .position(|day| lower == *day || (lower.len() == 3 && day.starts_with(&lower)))
+        })
+        .collect::<Option<Vec<usize>>>()?;
+    let step = match days.len() {
+        1 => 1,
+        _ => {
+            let step = (days[1] + 7 - days[0]) % 7;
+            days.windows(2)
+                .all(|pair| (pair[1] + 7 - pair[0]) % 7 == step)
+                .then_some(step)?
+        }
+    };
+
+    // The new names are written like the first one
+    let example = &seed[0];
+    let short = example.len() == 3;
+    let upper = example.chars().all(|c| c.is_ascii_uppercase());
+    let capitalized = example.starts_with(|c: char| c.is_ascii_uppercase());
+    let last = *days.last()?;
+    let names = (1..=count).map(|i| {
+        let day = WEEKDAYS[(last + step * i) % 7];
+        let day = if short { &day[..3] } else { day };
+        if upper {
+            day.to_uppercase()
+        } else if capitalized {
+            day[..1].to_uppercase() + &day[1..]
+        } else {
+            day.to_string()
+        }
+    });
+    Some(names.collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::extend;
+
+    fn extend_str(seed: &[&str], count: usize) -> Option<Vec<String>> {
+        let seed: Vec<_> = seed.iter().map(|&s| s.into()).collect();
+        extend(&seed, count)
+    }
+
+    #[test]
+    fn series() {
+        assert_eq!(extend_str(&["1", "2"], 3).unwrap(), ["3", "4", "5"]);
+        assert_eq!(extend_str(&["0.1", "0.2"], 2).unwrap(), ["0.3", "0.4"]);
+        assert_eq!(extend_str(&["10", "7", "4"], 2).unwrap(), ["1", "-2"]);
+        assert_eq!(extend_str(&["5"], 2), None);
+        assert_eq!(extend_str(&["1", "5", "6"], 1), None);
+        assert_eq!(
+            extend_str(&["2024-02-28"], 2).unwrap(),
+            ["2024-02-29", "2024-03-01"]
+        );
+        assert_eq!(
+            extend_str(&["2024-01-31 12:00:00", "2024-02-01"], 1).unwrap(),
+            ["2024-02-01 12:00:00"]
+        );
+        assert_eq!(
+            extend_str(&["2024-01-15", "2024-03-15"], 2).unwrap(),
+            ["2024-05-15", "2024-07-15"]
+        );
+        assert_eq!(
+            extend_str(&["Item 1", "Item 3"], 2).unwrap(),
+            ["Item 5", "Item 7"]
+        );
+        assert_eq!(extend_str(&["Q09"], 2).unwrap(), ["Q10", "Q11"]);
+        assert_eq!(extend_str(&["A1", "B2"], 1), None);
+        assert_eq!(
+            extend_str(&["Friday"], 3).unwrap(),
+            ["Saturday", "Sunday", "Monday"]
+        );
+        assert_eq!(extend_str(&["MON", "WED"], 2).unwrap(), ["FRI", "SUN"]);
+        assert_eq!(extend_str(&["text"], 1), None);
+        assert_eq!(extend_str(&["=A1"], 1), None);
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::table::cell::CellPos;
+
+/// A change of a single cell's source
+#[derive(Debug, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct Change {
+    pub pos: CellPos,
+    pub before: Option<Arc<str>>,
+    pub after: Option<Arc<str>>,
+}
+
+#[derive(Debug, Clone, Default, Archive, Serialize, Deserialize)]
+struct UndoNode {
+    parent: usize,
+    changes: Vec<Change>,
+    /// Ordered from the oldest to the most recent
+    children: Vec<usize>,
+}
+
+/// A tree of edits. Undoing and then making a new edit starts a new branch instead of discarding
+/// the undone edits. The node 0 is the root and contains no changes
+#[derive(Debug, Clone, Archive, Serialize, Deserialize)]
+pub struct UndoTree {
+    nodes: Vec<UndoNode>,
+    current: usize,
+}
+
+impl Default for UndoTree {
+    fn default() -> Self {
+        Self {
+            nodes: vec![UndoNode::default()],
+            current: 0,
+        }
+    }
+}
+
+impl UndoTree {
+    fn push(&mut self, changes: Vec<Change>) {
+        let idx = self.nodes.len();
+        self.nodes.push(UndoNode {
+            parent: self.current,
+            changes,
+            children: Vec::new(),
+        });
+        self.nodes[self.current].children.push(idx);
+        self.current = idx;
+    }
+
+    /// Replaces the sources of all the changes with the ones `map` returns for them
+    pub fn map_sources(&mut self, mut map: impl FnMut(&Arc<str>) -> Arc<str>) {
+        let changes = self.nodes.iter_mut().flat_map(|node| &mut node.changes);
+        for change in changes {
+            for source in [&mut change.before, &mut change.after]
+                .into_iter()
+                .flatten()
+            {
+                *source = map(source);
+            }
+        }
+    }
+
+    /// Moves to the parent of the current node, returning the changes that have to be reverted
+    fn undo(&mut self) -> Option<&[Change]> {
+        if self.current == 0 {
+            return None;
+        }
+        let node = &self.nodes[self.current];
+        self.current = node.parent;
+        Some(&node.changes)
+    }
+
+    /// Moves to the most recent child of the current node, returning the changes that have to be
+    /// applied
+    fn redo(&mut self) -> Option<&[Change]> {
+        let &child = self.nodes[self.current].children.last()?;
+        self.current = child;
+        Some(&self.nodes[child].changes)
+    }
+}
+
+/// Edit history of an editor. Changes recorded between [History::begin] and [History::commit] are
+/// undone and redone as a single step
+#[derive(Debug, Default)]
+pub struct History {
+    tree: UndoTree,
+    pending: Vec<Change>,
+    depth: usize,
+}
+
+impl History {
+    pub fn with_tree(tree: UndoTree) -> Self {
+        Self {
+            tree,
+            ..Default::default()
+        }
+    }
+
+    pub fn tree(&self) -> &UndoTree {
+        &self.tree
+    }
+
+    /// Opens a transaction. Transactions may be nested, only the outermost one creates a step
+    pub fn begin(&mut self) {
+        self.depth += 1;
+    }
+
+    /// Closes a transaction, creating a step from its changes if it was the outermost one
+    pub fn commit(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 && !self.pending.is_empty() {
+            self.tree.push(std::mem::take(&mut self.pending));
+        }
+    }
+
+    /// Records a change. Outside of a transaction the change is a step on its own
+    pub fn record(&mut self, change: Change) {
+        self.begin();
+        self.pending.push(change);
+        self.commit();
+    }
+
+    /// Returns the changes to revert in order to undo the last step
+    pub fn undo(&mut self) -> Option<impl Iterator<Item = (CellPos, Option<Arc<str>>)>> {
+        let changes = self.tree.undo()?;
+        Some(changes.iter().rev().map(|c| (c.pos, c.before.clone())))
+    }
+
+    /// Returns the changes to apply in order to redo the last undone step
+    pub fn redo(&mut self) -> Option<impl Iterator<Item = (CellPos, Option<Arc<str>>)>> {
+        let changes = self.tree.redo()?;
+        Some(changes.iter().map(|c| (c.pos, c.after.clone())))
+    }
+}
@@ -0,0 +1,264 @@
+/// A single line of editable text with a cursor and a history of committed lines, used by the
+/// command line and the formula bar
+///
+/// The cursor is a char index (not a byte index) in range `0..=len`
+#[derive(Debug, Default, Clone)]
+pub struct LineEditor {
+    text: String,
+    cursor: usize,
+    history: Vec<String>,
+    /// Index of the history entry being shown, None if the user's own text is shown
+    history_idx: Option<usize>,
+    /// The user's own text, stashed while browsing history
+    draft: String,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+        self.history_idx = None;
+    }
+
+    /// Replaces the text, placing the cursor at its end
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        self.clear();
+        self.text = text.into();
+        self.move_end();
+    }
+
+    /// Takes the text out of the editor leaving it empty
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+        self.history_idx = None;
+        std::mem::take(&mut self.text)
+    }
+
+    /// Takes the text out of the editor and records it in the history
+    pub fn commit(&mut self) -> String {
+        let text = self.take();
+        if !text.is_empty() && self.history.last() != Some(&text) {
+            self.history.push(text.clone());
+        }
+        text
+    }
+
+    /// Shows the previous (older) history entry
+    pub fn history_prev(&mut self) {
+        let idx = match self.history_idx {
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.text.clone();
+                self.history.len() - 1
+            }
+            Some(idx) => idx.saturating_sub(1),
+        };
+        self.history_idx = Some(idx);
+        self.text = self.history[idx].clone();
+        self.move_end();
+    }
+
+    /// Shows the next (newer) history entry, or the user's own text after the newest one
+    pub fn history_next(&mut self) {
+        let Some(idx) = self.history_idx else {
+            return;
+        };
+        if idx + 1 < self.history.len() {
+            self.history_idx = Some(idx + 1);
+            self.text = self.history[idx + 1].clone();
+        } else {
+            self.history_idx = None;
+            self.text = std::mem::take(&mut self.draft);
+        }
+        self.move_end();
+    }
+
+    fn byte_idx(&self, char_idx: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(char_idx)
+            .map(|(i, _)| i)
+            .unwrap_or(self.text.len())
+    }
+
+    fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    pub fn insert(&mut self, c: char) {
+        let idx = self.byte_idx(self.cursor);
+        self.text.insert(idx, c);
+        self.cursor += 1;
+    }
+
+    /// Removes the char before the cursor
+    pub fn backspace(&mut self) {
+        if self.cursor == 0 {
+            return;
+        }
+        self.cursor -= 1;
+        let idx = self.byte_idx(self.cursor);
+        self.text.remove(idx);
+    }
+
+    /// Removes the char under the cursor
+    pub fn delete(&mut self) {
+        if self.cursor < self.len() {
+            let idx = self.byte_idx(self.cursor);
+            self.text.remove(idx);
+        }
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor = std::cmp::min(self.cursor + 1, self.len());
+    }
+
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cursor = self.len();
+    }
+
+    /// Finds the start of the word before the cursor
+    fn prev_word_start(&self) -> usize {
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut idx = self.cursor;
+        while idx > 0 && !is_word_char(chars[idx - 1]) {
+            idx -= 1;
+        }
+        while idx > 0 && is_word_char(chars[idx - 1]) {
+            idx -= 1;
+        }
+        idx
+    }
+
+    /// Finds the end of the word after the cursor
+    fn next_word_end(&self) -> usize {
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut idx = self.cursor;
+        while idx < chars.len() && !is_word_char(chars[idx]) {
+            idx += 1;
+        }
+        while idx < chars.len() && is_word_char(chars[idx]) {
+            idx += 1;
+        }
+        idx
+    }
+
+    pub fn word_left(&mut self) {
+        self.cursor = self.prev_word_start();
+    }
+
+    pub fn word_right(&mut self) {
+        self.cursor = self.next_word_end();
+    }
+
+    fn delete_range(&mut self, start: usize, end: usize) {
+        let (start_idx, end_idx) = (self.byte_idx(start), self.byte_idx(end));
+        self.text.replace_range(start_idx..end_idx, "");
+        self.cursor = start;
+    }
+
+    /// Removes the word before the cursor
+    pub fn delete_word_backward(&mut self) {
+        self.delete_range(self.prev_word_start(), self.cursor);
+    }
+
+    /// Removes everything before the cursor
+    pub fn delete_to_start(&mut self) {
+        self.delete_range(0, self.cursor);
+    }
+
+    /// Removes everything after the cursor
+    pub fn delete_to_end(&mut self) {
+        self.delete_range(self.cursor, self.len());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn editor(text: &str, cursor: usize) -> LineEditor {
+        let mut line = LineEditor::new();
+        line.set_text(text);
+        line.cursor = cursor;
+        line
+    }
+
+    #[test]
+    fn insert_and_delete() {
+        let mut line = editor("прит", 3);
+        line.insert('в');
+        line.insert('е');
+        assert_eq!(line.text(), "привет");
+        assert_eq!(line.cursor(), 5);
+        line.backspace();
+        line.delete();
+        assert_eq!(line.text(), "прив");
+        assert_eq!(line.cursor(), 4);
+    }
+
+    #[test]
+    fn word_motions() {
+        let mut line = editor("=SUM(A1_B2) + foo", 17);
+        line.word_left();
+        assert_eq!(line.cursor(), 14);
+        line.word_left();
+        assert_eq!(line.cursor(), 5);
+        line.word_right();
+        assert_eq!(line.cursor(), 10);
+        line.delete_word_backward();
+        assert_eq!(line.text(), "=SUM() + foo");
+        assert_eq!(line.cursor(), 5);
+        line.delete_to_end();
+        assert_eq!(line.text(), "=SUM(");
+        line.delete_to_start();
+        assert_eq!(line.text(), "");
+    }
+
+    #[test]
+    fn history() {
+        let mut line = LineEditor::new();
+        line.set_text("first");
+        line.commit();
+        line.set_text("second");
+        line.commit();
+        line.set_text("draft");
+        line.history_prev();
+        assert_eq!(line.text(), "second");
+        line.history_prev();
+        line.history_prev();
+        assert_eq!(line.text(), "first");
+        line.history_next();
+        assert_eq!(line.text(), "second");
+        line.history_next();
+        assert_eq!(line.text(), "draft");
+    }
+}
@@ -6,11 +6,19 @@ pub enum Mode {
     Normal,
     Insert,
     Cell,
+    Command,
+    Visual,
+    VisualLine,
+    VisualColumn,
 }
 
 impl Mode {
     pub fn is_text(&self) -> bool {
-        matches!(self, Self::Insert)
+        matches!(self, Self::Insert | Self::Cell | Self::Command)
+    }
+
+    pub fn is_visual(&self) -> bool {
+        matches!(self, Self::Visual | Self::VisualLine | Self::VisualColumn)
     }
 }
 
@@ -28,7 +36,11 @@ impl Display for Mode {
             match self {
                 Self::Normal => "NORMAL",
                 Self::Insert => "INSERT",
-                Self::Cell => todo!(),
+                Self::Cell => "CELL",
+                Self::Command => "COMMAND",
+                Self::Visual => "VISUAL",
+                Self::VisualLine => "V-LINE",
+                Self::VisualColumn => "V-COLUMN",
             }
         )
     }
@@ -41,6 +53,8 @@ pub fn parse_modes(s: &str) -> Result<Vec<Mode>, ModeParseError> {
             'n' => Mode::Normal,
             'i' => Mode::Insert,
             'c' => Mode::Cell,
+            ':' => Mode::Command,
+            'v' => Mode::Visual,
             _ => return Err(ModeParseError::InvalidChar(c)),
         });
     }
@@ -0,0 +1,85 @@
+use crate::table::layout::DEFAULT_WIDTH;
+
+/// Editor options that can be changed with `:set`
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Save the undo history in the workbook file
+    pub undofile: bool,
+    /// Width of the columns that weren't resized. Not saved in the workbook file, the widths of
+    /// the resized columns are
+    pub colwidth: usize,
+    /// Evaluate the numbers as decimals, see [NumberMode](crate::evaluator::NumberMode). Saved in
+    /// the workbook file and set when a workbook is opened
+    pub decimal: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            undofile: false,
+            colwidth: DEFAULT_WIDTH,
+            decimal: false,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum OptionError {
+    #[error("Unknown option: {0}")]
+    UnknownOption(String),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+}
+
+impl Options {
+    /// Applies a `:set` argument: `name`, `noname`, `invname` or `name=value`
+    pub fn set(&mut self, arg: &str) -> Result<(), OptionError> {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg, None),
+        };
+        match name {
+            "undofile" | "noundofile" | "invundofile" => {
+                set_bool(&mut self.undofile, name, "undofile", value)
+            }
+            "decimal" | "nodecimal" | "invdecimal" => {
+                set_bool(&mut self.decimal, name, "decimal", value)
+            }
+            "colwidth" => set_number(&mut self.colwidth, name, value, 1),
+            _ => Err(OptionError::UnknownOption(name.to_owned())),
+        }
+    }
+}
+
+fn set_bool(
+    option: &mut bool,
+    name: &str,
+    base: &str,
+    value: Option<&str>,
+) -> Result<(), OptionError> {
+    if let Some(value) = value {
+        return Err(OptionError::InvalidArgument(format!("{name}={value}")));
+    }
+    *option = match name.strip_suffix(base) {
+        Some("") => true,
+        Some("no") => false,
+        Some("inv") => !*option,
+        _ => unreachable!("Only names ending with the option's name are passed"),
+    };
+    Ok(())
+}
+
+fn set_number(
+    option: &mut usize,
+    name: &str,
+    value: Option<&str>,
+    min: usize,
+) -> Result<(), OptionError> {
+    let arg = || format!("{name}={}", value.unwrap_or_default());
+    let value = value
+        .and_then(|v| v.parse().ok())
+        .filter(|&v| v >= min)
+        .ok_or_else(|| OptionError::InvalidArgument(arg()))?;
+    *option = value;
+    Ok(())
+}
@@ -1,14 +1,36 @@
 pub mod interaction;
 pub mod lua;
 
-use std::{collections::HashSet, error::Error, fmt::Display, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    error::Error,
+    fmt::Display,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll},
+};
 
 use futures::future::join_all;
-use tokio::sync::{Mutex, RwLock, RwLockWriteGuard, oneshot};
+use jiff::{
+    SignedDuration,
+    civil::{DateTime, Time},
+};
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+use tokio::{
+    sync::{RwLock, oneshot},
+    task::JoinHandle,
+};
 
 use crate::{
-    evaluator::interaction::CellInfo,
-    table::{HashTable, Table, cell::CellPos},
+    evaluator::{
+        interaction::{CellInfo, SharedEvaluation},
+        lua::LuaPool,
+    },
+    table::{HashTable, LineChange, Table, cell::CellPos, slice::SlicePos},
 };
 
 #[derive(Debug, thiserror::Error, Clone)]
@@ -17,13 +39,128 @@ pub enum TableError {
     LuaError(Arc<mlua::Error>),
     #[error(transparent)]
     OtherError(Arc<dyn Error + Send + Sync>),
+    #[error("evaluation timed out")]
+    TimedOut,
+    #[error("memory limit exceeded")]
+    MemoryLimitExceeded,
+    /// An error that only has a code, like the one of `NA()`
+    #[error("{0}")]
+    Code(ErrorCode),
+    /// An error with a code other than [ErrorCode::Other]
+    #[error("{1}")]
+    Coded(ErrorCode, Arc<dyn Error + Send + Sync>),
+}
+
+impl TableError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Code(code) | Self::Coded(code, _) => *code,
+            _ => ErrorCode::Other,
+        }
+    }
+}
+
+/// The kind of an error, shown before its message like spreadsheets show it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    DivisionByZero,
+    /// A reference outside of a range
+    Reference,
+    /// An argument of a wrong type or out of its domain
+    Value,
+    /// A value that wasn't found
+    NotAvailable,
+    Cycle,
+    /// An array that can't spill because the cells it would cover aren't empty
+    Spill,
+    Other,
 }
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let code = match self {
+            Self::DivisionByZero => "#DIV/0!",
+            Self::Reference => "#REF!",
+            Self::Value => "#VALUE!",
+            Self::NotAvailable => "#N/A",
+            Self::Cycle => "#CYCLE",
+            Self::Spill => "#SPILL!",
+            Self::Other => "#ERR",
+        };
+        write!(f, "{code}")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum TableValue {
     Empty,
     Text(Arc<str>), // Using Arc<str> instead of String as TableValue is never mutated, but cloning happens often
     Number(f64),
+    Int(i64),
+    /// A number in the decimal mode, see [NumberMode]
+    Decimal(Decimal),
+    Bool(bool),
+    /// Date and time without a time zone, like spreadsheets use
+    DateTime(DateTime),
+    Duration(SignedDuration),
     Err(TableError),
+    /// The values returned by a formula as a Lua table. They spill into the cells to the right of
+    /// and below the formula's cell, see [EvaluatorTable::apply]
+    Array(Arc<ArrayValue>),
+}
+
+/// A rectangular array of values, stored row by row
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArrayValue {
+    width: usize,
+    values: Vec<TableValue>,
+}
+
+impl ArrayValue {
+    /// Makes an array from its rows, padding the short ones with empty values. Returns None if
+    /// the array would have no values
+    pub fn from_rows(rows: Vec<Vec<TableValue>>) -> Option<Self> {
+        let width = rows.iter().map(Vec::len).max().filter(|&width| width > 0)?;
+        let values = rows
+            .into_iter()
+            .flat_map(|mut row| {
+                row.resize(width, TableValue::Empty);
+                row
+            })
+            .collect();
+        Some(Self { width, values })
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.values.len() / self.width
+    }
+    /// The value shown in the formula's own cell
+    pub fn first(&self) -> &TableValue {
+        &self.values[0]
+    }
+    /// Iterates over the values row by row
+    pub fn values(&self) -> impl Iterator<Item = &TableValue> {
+        self.values.iter()
+    }
+    pub fn rows(&self) -> impl Iterator<Item = &[TableValue]> {
+        self.values.chunks(self.width)
+    }
+}
+
+/// How a workbook stores numbers that aren't integers
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize,
+)]
+pub enum NumberMode {
+    /// Floating point numbers, which are fast but can't represent most decimal fractions exactly
+    #[default]
+    Float,
+    /// Decimal numbers with up to 28 significant digits, so that `0.1 + 0.2` is `0.3`. Numbers
+    /// in the sources are decimal and so are the numbers calculated by the functions like `SUM`.
+    /// Floating point numbers calculated by Lua are rounded to 15 significant digits
+    Decimal,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -36,7 +173,9 @@ impl From<Result<TableValue, EvalationError>> for TableValue {
     fn from(value: Result<TableValue, EvalationError>) -> Self {
         match value {
             Ok(val) => val,
-            Err(e) => TableValue::other_error(e),
+            Err(e @ EvalationError::DependencyCycle) => {
+                TableValue::coded_error(ErrorCode::Cycle, e)
+            }
         }
     }
 }
@@ -45,12 +184,51 @@ impl TableValue {
     pub fn other_error(error: impl Error + Send + Sync + 'static) -> Self {
         Self::Err(TableError::OtherError(Arc::new(error)))
     }
+    pub fn coded_error(code: ErrorCode, error: impl Error + Send + Sync + 'static) -> Self {
+        Self::Err(TableError::Coded(code, Arc::new(error)))
+    }
     pub fn lua_error(error: mlua::Error) -> Self {
         Self::Err(TableError::LuaError(Arc::new(error)))
     }
     pub fn is_err(&self) -> bool {
         matches!(self, Self::Err(_))
     }
+    /// Returns the value of numbers, other values (including booleans) aren't numbers
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            Self::Int(n) => Some(*n as f64),
+            Self::Decimal(n) => n.to_f64(),
+            _ => None,
+        }
+    }
+    /// Returns the exact value of integers and decimals and the decimal closest to other numbers
+    pub fn as_decimal(&self) -> Option<Decimal> {
+        match self {
+            Self::Decimal(n) => Some(*n),
+            Self::Int(n) => Some(Decimal::from(*n)),
+            Self::Number(n) => Decimal::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+    /// Converts a value calculated in the decimal mode: floating point numbers are rounded to 15
+    /// significant digits (like spreadsheets display them), so that `0.1 + 0.2` is `0.3`. Numbers
+    /// too big or too small for a decimal are left as they are
+    pub fn into_decimal_mode(self) -> Self {
+        match self {
+            Self::Number(n) => Decimal::from_scientific(&format!("{n:.14e}"))
+                .map_or(self, |d| Self::Decimal(d.normalize())),
+            Self::Array(array) => Self::Array(Arc::new(ArrayValue {
+                width: array.width,
+                values: array
+                    .values()
+                    .cloned()
+                    .map(Self::into_decimal_mode)
+                    .collect(),
+            })),
+            value => value,
+        }
+    }
     pub fn format_to_length(&self, length: usize) -> String {
         format!("{:<length$}", self.to_string().lines().next().unwrap_or(""))
             .chars()
@@ -66,6 +244,43 @@ impl TableValue {
     pub fn from_number(n: impl Into<f64>) -> Self {
         Self::Number(n.into())
     }
+    /// Parses the source of a cell that isn't a formula: integers, other numbers, `true` and
+    /// `false` (in any case), dates (`2024-01-31`) and dates with time (`2024-01-31 12:30:00`)
+    /// get their types, anything else is text. A leading `\` makes the rest text
+    pub fn parse_literal(source: &Arc<str>) -> Self {
+        Self::parse_literal_in(source, NumberMode::Float)
+    }
+    /// Parses a literal like [TableValue::parse_literal] does, with the numbers that aren't
+    /// integers parsed as decimals in the decimal mode (if they fit)
+    pub fn parse_literal_in(source: &Arc<str>, mode: NumberMode) -> Self {
+        if let Some(text) = source.strip_prefix('\\') {
+            return Self::Text(text.into());
+        }
+        if let Ok(n) = source.parse() {
+            return Self::Int(n);
+        }
+        if let Ok(n) = source.parse::<f64>()
+            && n.is_finite()
+        {
+            let decimal =
+                Decimal::from_str_exact(source).or_else(|_| Decimal::from_scientific(source));
+            return match decimal {
+                Ok(d) if mode == NumberMode::Decimal => Self::Decimal(d),
+                _ => Self::Number(n),
+            };
+        }
+        if source.eq_ignore_ascii_case("true") || source.eq_ignore_ascii_case("false") {
+            return Self::Bool(source.eq_ignore_ascii_case("true"));
+        }
+        // Parsing a string that doesn't start with a digit fails fast, but jiff also accepts
+        // things like `+002024-01-01` that are better left as text
+        if source.starts_with(|c: char| c.is_ascii_digit())
+            && let Ok(datetime) = source.parse()
+        {
+            return Self::DateTime(datetime);
+        }
+        Self::Text(source.clone())
+    }
 }
 
 impl Display for TableValue {
@@ -73,25 +288,110 @@ impl Display for TableValue {
         match self {
             Self::Text(s) => write!(f, "{s}"),
             Self::Number(value) => write!(f, "{value}"),
-            Self::Err(e) => write!(f, "#ERR: {e}"),
+            Self::Int(value) => write!(f, "{value}"),
+            Self::Decimal(value) => write!(f, "{}", value.normalize()),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::DateTime(dt) if dt.time() == Time::midnight() => write!(f, "{}", dt.date()),
+            Self::DateTime(dt) => write!(f, "{} {}", dt.date(), dt.time()),
+            Self::Duration(d) => {
+                let sign = if d.is_negative() { "-" } else { "" };
+                let secs = d.as_secs().unsigned_abs();
+                write!(
+                    f,
+                    "{sign}{}:{:02}:{:02}",
+                    secs / 3600,
+                    secs / 60 % 60,
+                    secs % 60
+                )?;
+                let nanos = d.subsec_nanos().unsigned_abs();
+                if nanos != 0 {
+                    let fraction = format!("{nanos:09}");
+                    write!(f, ".{}", fraction.trim_end_matches('0'))?;
+                }
+                Ok(())
+            }
+            Self::Err(TableError::Code(code)) => write!(f, "{code}"),
+            Self::Err(e) => write!(f, "{}: {e}", e.code()),
+            Self::Array(array) => write!(f, "{}", array.first()),
             Self::Empty => write!(f, ""),
         }
     }
 }
 
+/// Values of the same type are compared, except that integers and other numbers are compared with
+/// each other. Errors are not equal to anything (including themselves)
+impl PartialOrd for TableValue {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (Self::Empty, Self::Empty) => Some(std::cmp::Ordering::Equal),
+            (Self::Text(a), Self::Text(b)) => a.partial_cmp(b),
+            (Self::Bool(a), Self::Bool(b)) => a.partial_cmp(b),
+            (Self::DateTime(a), Self::DateTime(b)) => a.partial_cmp(b),
+            (Self::Duration(a), Self::Duration(b)) => a.partial_cmp(b),
+            (Self::Int(a), Self::Int(b)) => a.partial_cmp(b),
+            // Decimals are compared exactly with integers and other decimals
+            (Self::Decimal(_), Self::Decimal(_) | Self::Int(_))
+            | (Self::Int(_), Self::Decimal(_)) => {
+                self.as_decimal()?.partial_cmp(&other.as_decimal()?)
+            }
+            (a, b) => a.as_number()?.partial_cmp(&b.as_number()?),
+        }
+    }
+}
+
+impl PartialEq for TableValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(std::cmp::Ordering::Equal)
+    }
+}
+
 pub type SourceTable = HashTable<Arc<str>>;
 pub type CacheTable = HashTable<RwLock<Option<TableValue>>>;
 pub type ValueTable = HashTable<TableValue>;
 pub type DependencyChannelTable = HashTable<Vec<oneshot::Sender<TableValue>>>;
 pub type GraphTable = HashTable<HashSet<CellPos>>;
 
+/// How many times in a row applying an evaluation may invalidate the cells that read spilled
+/// values. Formulas whose spills change the values they read could otherwise be evaluated forever
+const MAX_SPILL_ROUNDS: usize = 16;
+
+/// The cells an array returned by a formula covers, including the formula's cell
+#[derive(Debug, Clone, Copy)]
+struct Spill {
+    area: SlicePos,
+    /// Whether the area wasn't empty, so that the formula's value is a #SPILL! error
+    blocked: bool,
+}
+
 #[derive(Debug, Default)]
 pub struct EvaluatorTable {
     source: SourceTable,
-    result: ValueTable,
+    /// Shared with the running evaluations and copied when changed during one. The values of the
+    /// invalid cells are kept until they're evaluated again, but aren't returned
+    result: Arc<ValueTable>,
     required_by: GraphTable,  // required_by is inversed dependencies
     dependencies: GraphTable, // dependencies is inversed required_by
     invalid_caches: HashSet<CellPos>,
+    /// Invalid cells in the order they were invalidated, which puts cells before the cells that
+    /// depend on them, so evaluating them in this order doesn't make the dependents wait. May
+    /// contain cells that were removed or invalidated twice
+    invalidation_order: Vec<CellPos>,
+    /// Incremented on every change of the source and every applied evaluation, so that outdated
+    /// evaluations can be detected
+    generation: u64,
+    /// The arrays returned by formulas, by the formula's cell
+    spills: HashTable<Spill>,
+    /// The formula's cell of each cell with a spilled value
+    spilled_by: HashTable<CellPos>,
+    /// How many evaluations were applied since the source changed, see [MAX_SPILL_ROUNDS]
+    spill_rounds: usize,
+    number_mode: NumberMode,
+    /// The cells that used ranges reaching the end of the table, which have to be evaluated again
+    /// when it grows
+    open_range_readers: HashSet<CellPos>,
+    /// The smallest extent the open ranges were bound with
+    open_range_extent: CellPos,
+    lua_pool: Arc<LuaPool>,
 }
 
 impl EvaluatorTable {
@@ -99,6 +399,7 @@ impl EvaluatorTable {
         let invalid_caches: HashSet<CellPos> = source.iter().map(|(pos, _)| *pos).collect();
         Self {
             source,
+            invalidation_order: invalid_caches.iter().copied().collect(),
             invalid_caches,
             ..Default::default()
         }
@@ -106,11 +407,42 @@ impl EvaluatorTable {
     pub fn source_table(&self) -> &SourceTable {
         &self.source
     }
+    pub fn number_mode(&self) -> NumberMode {
+        self.number_mode
+    }
+    /// Changes how numbers are stored, which invalidates every cell
+    pub fn set_number_mode(&mut self, mode: NumberMode) {
+        if mode == self.number_mode {
+            return;
+        }
+        self.number_mode = mode;
+        self.generation += 1;
+        self.spill_rounds = 0;
+        let cells: Vec<CellPos> = self.source.keys().copied().collect();
+        for pos in cells {
+            self.invalidate_cell(pos);
+        }
+    }
     pub fn set_source<S>(&mut self, pos: impl Into<CellPos>, src: Option<S>)
     where
         Arc<str>: From<S>,
     {
         let pos = pos.into();
+        self.generation += 1;
+        self.spill_rounds = 0;
+        // A spill is blocked by the new source or may fit without the removed one
+        let anchors: Vec<CellPos> = self
+            .spills
+            .iter()
+            .filter(|(anchor, spill)| **anchor != pos && spill.area.is_inside(pos))
+            .map(|(anchor, _)| *anchor)
+            .collect();
+        for anchor in anchors {
+            self.invalidate_cell(anchor);
+        }
+        if src.is_some() && !self.is_inside_open_ranges(pos) {
+            self.invalidate_open_range_readers();
+        }
         match &src {
             Some(_) => self.invalidate_cell(pos),
             None => self.remove_cell(pos),
@@ -125,15 +457,146 @@ impl EvaluatorTable {
         };
     }
 
+    /// Whether the values of all the cells are up to date, so that they can be accessed
+    pub fn is_evaluated(&self) -> bool {
+        self.invalid_caches.is_empty()
+    }
+
+    /// Whether the value of the cell is being calculated (or is going to be)
+    pub fn is_pending(&self, pos: impl Into<CellPos>) -> bool {
+        self.invalid_caches.contains(&pos.into())
+    }
+
     pub fn get_source(&self, pos: impl Into<CellPos>) -> Option<&Arc<str>> {
         let pos = pos.into();
         self.source.get(&pos)
     }
+
+    /// Returns the sources that change when columns or rows are inserted or deleted: the cells
+    /// after them move and the references in the formulas are adjusted to the moved cells (see
+    /// [lua::references::adjust]). The positions are sorted by row, then by column
+    pub fn line_changes(&self, change: LineChange) -> Vec<(CellPos, Option<Arc<str>>)> {
+        let mut moved = SourceTable::new();
+        for (&pos, source) in &self.source {
+            let Some(pos) = change.move_pos(pos) else {
+                continue;
+            };
+            let source = if source.starts_with('=') {
+                match lua::references::adjust(source, change) {
+                    Cow::Borrowed(_) => source.clone(),
+                    Cow::Owned(adjusted) => adjusted.into(),
+                }
+            } else {
+                source.clone()
+            };
+            moved.insert(pos, source);
+        }
+        let mut changes: Vec<_> = self
+            .source
+            .keys()
+            .filter(|pos| !moved.contains_key(*pos))
+            .map(|&pos| (pos, None))
+            .collect();
+        changes.extend(
+            moved
+                .into_iter()
+                .filter(|(pos, source)| self.source.get(pos) != Some(source))
+                .map(|(pos, source)| (pos, Some(source))),
+        );
+        changes.sort_by_key(|(pos, _)| (pos.y, pos.x));
+        changes
+    }
+
+    /// Returns the sources that change when the cells of `area` are moved to start at `to`, like
+    /// cutting and pasting them does: the moved cells replace the ones at `to`, and the references
+    /// to them in every formula follow them (see [lua::references::relocate]). The positions are
+    /// sorted by row, then by column
+    pub fn move_changes(&self, area: SlicePos, to: CellPos) -> Vec<(CellPos, Option<Arc<str>>)> {
+        let dx = to.x as isize - area.start.x as isize;
+        let dy = to.y as isize - area.start.y as isize;
+        let target = SlicePos::new(to, (to.x + area.width(), to.y + area.height()));
+        let relocate = |source: &Arc<str>| -> Arc<str> {
+            if !source.starts_with('=') {
+                return source.clone();
+            }
+            match lua::references::relocate(source, area, dx, dy) {
+                Cow::Borrowed(_) => source.clone(),
+                Cow::Owned(relocated) => relocated.into(),
+            }
+        };
+
+        let mut changes: HashTable<Option<Arc<str>>> = HashTable::new();
+        // The dependency graph only has the references read by the last evaluation, which
+        // misses the ones in branches that weren't taken, so every formula is relocated
+        for (&pos, source) in &self.source {
+            if !area.is_inside(pos) && !target.is_inside(pos) {
+                changes.insert(pos, Some(relocate(source)));
+            }
+        }
+        for pos in area.positions().chain(target.positions()) {
+            changes.insert(pos, None);
+        }
+        for pos in area.positions() {
+            if let Some(source) = self.source.get(&pos) {
+                let pos = (pos.x + to.x - area.start.x, pos.y + to.y - area.start.y).into();
+                changes.insert(pos, Some(relocate(source)));
+            }
+        }
+
+        let mut changes: Vec<_> = changes
+            .into_iter()
+            .filter(|(pos, source)| self.source.get(pos) != source.as_ref())
+            .collect();
+        changes.sort_by_key(|(pos, _)| (pos.y, pos.x));
+        changes
+    }
+
+    /// Moves the cells of `area` to start at `to`, see [move_changes](Self::move_changes)
+    pub fn move_cells(&mut self, area: SlicePos, to: CellPos) {
+        for (pos, source) in self.move_changes(area, to) {
+            self.set_source(pos, source);
+        }
+    }
+
+    /// Inserts or deletes columns or rows, see [line_changes](Self::line_changes)
+    pub fn change_lines(&mut self, change: LineChange) {
+        for (pos, source) in self.line_changes(change) {
+            self.set_source(pos, source);
+        }
+    }
+
+    /// Returns the position right after the bottom-right corner of the area containing every cell
+    /// with source or a spilled value (so it can be used as an exclusive SlicePos end)
+    pub fn extent(&self) -> CellPos {
+        self.source
+            .keys()
+            .chain(self.spilled_by.keys())
+            .fold(CellPos::default(), |ext, pos| {
+                (ext.x.max(pos.x + 1), ext.y.max(pos.y + 1)).into()
+            })
+    }
+
+    /// Returns the cell of the formula whose array covers the cell, if there is one
+    pub fn spill_anchor(&self, pos: impl Into<CellPos>) -> Option<CellPos> {
+        self.spilled_by.get(&pos.into()).copied()
+    }
+    fn is_inside_open_ranges(&self, pos: CellPos) -> bool {
+        pos.x < self.open_range_extent.x && pos.y < self.open_range_extent.y
+    }
+
+    fn invalidate_open_range_readers(&mut self) {
+        for pos in std::mem::take(&mut self.open_range_readers) {
+            self.invalidate_cell(pos);
+        }
+    }
+
     fn invalidate_cell(&mut self, pos: impl Into<CellPos>) {
         let pos = pos.into();
         if !self.invalid_caches.contains(&pos) {
-            self.result.remove(&pos);
+            self.open_range_readers.remove(&pos);
             self.invalid_caches.insert(pos);
+            self.invalidation_order.push(pos);
+            self.remove_spill(pos);
 
             for dep in self
                 .dependencies
@@ -142,7 +605,10 @@ impl EvaluatorTable {
                 .into_iter()
                 .flatten()
             {
-                self.required_by.remove(&dep);
+                // Other cells may still depend on `dep`
+                if let Some(set) = self.required_by.get_mut(&dep) {
+                    set.remove(&pos);
+                }
             }
 
             if let Some(set) = self.required_by.get(&pos) {
@@ -155,115 +621,344 @@ impl EvaluatorTable {
         log::trace!("Invalidated cell {}", pos);
     }
 
+    /// Removes the values spilled by the formula in the cell and invalidates the cells reading
+    /// them, as well as the formulas whose arrays were blocked by them
+    fn remove_spill(&mut self, anchor: CellPos) {
+        let Some(spill) = self.spills.remove(&anchor) else {
+            return;
+        };
+        if spill.blocked {
+            return;
+        }
+        for pos in spill.area.positions().filter(|pos| *pos != anchor) {
+            self.spilled_by.remove(&pos);
+            if self.result.contains_key(&pos) {
+                Arc::make_mut(&mut self.result).remove(&pos);
+            }
+            if let Some(set) = self.required_by.get(&pos) {
+                for req in set.clone() {
+                    self.invalidate_cell(req);
+                }
+            }
+        }
+        let blocked: Vec<CellPos> = self
+            .spills
+            .iter()
+            .filter(|(_, other)| other.blocked && overlap(other.area, spill.area))
+            .map(|(anchor, _)| *anchor)
+            .collect();
+        for anchor in blocked {
+            self.invalidate_cell(anchor);
+        }
+    }
+
+    /// Spills the array returned by the formula in the cell, unless the cells it would cover have
+    /// source or are covered by another array. Returns the cells whose values changed other than
+    /// the formula's one
+    fn spill(&mut self, anchor: CellPos, array: &ArrayValue) -> Vec<CellPos> {
+        let end = (anchor.x + array.width(), anchor.y + array.height());
+        let area = SlicePos::new(anchor, end);
+        let blocked = area.positions().any(|pos| {
+            pos != anchor && (self.source.contains_key(&pos) || self.spilled_by.contains_key(&pos))
+        });
+        self.spills.insert(anchor, Spill { area, blocked });
+        if blocked {
+            Arc::make_mut(&mut self.result)
+                .insert(anchor, TableValue::Err(TableError::Code(ErrorCode::Spill)));
+            // The cells that read the formula during the evaluation got its first value
+            return vec![anchor];
+        }
+        let result = Arc::make_mut(&mut self.result);
+        for (pos, value) in area.positions().zip(array.values()) {
+            if pos != anchor {
+                self.spilled_by.insert(pos, anchor);
+            }
+            result.insert(pos, value.clone());
+        }
+        area.positions().filter(|pos| *pos != anchor).collect()
+    }
+
     fn remove_cell(&mut self, pos: impl Into<CellPos>) {
         let pos = pos.into();
         self.invalidate_cell(pos);
         self.invalid_caches.remove(&pos);
+        if self.result.contains_key(&pos) {
+            Arc::make_mut(&mut self.result).remove(&pos);
+        }
     }
 
+    /// Evaluates all the invalid cells, blocking until it's done. Evaluates again while spilled
+    /// arrays invalidate the cells reading them
     pub fn evaluate(&mut self) {
-        log::info!("Starting cell evaluation");
-        let dep_tables = Mutex::new((
-            std::mem::take(&mut self.dependencies),
-            std::mem::take(&mut self.required_by),
-        ));
+        while !self.is_evaluated() {
+            let evaluated = self.evaluation().run();
+            self.apply(evaluated);
+        }
+    }
 
-        let intermediate_table: CacheTable = self
-            .invalid_caches
+    /// Makes an evaluation of the invalid cells that doesn't borrow the table. The evaluation
+    /// shares the values with the table and only records the dependencies of the invalid cells
+    pub fn evaluation(&self) -> Evaluation {
+        let mut seen = HashSet::new();
+        Evaluation {
+            generation: self.generation,
+            number_mode: self.number_mode,
+            extent: self.extent(),
+            cells: self
+                .invalidation_order
+                .iter()
+                .filter(|pos| self.invalid_caches.contains(pos) && seen.insert(**pos))
+                .map(|pos| {
+                    let source = self
+                        .source
+                        .get(pos)
+                        .expect("Only cells with source may be marked as invalid cache");
+                    (*pos, source.clone())
+                })
+                .collect(),
+            result: self.result.clone(),
+            cancelled: Arc::default(),
+            lua_pool: self.lua_pool.clone(),
+        }
+    }
+
+    /// Stores the values of a finished evaluation. Returns false (and doesn't change anything) if
+    /// the source was changed or another evaluation was applied after the evaluation had been
+    /// made.
+    ///
+    /// Arrays spill into the cells to the right of and below their formulas, top to bottom. An
+    /// array that would cover a cell with source or a cell another array spilled into is a
+    /// #SPILL! error instead. The cells that read the spilled cells during the evaluation are
+    /// invalidated, so the table may need to be evaluated again
+    pub fn apply(&mut self, evaluated: Evaluated) -> bool {
+        if evaluated.generation != self.generation {
+            return false;
+        }
+        self.generation += 1;
+        // The dependencies of the invalid cells were removed when they were invalidated
+        for (pos, deps) in evaluated.dependencies {
+            self.dependencies.entry(pos).or_default().extend(deps);
+        }
+        for (pos, reqs) in evaluated.required_by {
+            self.required_by.entry(pos).or_default().extend(reqs);
+        }
+        self.open_range_extent = if self.open_range_readers.is_empty() {
+            evaluated.extent
+        } else {
+            let extent = self.open_range_extent;
+            (
+                extent.x.min(evaluated.extent.x),
+                extent.y.min(evaluated.extent.y),
+            )
+                .into()
+        };
+        self.open_range_readers.extend(evaluated.open_range_readers);
+        let mut arrays = Vec::new();
+        let result = Arc::make_mut(&mut self.result);
+        for (pos, value) in evaluated.values {
+            match value {
+                TableValue::Array(array) => arrays.push((pos, array)),
+                value => {
+                    result.insert(pos, value);
+                }
+            }
+        }
+        self.invalid_caches.clear();
+        self.invalidation_order.clear();
+
+        arrays.sort_by_key(|(pos, _)| (pos.y, pos.x));
+        let changed: Vec<CellPos> = arrays
             .iter()
-            .map(|pos| (*pos, RwLock::new(None)))
+            .flat_map(|(pos, array)| self.spill(*pos, array))
             .collect();
-
-        let invalid_cells = self
-            .invalid_caches
+        let mut readers: HashSet<CellPos> = changed
             .iter()
-            .map(|&pos| {
-                CellInfo::new(
-                    self.source
-                        .get(&pos)
-                        .expect("Only cells with source may be marked as invalid cache"),
-                    pos,
-                    &dep_tables,
-                    &intermediate_table,
-                    &self.result,
-                )
-            })
-            .collect::<Vec<_>>();
-
-        log::trace!("Invalid cells: {:#?}", invalid_cells);
-        async fn make_evaluator_future<'a, F, FT>(
-            mut guard: RwLockWriteGuard<'a, Option<TableValue>>,
-            info: &'a CellInfo<'_>,
-            eval_fn: F,
-        ) where
-            FT: Future<Output = TableValue> + 'a,
-            F: Fn(&'a CellInfo<'a>) -> FT + 'a,
-        {
-            *guard = Some(eval_fn(info).await)
+            .filter_map(|pos| self.required_by.get(pos))
+            .flatten()
+            .copied()
+            .filter(|pos| self.source.contains_key(pos))
+            .collect();
+        // Arrays may spill outside of the open ranges
+        if changed.iter().any(|pos| !self.is_inside_open_ranges(*pos)) {
+            readers.extend(self.open_range_readers.iter().copied());
+        }
+        if !readers.is_empty() {
+            if self.spill_rounds < MAX_SPILL_ROUNDS {
+                self.spill_rounds += 1;
+                for pos in readers {
+                    self.invalidate_cell(pos);
+                }
+            } else {
+                log::warn!("Spilled arrays keep changing the cells reading them");
+            }
         }
+        true
+    }
+}
 
-        let futures: Vec<_> = invalid_cells
+/// The invalid cells of a table with everything needed to evaluate them
+#[derive(Debug)]
+pub struct Evaluation {
+    generation: u64,
+    number_mode: NumberMode,
+    extent: CellPos,
+    /// The cells to evaluate with their sources, in the order they are started
+    cells: Vec<(CellPos, Arc<str>)>,
+    result: Arc<ValueTable>,
+    cancelled: Arc<AtomicBool>,
+    lua_pool: Arc<LuaPool>,
+}
+
+/// The values calculated by an [Evaluation], see [EvaluatorTable::apply]
+#[derive(Debug)]
+pub struct Evaluated {
+    generation: u64,
+    extent: CellPos,
+    open_range_readers: HashSet<CellPos>,
+    values: ValueTable,
+    dependencies: GraphTable,
+    required_by: GraphTable,
+}
+
+impl Evaluation {
+    /// Evaluates the cells, blocking until it's done
+    pub fn run(self) -> Evaluated {
+        let lua_pool = self.lua_pool.clone();
+        self.run_with(|info| evaluate(info, &lua_pool))
+    }
+
+    /// Evaluates the cells with `eval_fn`, blocking until it's done
+    fn run_with<F, FT>(self, eval_fn: F) -> Evaluated
+    where
+        F: Fn(CellInfo) -> FT,
+        FT: Future<Output = TableValue>,
+    {
+        log::info!("Starting cell evaluation");
+        log::trace!("Invalid cells: {:#?}", self.cells);
+        let intermediate_table: CacheTable = self
+            .cells
             .iter()
-            .map(|info| {
-                make_evaluator_future(
-                    intermediate_table
-                        .get(&info.pos())
-                        .expect("Only cells with cache = None may be marked as invalid cache")
-                        .try_write()
-                        .expect("Each cache can only be locked for writing once"),
-                    info,
-                    evaluate,
-                )
+            .map(|(pos, _)| (*pos, RwLock::new(None)))
+            .collect();
+        let shared = Arc::new(SharedEvaluation::new(
+            Default::default(),
+            intermediate_table,
+            self.result,
+            self.cancelled,
+            self.number_mode,
+            self.extent,
+        ));
+
+        let futures: Vec<_> = self
+            .cells
+            .into_iter()
+            .map(|(pos, source)| {
+                let mut guard = shared
+                    .cache_table()
+                    .get(&pos)
+                    .expect("Only cells with cache = None may be marked as invalid cache")
+                    .try_write()
+                    .expect("Each cache can only be locked for writing once");
+                let value = eval_fn(CellInfo::new(source, pos, shared.clone()));
+                async move { *guard = Some(value.await) }
             })
             .collect();
-        let rt = tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .unwrap();
-        rt.block_on(join_all(futures));
-
-        let dep_tables = dep_tables.into_inner();
-        self.dependencies = dep_tables.0;
-        self.required_by = dep_tables.1;
-        std::mem::take(&mut self.invalid_caches)
+        futures::executor::block_on(join_all(futures));
+
+        let (dependencies, required_by) = shared.take_dep_tables();
+        let open_range_readers = shared.take_open_range_readers();
+        let values = shared
+            .cache_table()
             .iter()
-            .for_each(|&pos| {
-                let val = intermediate_table
-                    .get(&pos)
-                    .expect("All invalid cells are in intermediate_table")
+            .map(|(pos, cache)| {
+                let val = cache
                     .try_write()
                     .expect("No guard is held after evaluation")
                     .take()
                     .expect("All invalid cells were evaluated");
-                self.result.insert(pos, val);
-            });
+                (*pos, val)
+            })
+            .collect();
         log::info!("Finished cell evaluation");
+        Evaluated {
+            generation: self.generation,
+            extent: self.extent,
+            open_range_readers,
+            values,
+            dependencies,
+            required_by,
+        }
+    }
+}
+
+/// An [Evaluation] running on a blocking thread, so that slow formulas don't block the caller.
+/// Awaiting it returns the values
+#[derive(Debug)]
+pub struct BackgroundEvaluation {
+    generation: u64,
+    cancelled: Arc<AtomicBool>,
+    handle: JoinHandle<Evaluated>,
+}
+
+impl BackgroundEvaluation {
+    /// Starts the evaluation. Must be called from within a tokio runtime
+    pub fn spawn(evaluation: Evaluation) -> Self {
+        Self {
+            generation: evaluation.generation,
+            cancelled: evaluation.cancelled.clone(),
+            handle: tokio::task::spawn_blocking(move || evaluation.run()),
+        }
+    }
+
+    /// Whether the table's source was changed after the evaluation had been started, so that its
+    /// values can't be applied
+    pub fn is_outdated(&self, table: &EvaluatorTable) -> bool {
+        self.generation != table.generation
+    }
+
+    /// Stops the evaluation. Formulas that are being run are interrupted with an error
+    pub fn cancel(self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Future for BackgroundEvaluation {
+    type Output = Evaluated;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.handle).poll(cx).map(|res| match res {
+            Ok(evaluated) => evaluated,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        })
     }
 }
 
 impl Table for EvaluatorTable {
     type Item = TableValue;
+    /// Returns None for the cells that are pending evaluation (see [EvaluatorTable::is_pending])
     fn get(&self, pos: CellPos) -> Option<&Self::Item> {
-        if !self.invalid_caches.is_empty() {
-            panic!("Table values should never be accessed with invalid caches present!");
-            // TODO: cache values on get request using interior mutability
+        if self.invalid_caches.contains(&pos) {
+            return None;
         }
         self.result.get(&pos)
     }
 }
 
-async fn evaluate<'a>(info: &'a CellInfo<'a>) -> TableValue {
+fn overlap(a: SlicePos, b: SlicePos) -> bool {
+    a.start.x < b.end.x && b.start.x < a.end.x && a.start.y < b.end.y && b.start.y < a.end.y
+}
+
+async fn evaluate(info: CellInfo, lua_pool: &LuaPool) -> TableValue {
     let source = info.source();
     if source.starts_with('=') {
         let lua_source = source.split_at(1).1;
-        lua::evaluate(lua_source, info).await
+        let value = lua::evaluate(lua_source, &info, lua_pool).await;
+        match info.number_mode() {
+            NumberMode::Float => value,
+            NumberMode::Decimal => value.into_decimal_mode(),
+        }
     } else {
-        let out = if source.starts_with('\\') {
-            Arc::<str>::from(source.split_at(1).1)
-        } else {
-            source.clone()
-        };
-        TableValue::Text(out)
+        TableValue::parse_literal_in(source, info.number_mode())
     }
 }
 
@@ -287,4 +982,419 @@ mod test {
             "67891"
         );
     }
+
+    #[test]
+    fn literals() {
+        let parse = |s: &str| TableValue::parse_literal(&Arc::from(s));
+        assert_eq!(parse("42"), TableValue::Int(42));
+        assert_eq!(parse("-1.5"), TableValue::Number(-1.5));
+        assert_eq!(parse("TRUE"), TableValue::Bool(true));
+        assert_eq!(parse("2024-02-29").to_string(), "2024-02-29");
+        assert_eq!(parse("2024-02-29 13:05").to_string(), "2024-02-29 13:05:00");
+        assert_eq!(
+            parse("2024-02-29T13:05:01").to_string(),
+            "2024-02-29 13:05:01"
+        );
+        assert_eq!(
+            parse("2023-02-29"),
+            TableValue::from_stringable("2023-02-29")
+        );
+        assert_eq!(parse("\\42"), TableValue::from_stringable("42"));
+        assert_eq!(parse("inf"), TableValue::from_stringable("inf"));
+    }
+
+    #[test]
+    fn typed_values() {
+        let duration = TableValue::Duration(SignedDuration::new(-3723, -500_000_000));
+        assert_eq!(duration.to_string(), "-1:02:03.5");
+        let na = TableValue::Err(TableError::Code(ErrorCode::NotAvailable));
+        assert_eq!(na.to_string(), "#N/A");
+        let cycle = TableValue::from(Err(EvalationError::DependencyCycle));
+        assert_eq!(cycle.to_string(), "#CYCLE: Dependency cycle detected");
+
+        assert_eq!(TableValue::Int(2), TableValue::Number(2.0));
+        assert!(TableValue::Int(1) < TableValue::Number(1.5));
+        assert!(TableValue::Bool(false) < TableValue::Bool(true));
+        assert_eq!(
+            TableValue::Int(1).partial_cmp(&TableValue::from_stringable("1")),
+            None
+        );
+        assert_ne!(na, na.clone());
+    }
+
+    /// Evaluates a cell without Lua, so that the tests using it can run under Miri. `=A0 B1` is
+    /// the sum of the cells, other sources are numbers or text
+    async fn sum_refs(info: CellInfo) -> TableValue {
+        let Some(refs) = info.source().strip_prefix('=') else {
+            return match info.source().parse() {
+                Ok(n) => TableValue::Number(n),
+                Err(_) => TableValue::Text(info.source().clone()),
+            };
+        };
+        let mut sum = 0.0;
+        for pos in refs.split_whitespace() {
+            match info.get(pos.parse().unwrap()).await {
+                Ok(err @ TableValue::Err(_)) => return err,
+                Ok(value) => sum += value.as_number().unwrap_or(0.0),
+                Err(e) => return TableValue::other_error(e),
+            }
+        }
+        TableValue::Number(sum)
+    }
+
+    fn evaluate_sums(table: &mut EvaluatorTable) {
+        let evaluated = table.evaluation().run_with(sum_refs);
+        assert!(table.apply(evaluated));
+        assert!(table.is_evaluated());
+    }
+
+    fn number(table: &EvaluatorTable, pos: &str) -> Option<f64> {
+        match table.get(pos.parse().unwrap()) {
+            Some(TableValue::Number(n)) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn is_err(table: &EvaluatorTable, pos: &str) -> bool {
+        table
+            .get(pos.parse().unwrap())
+            .is_some_and(TableValue::is_err)
+    }
+
+    #[test]
+    fn dependencies() {
+        let mut table = EvaluatorTable::default();
+        table.set_source((0, 2), Some("=A0 A1"));
+        table.set_source((0, 1), Some("=A0 B0"));
+        table.set_source((0, 0), Some("1"));
+        table.set_source((1, 0), Some("text"));
+        evaluate_sums(&mut table);
+        assert_eq!(number(&table, "A1"), Some(1.0));
+        assert_eq!(number(&table, "A2"), Some(2.0));
+
+        // Only the cells depending on the changed one are evaluated again
+        table.set_source((0, 0), Some("5"));
+        assert!(table.is_pending((0, 1)) && table.is_pending((0, 2)));
+        assert!(!table.is_pending((1, 0)));
+        assert_eq!(number(&table, "A1"), None);
+        evaluate_sums(&mut table);
+        assert_eq!(number(&table, "A1"), Some(5.0));
+        assert_eq!(number(&table, "A2"), Some(10.0));
+
+        table.set_source::<&str>((0, 0), None);
+        evaluate_sums(&mut table);
+        assert_eq!(number(&table, "A2"), Some(0.0));
+    }
+
+    #[test]
+    fn dependency_cycles() {
+        let mut table = EvaluatorTable::default();
+        table.set_source((0, 0), Some("=A1"));
+        table.set_source((0, 1), Some("=A0"));
+        table.set_source((0, 2), Some("=A2"));
+        table.set_source((0, 3), Some("=A0 B0"));
+        table.set_source((1, 0), Some("2"));
+        evaluate_sums(&mut table);
+        for pos in ["A0", "A1", "A2", "A3"] {
+            assert!(is_err(&table, pos), "{pos} is in or depends on a cycle");
+        }
+        assert_eq!(number(&table, "B0"), Some(2.0));
+
+        // Breaking the cycle fixes the cells depending on it
+        table.set_source((0, 1), Some("=B0"));
+        evaluate_sums(&mut table);
+        assert_eq!(number(&table, "A0"), Some(2.0));
+        assert_eq!(number(&table, "A1"), Some(2.0));
+        assert_eq!(number(&table, "A3"), Some(4.0));
+        assert!(is_err(&table, "A2"));
+    }
+
+    #[test]
+    fn shared_values() {
+        let mut table = EvaluatorTable::default();
+        table.set_source((0, 0), Some("1"));
+        table.set_source((0, 1), Some("=A0"));
+        evaluate_sums(&mut table);
+
+        // Changing the source doesn't copy the values the evaluation uses
+        table.set_source((0, 2), Some("=A1 A0"));
+        let evaluation = table.evaluation();
+        table.set_source((0, 0), Some("=A2"));
+        assert!(Arc::ptr_eq(&evaluation.result, &table.result));
+        assert!(table.is_pending((0, 1)));
+        let evaluated = evaluation.run_with(sum_refs);
+        assert_eq!(
+            evaluated.values[&CellPos::from((0, 2))],
+            TableValue::Number(2.0)
+        );
+
+        // The cycle goes through a cell that was evaluated before
+        evaluate_sums(&mut table);
+        for pos in ["A0", "A1", "A2"] {
+            assert!(is_err(&table, pos), "{pos} is in a cycle");
+        }
+        table.set_source((0, 0), Some("4"));
+        evaluate_sums(&mut table);
+        assert_eq!(number(&table, "A2"), Some(8.0));
+    }
+
+    #[test]
+    fn outdated_evaluation() {
+        let mut table = EvaluatorTable::default();
+        table.set_source((0, 0), Some("1"));
+        let evaluation = table.evaluation();
+        table.set_source((0, 0), Some("2"));
+        assert!(!table.apply(evaluation.run_with(sum_refs)));
+        assert!(table.is_pending((0, 0)));
+        let evaluation = table.evaluation();
+        evaluate_sums(&mut table);
+        assert_eq!(number(&table, "A0"), Some(2.0));
+        assert!(!table.apply(evaluation.run_with(sum_refs)));
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    async fn cancel_background_evaluation() {
+        let mut table = EvaluatorTable::default();
+        table.set_source((0, 0), Some("=(function() while true do end end)()"));
+        table.set_source((1, 0), Some("=1+1"));
+
+        let ev = BackgroundEvaluation::spawn(table.evaluation());
+        assert!(table.is_pending((1, 0)));
+        assert!(table.get((1, 0).into()).is_none());
+
+        table.set_source((0, 0), Some("=2"));
+        assert!(ev.is_outdated(&table));
+        let evaluated = {
+            ev.cancelled.store(true, Ordering::Relaxed);
+            ev.await
+        };
+        assert!(!table.apply(evaluated));
+        assert!(!table.is_evaluated());
+
+        let ev = BackgroundEvaluation::spawn(table.evaluation());
+        assert!(table.apply(ev.await));
+        assert_eq!(table.get((0, 0).into()), Some(&TableValue::Int(2)));
+        assert_eq!(table.get((1, 0).into()), Some(&TableValue::Int(2)));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn reused_states_are_isolated() {
+        let mut table = EvaluatorTable::default();
+        table.set_source(
+            (0, 0),
+            Some("=(function() x = 1; _G.y = 2; return POSX() end)()"),
+        );
+        table.evaluate();
+        assert_eq!(table.get((0, 0).into()), Some(&TableValue::Int(0)));
+
+        table.set_source((0, 1), Some("=x"));
+        table.set_source((1, 1), Some("=y"));
+        table.set_source((2, 1), Some("=A0 + POSX()"));
+        table.evaluate();
+        // Unknown globals are nil, not the values set by the first cell
+        assert_eq!(table.get((0, 1).into()), Some(&TableValue::Empty));
+        assert_eq!(table.get((1, 1).into()), Some(&TableValue::Empty));
+        assert_eq!(table.get((2, 1).into()), Some(&TableValue::Int(2)));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn shared_tables_are_read_only() {
+        let mut table = EvaluatorTable::default();
+        let changes = [
+            "=(function() string.upper = function() return 1 end end)()",
+            "=(function() getmetatable('').__index.upper = function() return 1 end end)()",
+            "=(function() getmetatable(_G).__index.SUM = nil end)()",
+            "=(function() math.pi = 3 end)()",
+        ];
+        for (y, source) in changes.into_iter().enumerate() {
+            table.set_source((0, y), Some(source));
+        }
+        table.evaluate();
+        for y in 0..changes.len() {
+            let value = table.get((0, y).into());
+            assert!(matches!(value, Some(TableValue::Err(_))), "{value:?}");
+        }
+
+        table.set_source((1, 0), Some("=string.upper('a') .. ('b'):upper()"));
+        table.set_source((1, 1), Some("=SUM(1, 2) + math.floor(math.pi)"));
+        table.evaluate();
+        let value = |pos: (usize, usize)| table.get(pos.into()).map(|v| v.to_string());
+        assert_eq!(value((1, 0)).as_deref(), Some("AB"));
+        assert_eq!(value((1, 1)).as_deref(), Some("6"));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn spills() {
+        let mut table = EvaluatorTable::default();
+        table.set_source((0, 0), Some("={1, 2, 3}"));
+        table.set_source((1, 0), Some("=SUM(A2) * 10 + SUM(D1)"));
+        table.set_source((2, 0), Some("={{1, 2}, {3, 4}}"));
+        table.evaluate();
+        let value = |table: &EvaluatorTable, pos: &str| {
+            table.get(pos.parse().unwrap()).map(|v| v.to_string())
+        };
+        assert_eq!(value(&table, "A2").as_deref(), Some("3"));
+        assert_eq!(value(&table, "D1").as_deref(), Some("4"));
+        assert_eq!(value(&table, "B0").as_deref(), Some("34"));
+        assert_eq!(table.spill_anchor((3, 1)), Some((2, 0).into()));
+        assert_eq!(table.extent(), (4, 3).into());
+
+        // A blocked array is an error and spills again when the cell is cleared
+        table.set_source((3, 1), Some("x"));
+        table.evaluate();
+        assert_eq!(value(&table, "C0").as_deref(), Some("#SPILL!"));
+        assert_eq!(value(&table, "D0"), None);
+        assert_eq!(value(&table, "B0").as_deref(), Some("30"));
+        table.set_source::<&str>((3, 1), None);
+        table.evaluate();
+        assert_eq!(value(&table, "B0").as_deref(), Some("34"));
+
+        // The cells reading a spilled value are evaluated again when the array changes
+        table.set_source((0, 0), Some("={1}"));
+        assert!(table.is_pending((1, 0)));
+        table.evaluate();
+        assert_eq!(value(&table, "A2"), None);
+        assert_eq!(value(&table, "B0").as_deref(), Some("4"));
+
+        // An array changing the values it reads stops being evaluated again
+        table.set_source((5, 0), Some("={F1 or 0, (F1 or 0) + 1}"));
+        table.evaluate();
+        assert!(table.is_evaluated());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn inserted_and_deleted_lines() {
+        use crate::table::{Axis, LineChange};
+
+        let mut table = EvaluatorTable::default();
+        table.set_source((0, 0), Some("1"));
+        table.set_source((0, 1), Some("2"));
+        table.set_source((0, 2), Some("3"));
+        table.set_source((1, 0), Some("=SUM(A0:A2) + $A$2"));
+        table.set_source((1, 1), Some("=A1 * 10"));
+        table.evaluate();
+        let value = |table: &EvaluatorTable, pos: &str| {
+            table.get(pos.parse().unwrap()).map(|v| v.to_string())
+        };
+        let source = |table: &EvaluatorTable, pos: &str| {
+            table
+                .get_source(pos.parse::<CellPos>().unwrap())
+                .map(|s| s.to_string())
+        };
+
+        let insert = LineChange::Insert {
+            axis: Axis::Row,
+            at: 1,
+            count: 2,
+        };
+        table.change_lines(insert);
+        table.evaluate();
+        assert_eq!(source(&table, "B0").as_deref(), Some("=SUM(A0:A4) + $A$4"));
+        assert_eq!(source(&table, "B3").as_deref(), Some("=A3 * 10"));
+        assert_eq!(source(&table, "B1"), None);
+        assert_eq!(value(&table, "B0").as_deref(), Some("9"));
+        table.set_source((0, 1), Some("10"));
+        table.evaluate();
+        assert_eq!(value(&table, "B0").as_deref(), Some("19"));
+
+        let delete = LineChange::Delete {
+            axis: Axis::Row,
+            at: 1,
+            count: 3,
+        };
+        table.change_lines(delete);
+        table.evaluate();
+        assert_eq!(source(&table, "B0").as_deref(), Some("=SUM(A0:A1) + $A$1"));
+        assert_eq!(value(&table, "B0").as_deref(), Some("7"));
+        assert_eq!(table.extent(), (2, 2).into());
+
+        let delete = LineChange::Delete {
+            axis: Axis::Column,
+            at: 0,
+            count: 1,
+        };
+        table.change_lines(delete);
+        table.evaluate();
+        assert_eq!(source(&table, "A0").as_deref(), Some("=SUM(#REF!) + #REF!"));
+        assert_eq!(value(&table, "A0").as_deref(), Some("#REF!"));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn moved_cells() {
+        let mut table = EvaluatorTable::default();
+        table.set_source((0, 0), Some("1"));
+        table.set_source((0, 1), Some("=A0 * 2"));
+        table.set_source((1, 0), Some("=A0 + A1"));
+        table.set_source((2, 0), Some("=SUM(A0:A1) + D2"));
+        table.set_source((3, 2), Some("5"));
+        // A1 is only read when A0 isn't positive, so it isn't in the dependency graph
+        table.set_source((5, 0), Some("=A0 > 0 and A0 or A1"));
+        table.evaluate();
+        // Not evaluated yet, so it isn't in the dependency graph
+        table.set_source((4, 0), Some("=A1 * 10"));
+
+        table.move_cells(SlicePos::new((0, 0), (1, 2)), (3, 1).into());
+        let source = |table: &EvaluatorTable, pos: &str| {
+            table
+                .get_source(pos.parse::<CellPos>().unwrap())
+                .map(|s| s.to_string())
+        };
+        assert_eq!(source(&table, "A0"), None);
+        assert_eq!(source(&table, "D1").as_deref(), Some("1"));
+        assert_eq!(source(&table, "D2").as_deref(), Some("=D1 * 2"));
+        assert_eq!(source(&table, "B0").as_deref(), Some("=D1 + D2"));
+        assert_eq!(source(&table, "C0").as_deref(), Some("=SUM(D1:D2) + #REF!"));
+        assert_eq!(source(&table, "E0").as_deref(), Some("=D2 * 10"));
+        assert_eq!(
+            source(&table, "F0").as_deref(),
+            Some("=D1 > 0 and D1 or D2")
+        );
+        table.evaluate();
+        assert_eq!(
+            table.get((1, 0).into()).map(|v| v.to_string()).as_deref(),
+            Some("3")
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn sandbox() {
+        let mut table = EvaluatorTable::default();
+        table.set_source((0, 0), Some("=(function() while true do end end)()"));
+        table.set_source((0, 1), Some("=#string.rep('x', 2^30)"));
+        // Catching the error doesn't reset the budget
+        table.set_source(
+            (0, 2),
+            Some("=(function() pcall(function() while true do end end) while true do end end)()"),
+        );
+        table.set_source(
+            (1, 0),
+            Some("=io == nil and os.execute == nil and print == nil"),
+        );
+        table.set_source((1, 1), Some("=os.time() > 0"));
+        table.evaluate();
+        let value = |pos: (usize, usize)| table.get(pos.into()).unwrap();
+
+        assert!(matches!(
+            value((0, 0)),
+            TableValue::Err(TableError::TimedOut)
+        ));
+        assert!(matches!(
+            value((0, 1)),
+            TableValue::Err(TableError::MemoryLimitExceeded)
+        ));
+        assert!(matches!(
+            value((0, 2)),
+            TableValue::Err(TableError::TimedOut)
+        ));
+        assert_eq!(value((0, 0)).to_string(), "#ERR: evaluation timed out");
+        assert_eq!(value((1, 0)).to_string(), "true");
+        assert_eq!(value((1, 1)).to_string(), "true");
+    }
 }
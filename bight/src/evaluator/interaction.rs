@@ -1,49 +1,126 @@
 use hashbrown::HashMap;
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    sync::{Arc, atomic::AtomicBool},
+};
 
 use tokio::sync::Mutex;
 
 use crate::{
-    evaluator::{EvalationError, TableValue, ValueTable},
-    table::{HashTable, cell::CellPos},
+    evaluator::{EvalationError, NumberMode, TableValue, ValueTable},
+    table::{
+        HashTable,
+        cell::CellPos,
+        slice::{OpenSlicePos, SlicePos},
+    },
 };
 
 use super::{CacheTable, GraphTable};
+
+/// The state of an evaluation shared by all the evaluated cells
 #[derive(Debug)]
-pub struct CellInfo<'a> {
-    source: &'a Arc<str>,
-    pos: CellPos,
-    dep_tables: &'a Mutex<(GraphTable, GraphTable)>,
-    cache_table: &'a CacheTable,
-    result_table: &'a ValueTable,
+pub struct SharedEvaluation {
+    dep_tables: Mutex<(GraphTable, GraphTable)>,
+    cache_table: CacheTable,
+    /// The values of the table, including outdated values of the evaluated cells
+    result_table: Arc<ValueTable>,
+    cancelled: Arc<AtomicBool>,
+    number_mode: NumberMode,
+    /// The end of the table, where open ranges end
+    extent: CellPos,
+    /// The cells that used open ranges
+    open_range_readers: std::sync::Mutex<HashSet<CellPos>>,
 }
 
-impl<'a> CellInfo<'a> {
+impl SharedEvaluation {
     pub fn new(
-        source: &'a Arc<str>,
-        pos: CellPos,
-        dep_tables: &'a Mutex<(GraphTable, GraphTable)>,
-        cache_table: &'a CacheTable,
-        result_table: &'a ValueTable,
+        dep_tables: (GraphTable, GraphTable),
+        cache_table: CacheTable,
+        result_table: Arc<ValueTable>,
+        cancelled: Arc<AtomicBool>,
+        number_mode: NumberMode,
+        extent: CellPos,
     ) -> Self {
         Self {
-            source,
-            pos,
-            dep_tables,
+            dep_tables: Mutex::new(dep_tables),
             cache_table,
             result_table,
+            cancelled,
+            number_mode,
+            extent,
+            open_range_readers: Default::default(),
+        }
+    }
+    pub fn cache_table(&self) -> &CacheTable {
+        &self.cache_table
+    }
+    /// Takes the cells that used open ranges during the evaluation
+    pub fn take_open_range_readers(&self) -> HashSet<CellPos> {
+        std::mem::take(
+            &mut self
+                .open_range_readers
+                .lock()
+                .expect("the lock is never poisoned"),
+        )
+    }
+    /// Takes the dependencies and the required_by tables filled during the evaluation
+    pub fn take_dep_tables(&self) -> (GraphTable, GraphTable) {
+        std::mem::take(
+            &mut *self
+                .dep_tables
+                .try_lock()
+                .expect("The tables aren't locked after the evaluation"),
+        )
+    }
+}
+
+/// A cell being evaluated. Owns a handle to the evaluation, so it can be moved into the functions
+/// the cell calls
+#[derive(Debug, Clone)]
+pub struct CellInfo {
+    source: Arc<str>,
+    pos: CellPos,
+    evaluation: Arc<SharedEvaluation>,
+}
+
+impl CellInfo {
+    pub fn new(source: Arc<str>, pos: CellPos, evaluation: Arc<SharedEvaluation>) -> Self {
+        Self {
+            source,
+            pos,
+            evaluation,
         }
     }
     pub fn pos(&self) -> CellPos {
         self.pos
     }
     pub fn source(&self) -> &Arc<str> {
-        self.source
+        &self.source
+    }
+    /// Returns the flag that is set when the evaluation is cancelled, for the code that can't
+    /// hold the info
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.evaluation.cancelled.clone()
+    }
+    pub fn number_mode(&self) -> NumberMode {
+        self.evaluation.number_mode
+    }
+    /// Returns the cells of a range, with its open ends at the end of the table. The cell will be
+    /// evaluated again if the table grows
+    pub fn bound(&self, range: OpenSlicePos) -> SlicePos {
+        if range.is_open() {
+            self.evaluation
+                .open_range_readers
+                .lock()
+                .expect("the lock is never poisoned")
+                .insert(self.pos);
+        }
+        range.bound(self.evaluation.extent)
     }
     pub async fn get(&self, req: CellPos) -> Result<TableValue, EvalationError> {
         log::debug!("ValueRequest for {} by {}", req, self.pos);
 
-        let mut dep_tables = self.dep_tables.lock().await;
+        let mut dep_tables = self.evaluation.dep_tables.lock().await;
         dep_tables.0.entry(self.pos).or_default().insert(req);
         dep_tables.1.entry(req).or_default().insert(self.pos);
 
@@ -59,19 +136,22 @@ impl<'a> CellInfo<'a> {
 
         drop(dep_tables);
 
-        if let Some(value) = self.result_table.get(&req) {
-            return Ok(value.clone());
-        }
-
-        let Some(cache) = self.cache_table.get(&req) else {
-            return Ok(TableValue::Empty);
+        let Some(cache) = self.evaluation.cache_table.get(&req) else {
+            let value = self.evaluation.result_table.get(&req);
+            return Ok(value.cloned().unwrap_or(TableValue::Empty));
         };
 
-        Ok(cache
+        let value = cache
             .read()
             .await
             .clone()
-            .expect("WriteGuard on the cache can only be dropped after the cache is evaluated"))
+            .expect("WriteGuard on the cache can only be dropped after the cache is evaluated");
+        // The array isn't spilled until the evaluation is applied, the cell only has its first
+        // value
+        Ok(match value {
+            TableValue::Array(array) => array.first().clone(),
+            value => value,
+        })
     }
 }
 
@@ -1,148 +1,410 @@
-use std::{marker::PhantomData, pin::Pin, sync::Arc};
+mod comparisons;
+mod decimal;
+mod functions;
+mod range;
+pub mod references;
+#[cfg(test)]
+mod test_support;
+mod time;
 
-use mlua::{FromLua, FromLuaMulti, IntoLua, IntoLuaMulti, Lua};
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    sync::{Arc, Mutex, atomic::Ordering},
+};
+
+use mlua::{
+    FromLua, HookTriggers, IntoLua, Lua, LuaOptions, MetaMethod, StdLib, UserData, UserDataFields,
+    UserDataMethods, Value, Variadic, VmState,
+};
 
 use crate::{
-    evaluator::{TableError, TableValue, interaction::CellInfo},
-    table::{cell::CellPos, slice::SlicePos},
+    evaluator::{
+        ArrayValue, ErrorCode, NumberMode, TableError, TableValue,
+        interaction::CellInfo,
+        lua::{
+            decimal::LuaDecimal,
+            range::Range,
+            time::{LuaDateTime, LuaDuration},
+        },
+    },
+    table::{
+        cell::CellPos,
+        slice::{OpenSlicePos, SlicePos},
+    },
 };
 
-type TableLuaBoxFuture<'a, V> = Pin<Box<dyn Future<Output = mlua::Result<V>> + Send + Sync + 'a>>;
-fn global_cell_access<'a>(
-    info: &'a CellInfo<'a>,
-) -> impl Fn(Lua, (mlua::Value, CellPos)) -> TableLuaBoxFuture<'a, TableValue> {
-    move |_, (_, pos): (mlua::Value, CellPos)| {
-        Box::pin(async move { Ok(info.get(pos).await.into()) })
-    }
-}
-
-type TableBoxFn<'a, T, V> = Box<dyn Fn(Lua, T) -> TableLuaBoxFuture<'a, V> + Send + Sync + 'a>;
-
-fn sum<'a>(info: &'a CellInfo<'a>) -> TableBoxFn<'a, SlicePos, TableValue> {
-    Box::new(move |_lua, pos: SlicePos| {
-        Box::pin({
-            async move {
-                dbg!(pos);
-                let mut sum: f64 = 0.0;
-                for row in pos.rows() {
-                    for column in pos.columns() {
-                        dbg!(row, column);
-                        let cell = pos.shift_to_pos((column, row).into()).unwrap();
-                        let res = info.get(cell).await;
-                        let Ok(val) = res else {
-                            return Ok(res.into());
-                        };
-                        if val.is_err() {
-                            return Ok(val);
-                        }
-                        let TableValue::Number(val) = val else {
-                            continue;
-                        };
-                        sum += val;
-                    }
-                }
-                Ok(TableValue::from_number(sum))
-            }
-        })
-    })
-}
-
-fn rel_cell<'a>(info: &'a CellInfo<'a>) -> TableBoxFn<'a, (i64, i64), TableValue> {
-    Box::new(move |_lua, (shx, shy)| {
-        Box::pin({
-            async move {
-                let x = info.pos().x as i64 + shx;
-                let y = info.pos().y as i64 + shy;
-                if x < 0 || y < 0 {
-                    Ok(TableValue::Empty)
-                } else {
-                    Ok(info.get((x as usize, y as usize).into()).await.into())
-                }
+/// How many Lua instructions are run between the checks of the instruction limit and of whether
+/// the evaluation was cancelled
+const HOOK_INTERVAL: u32 = 1000;
+/// How many Lua instructions a cell may run before it times out
+const INSTRUCTION_LIMIT: u64 = 100_000_000;
+/// How many bytes a cell may allocate in its state
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Globals that access files, print to the terminal, load code outside of the cell's
+/// environment, control the garbage collector or could change the tables shared by the cells
+const REMOVED_GLOBALS: [&str; 10] = [
+    "dofile",
+    "loadfile",
+    "load",
+    "loadstring",
+    "print",
+    "collectgarbage",
+    "getfenv",
+    "setfenv",
+    "rawset",
+    "jit",
+];
+const OS_FUNCTIONS: [&str; 4] = ["clock", "date", "difftime", "time"];
+/// How many idle Lua states a [LuaPool] keeps. An evaluation may need more at once (one for
+/// every cell waiting for its dependencies), the rest are dropped when they are returned
+const MAX_POOLED_STATES: usize = 256;
+
+/// Lua states with the prelude already loaded, shared by the evaluations of a table so that a
+/// state doesn't have to be created for every cell
+#[derive(Debug, Default)]
+pub struct LuaPool {
+    states: Mutex<Vec<Lua>>,
+}
+
+impl LuaPool {
+    fn take(&self) -> Lua {
+        let lua = self
+            .states
+            .lock()
+            .expect("the lock is never poisoned")
+            .pop();
+        lua.unwrap_or_else(new_state)
+    }
+
+    fn put(&self, lua: Lua) {
+        let mut states = self.states.lock().expect("the lock is never poisoned");
+        if states.len() < MAX_POOLED_STATES {
+            states.push(lua);
+        }
+    }
+}
+
+/// Makes a state with the restricted standard library, the builtins and the prelude. Globals that
+/// aren't defined are looked up as cells of the table
+fn new_state() -> Lua {
+    // `jit` is only loaded to turn the compiler off and `os` is replaced with its functions that
+    // only read the clock
+    let libs =
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::BIT | StdLib::OS | StdLib::JIT;
+    let lua = Lua::new_with(libs, LuaOptions::default()).expect("The libraries are safe");
+    let globals = lua.globals();
+    functions::register(&lua).expect("no error is documented");
+    range::register(&lua).expect("The range methods are valid and known at compile time");
+    time::register(&lua).expect("no error is documented");
+    decimal::register(&lua).expect("no error is documented");
+    let builtins = [
+        ("POS", lua.create_async_function(pos)),
+        ("REL", lua.create_async_function(rel_cell)),
+    ];
+    for (name, f) in builtins {
+        globals
+            .set(name, f.expect("no error is documented"))
+            .expect("no error is documented");
+    }
+    let metatable = lua.create_table().expect("no error is documented");
+    metatable
+        .set(
+            "__index",
+            lua.create_async_function(global_cell_access)
+                .expect("no error is documented"),
+        )
+        .expect("no error is documented");
+    globals.set_metatable(Some(metatable));
+
+    lua.load(include_str!("../prelude.lua"))
+        .exec()
+        .expect("Prelude is valid and known at compile time");
+    // Compiled code doesn't run hooks, so a loop could never be cancelled
+    lua.load("jit.off()")
+        .exec()
+        .expect("The jit module is always loaded by LuaJIT");
+    restrict_globals(&lua).expect("The standard library is loaded");
+    lua
+}
+
+fn restrict_globals(lua: &Lua) -> mlua::Result<()> {
+    let globals = lua.globals();
+    for name in REMOVED_GLOBALS {
+        globals.raw_remove(name)?;
+    }
+    let string: mlua::Table = globals.get("string")?;
+    string.raw_remove("dump")?;
+
+    let os: mlua::Table = globals.get("os")?;
+    let restricted_os = lua.create_table()?;
+    for name in OS_FUNCTIONS {
+        restricted_os.raw_set(name, os.get::<mlua::Function>(name)?)?;
+    }
+    globals.raw_set("os", restricted_os)?;
+
+    // The libraries are shared by all the cells evaluated by the state, so a cell can only read
+    // them. The methods of strings are looked up in `string` through their metatable
+    let libraries = globals
+        .pairs::<Value, Value>()
+        .filter_map(|pair| match pair {
+            Ok((name, Value::Table(library))) if name.as_str().is_none_or(|name| name != "_G") => {
+                Some(Ok((name, library)))
             }
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
         })
-    })
+        .collect::<mlua::Result<Vec<_>>>()?;
+    for (name, library) in libraries {
+        globals.raw_set(name, read_only(lua, library)?)?;
+    }
+    let string_metatable: mlua::Table = lua.load("getmetatable('')").eval()?;
+    string_metatable.raw_set("__metatable", false)
 }
 
-fn pos<'a>(info: &'a CellInfo<'a>) -> TableBoxFn<'a, (), (usize, usize)> {
-    Box::new(move |_lua, _| {
-        Box::pin({
-            let pos = info.pos();
-            async move { Ok((pos.x, pos.y)) }
-        })
-    })
+/// Returns a table reading the fields of `table` that can't be changed
+fn read_only(lua: &Lua, table: mlua::Table) -> mlua::Result<mlua::Table> {
+    let proxy = lua.create_table()?;
+    let metatable = lua.create_table()?;
+    metatable.raw_set("__index", table)?;
+    metatable.raw_set(
+        "__newindex",
+        lua.create_function(|_, _: Variadic<Value>| -> mlua::Result<()> {
+            Err(mlua::Error::runtime("libraries can't be changed"))
+        })?,
+    )?;
+    metatable.raw_set("__metatable", false)?;
+    proxy.set_metatable(Some(metatable));
+    Ok(proxy)
 }
 
-unsafe fn trust_me_bro(info: &CellInfo) -> &'static CellInfo<'static> {
-    // clippy warns about 2 identical casts here but his fix suggestion doesn't work
-    #[allow(clippy::unnecessary_cast)]
-    unsafe {
-        &*(info as *const _ as *const CellInfo<'static>)
+/// The cell a Lua state is evaluating. Set as the state's app data for the time of the
+/// evaluation, so that the builtins can be registered once per state
+struct CurrentCell(CellInfo);
+
+fn current_cell(lua: &Lua) -> mlua::Result<CellInfo> {
+    lua.app_data_ref::<CurrentCell>()
+        .map(|cell| cell.0.clone())
+        .ok_or_else(|| mlua::Error::runtime("No cell is being evaluated"))
+}
+
+/// Returns the value of the cell or the range a global is named after
+async fn global_cell_access(lua: Lua, (_, name): (Value, Value)) -> mlua::Result<Value> {
+    if let Some(range) = name.as_str().and_then(|name| name.parse().ok()) {
+        return Range(range).into_lua(&lua);
     }
+    let pos = CellPos::from_lua(name, &lua)?;
+    let info = current_cell(&lua)?;
+    TableValue::from(info.get(pos).await).into_lua(&lua)
 }
 
-pub struct CellEvaluator<'a> {
-    lua: Lua,
-    info: &'static CellInfo<'static>,
-    _phantom_info: PhantomData<&'a CellInfo<'a>>,
+async fn rel_cell(lua: Lua, (shx, shy): (i64, i64)) -> mlua::Result<TableValue> {
+    let info = current_cell(&lua)?;
+    let x = info.pos().x as i64 + shx;
+    let y = info.pos().y as i64 + shy;
+    if x < 0 || y < 0 {
+        Ok(TableValue::Empty)
+    } else {
+        Ok(info.get((x as usize, y as usize).into()).await.into())
+    }
+}
+
+async fn pos(lua: Lua, _: ()) -> mlua::Result<(usize, usize)> {
+    let pos = current_cell(&lua)?.pos();
+    Ok((pos.x, pos.y))
 }
 
-impl<'a> CellEvaluator<'a> {
-    fn new(info: &'a CellInfo<'a>, lua: Lua) -> Self {
-        let info = unsafe { trust_me_bro(info) };
+pub struct CellEvaluator {
+    lua: Lua,
+    info: CellInfo,
+}
 
+impl CellEvaluator {
+    fn new(info: &CellInfo, lua: Lua) -> Self {
+        lua.set_app_data(CurrentCell(info.clone()));
         Self {
             lua,
-            info,
-            _phantom_info: PhantomData,
+            info: info.clone(),
         }
     }
-    fn add_global_fn<T: FromLuaMulti + 'static, V: IntoLuaMulti + 'static>(
-        &mut self,
-        name: &str,
-        f: impl Fn(&'static CellInfo<'static>) -> TableBoxFn<'static, T, V>,
-    ) {
-        let f = self.lua.create_async_function(f(self.info)).unwrap();
-        self.lua.globals().set(name, f).unwrap();
-    }
 
     async fn evaluate(&mut self, source: &str) -> mlua::Result<TableValue> {
-        let global_cell_access = self
+        // The limit is counted from the memory used by the state before the evaluation, which may
+        // include the garbage left by the previous cells
+        self.lua
+            .set_memory_limit(self.lua.used_memory() + MEMORY_LIMIT)?;
+
+        // Globals set by the formula go to its own environment, so that they don't leak to the
+        // cells evaluated later by the same state
+        let env = self.lua.create_table()?;
+        let env_metatable = self.lua.create_table()?;
+        env_metatable.set("__index", self.lua.globals())?;
+        // The globals are shared too
+        env_metatable.set("__metatable", false)?;
+        env.set_metatable(Some(env_metatable));
+        env.raw_set("_G", &env)?;
+
+        let source = references::translate(source);
+        let source = match self.info.number_mode() {
+            NumberMode::Decimal => Cow::Owned(comparisons::translate(&source).into_owned()),
+            NumberMode::Float => source,
+        };
+        // Evaluated like `Chunk::eval_async` does, but in a thread we can set the hook on (hooks
+        // only apply to the thread they were set on)
+        let function = self
             .lua
-            .create_async_function(global_cell_access(self.info))
-            .unwrap();
-        let metatable = self.lua.create_table().expect("no error is documented");
-        metatable.set("__index", global_cell_access).unwrap();
-        self.lua.globals().set_metatable(Some(metatable));
+            .load(format!("return {source}"))
+            .set_environment(env.clone())
+            .into_function()
+            .or_else(|_| {
+                self.lua
+                    .load(source.as_ref())
+                    .set_environment(env)
+                    .into_function()
+            })?;
+        let thread = self.lua.create_thread(function)?;
+        let cancelled = self.info.cancel_flag();
+        let instructions = Cell::new(0);
+        thread.set_hook(
+            HookTriggers::new().every_nth_instruction(HOOK_INTERVAL),
+            move |_, _| {
+                if cancelled.load(Ordering::Relaxed) {
+                    return Err(mlua::Error::runtime("Evaluation cancelled"));
+                }
+                instructions.set(instructions.get() + u64::from(HOOK_INTERVAL));
+                if instructions.get() > INSTRUCTION_LIMIT {
+                    return Err(mlua::Error::external(TableError::TimedOut));
+                }
+                Ok(VmState::Continue)
+            },
+        );
+        thread.into_async::<TableValue>(()).await
+    }
 
-        let chunk = self.lua.load(source);
-        chunk.eval_async::<TableValue>().await
+    /// Detaches the state from the cell, so that it can be reused
+    fn into_lua(self) -> Lua {
+        self.lua.remove_app_data::<CurrentCell>();
+        self.lua
     }
 }
 
-pub async fn evaluate<'a>(source: &str, info: &'a CellInfo<'a>) -> TableValue {
-    let lua = Lua::new();
-    lua.load(include_str!("../prelude.lua"))
-        .exec()
-        .expect("Prelude is valid and known at compile time");
-    let mut ev = CellEvaluator::new(info, lua);
+pub async fn evaluate(source: &str, info: &CellInfo, pool: &LuaPool) -> TableValue {
+    let mut ev = CellEvaluator::new(info, pool.take());
+    let res = ev.evaluate(source).await;
+    pool.put(ev.into_lua());
 
-    ev.add_global_fn("SUM", sum);
-    // ev.add_global_fn("POSX", self_x);
-    // ev.add_global_fn("POSY", self_y);
-    ev.add_global_fn("POS", pos);
-    ev.add_global_fn("REL", rel_cell);
+    res.unwrap_or_else(|err| TableValue::Err(table_error(err)))
+}
 
-    let res = ev.evaluate(source).await;
+/// Converts the errors caused by exceeding the limits to their own variants
+fn table_error(err: mlua::Error) -> TableError {
+    fn limit_error(err: &mlua::Error) -> Option<TableError> {
+        match err {
+            mlua::Error::MemoryError(_) => Some(TableError::MemoryLimitExceeded),
+            mlua::Error::CallbackError { cause, .. } | mlua::Error::WithContext { cause, .. } => {
+                limit_error(cause)
+            }
+            err => err.downcast_ref::<TableError>().cloned(),
+        }
+    }
+    limit_error(&err).unwrap_or_else(|| TableError::LuaError(Arc::new(err)))
+}
+
+/// An error value in Lua. Arithmetic and concatenation with it result in the error, so an error
+/// in a cell propagates to the formulas using it
+#[derive(Debug, Clone)]
+pub struct CellError(pub TableError);
+
+impl UserData for CellError {
+    fn add_fields<F: UserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("code", |_, this| Ok(this.0.code().to_string()));
+    }
+
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(TableValue::Err(this.0.clone()).to_string())
+        });
+        let operators = [
+            MetaMethod::Add,
+            MetaMethod::Sub,
+            MetaMethod::Mul,
+            MetaMethod::Div,
+            MetaMethod::Mod,
+            MetaMethod::Pow,
+            MetaMethod::Unm,
+            MetaMethod::Concat,
+        ];
+        for operator in operators {
+            methods.add_meta_function(operator, |_, operands: Variadic<Value>| {
+                Ok(operands
+                    .into_iter()
+                    .find(|v| v.as_userdata().is_some_and(|ud| ud.is::<CellError>())))
+            });
+        }
+    }
+}
 
-    res.unwrap_or_else(|err| TableValue::Err(TableError::LuaError(Arc::new(err))))
+/// Returns the number of values in a sequence, which is its field `n` if it has one (like the
+/// tables made by `table.pack`), so that it may end with nils
+fn sequence_len(table: &mlua::Table) -> mlua::Result<usize> {
+    match table.raw_get::<Option<usize>>("n") {
+        Ok(Some(n)) => Ok(n),
+        _ => Ok(table.raw_len()),
+    }
+}
+
+/// Converts a table to an array. A sequence of sequences is a sequence of rows, the other values
+/// of a sequence are rows with one value, so `{1, 2}` is a column. Tables nested deeper are
+/// #VALUE! errors, a table without values is empty
+fn array_from_lua(table: &mlua::Table, lua: &Lua) -> mlua::Result<TableValue> {
+    let value = |value: Value| match value {
+        Value::Table(_) => Ok(TableValue::Err(TableError::Code(ErrorCode::Value))),
+        value => TableValue::from_lua(value, lua),
+    };
+    let mut rows = Vec::new();
+    for idx in 1..=sequence_len(table)? {
+        let row = match table.raw_get(idx)? {
+            Value::Table(row) => (1..=sequence_len(&row)?)
+                .map(|idx| value(row.raw_get(idx)?))
+                .collect::<mlua::Result<_>>()?,
+            cell => vec![value(cell)?],
+        };
+        rows.push(row);
+    }
+    Ok(
+        ArrayValue::from_rows(rows).map_or(TableValue::Empty, |array| {
+            TableValue::Array(Arc::new(array))
+        }),
+    )
+}
+
+/// Returns the first of two operands that is an error, which is the result of an operator
+fn first_error(a: &TableValue, b: &TableValue) -> Option<TableValue> {
+    [a, b].into_iter().find(|v| v.is_err()).cloned()
 }
 
 impl FromLua for TableValue {
-    fn from_lua(value: mlua::Value, _lua: &Lua) -> mlua::Result<Self> {
-        use mlua::Value::{Integer, Number};
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        use mlua::Value::{Boolean, Integer, Nil, Number, UserData};
         match value {
+            Nil => Ok(TableValue::Empty),
+            Boolean(b) => Ok(TableValue::Bool(b)),
             Number(n) => Ok(TableValue::Number(n)),
-            Integer(n) => Ok(TableValue::Number(n as f64)),
+            Integer(n) => Ok(TableValue::Int(n)),
+            UserData(ud) if ud.is::<CellError>() => {
+                Ok(TableValue::Err(ud.borrow::<CellError>()?.0.clone()))
+            }
+            UserData(ud) if ud.is::<LuaDateTime>() => {
+                Ok(TableValue::DateTime(ud.borrow::<LuaDateTime>()?.0))
+            }
+            UserData(ud) if ud.is::<LuaDuration>() => {
+                Ok(TableValue::Duration(ud.borrow::<LuaDuration>()?.0))
+            }
+            UserData(ud) if ud.is::<LuaDecimal>() => {
+                Ok(TableValue::Decimal(ud.borrow::<LuaDecimal>()?.0))
+            }
+            mlua::Value::Table(table) => array_from_lua(&table, lua),
             _ => match value.to_string() {
                 Ok(s) => Ok(TableValue::from_stringable(s)),
                 Err(e) => Ok(TableValue::lua_error(e)),
@@ -156,9 +418,25 @@ impl IntoLua for TableValue {
         match self {
             Self::Empty => mlua::Nil.into_lua(lua),
             Self::Text(s) => s.to_string().into_lua(lua),
-            Self::Err(TableError::LuaError(le)) => le.as_ref().to_owned().into_lua(lua),
             Self::Number(value) => Ok(value.into_lua(lua).expect("Failed to conver f64 to lua")),
-            Self::Err(e) => e.to_string().into_lua(lua),
+            Self::Int(value) => value.into_lua(lua),
+            Self::Decimal(value) => LuaDecimal(value).into_lua(lua),
+            Self::Bool(value) => value.into_lua(lua),
+            Self::DateTime(value) => LuaDateTime(value).into_lua(lua),
+            Self::Duration(value) => LuaDuration(value).into_lua(lua),
+            Self::Err(e) => CellError(e).into_lua(lua),
+            // A column becomes a sequence of its values, like the one it could be made from
+            Self::Array(array) if array.width() == 1 => lua
+                .create_sequence_from(array.values().cloned())?
+                .into_lua(lua),
+            Self::Array(array) => lua
+                .create_sequence_from(
+                    array
+                        .rows()
+                        .map(|row| lua.create_sequence_from(row.iter().cloned()))
+                        .collect::<mlua::Result<Vec<_>>>()?,
+                )?
+                .into_lua(lua),
         }
     }
 }
@@ -182,12 +460,15 @@ impl FromLua for CellPos {
 }
 
 impl FromLua for SlicePos {
-    fn from_lua(value: mlua::Value, _lua: &Lua) -> mlua::Result<Self> {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> mlua::Result<Self> {
+        if value.is_userdata() {
+            return Ok(Range::from_lua(value, lua)?.0);
+        }
         let err = Err(mlua::Error::FromLuaConversionError {
             from: "",
             to: "SlicePos".into(),
             message: Some(
-                "CellPos can be created from a string in format {CellPos}_{CellPos}".into(),
+                "SlicePos can be created from a range or a string like A1:B3, A:B or 1:2".into(),
             ),
         });
 
@@ -195,8 +476,15 @@ impl FromLua for SlicePos {
             return err;
         };
         let Ok(pos) = pos.to_str() else { return err };
-        let Ok(pos) = pos.parse() else { return err };
+        let Ok(pos) = pos.parse::<OpenSlicePos>() else {
+            return err;
+        };
 
-        Ok(pos)
+        // Open ranges end at the end of the table being evaluated
+        if pos.is_open() {
+            Ok(current_cell(lua)?.bound(pos))
+        } else {
+            Ok(pos.bound(CellPos::default()))
+        }
     }
 }
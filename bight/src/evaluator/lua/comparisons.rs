@@ -0,0 +1,262 @@
+//! Comparisons of decimals with Lua numbers.
+//!
+//! LuaJIT only calls the comparison metamethods when both values are userdata, so in the decimal
+//! mode `A0 > 0` would fail and `A0 == 0.5` would be false if `A0` is a decimal. Formulas
+//! evaluated in the decimal mode have their comparisons rewritten as calls to `__compare`, which
+//! compares decimals with numbers and compares the other values like the operator does:
+//! `A0 > 0` becomes `__compare(A0, ">", 0)`
+
+use std::borrow::Cow;
+
+use super::references::{is_word_char, skip_literal, word_end};
+
+/// Name of the function the comparisons are rewritten as calls to, see the [module](self) docs
+pub const COMPARE: &str = "__compare";
+
+const COMPARISONS: [&str; 6] = ["==", "~=", "<=", ">=", "<", ">"];
+const SYMBOLS: [&str; 7] = ["...", "==", "~=", "<=", ">=", "..", "::"];
+
+const KEYWORDS: [&str; 22] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Name,
+    Keyword,
+    Number,
+    String,
+    Symbol,
+}
+
+/// A token of the source of a formula. Comments aren't tokens
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    kind: Kind,
+    text: &'a str,
+    start: usize,
+    end: usize,
+}
+
+impl Token<'_> {
+    fn is(&self, kind: Kind, texts: &[&str]) -> bool {
+        self.kind == kind && texts.contains(&self.text)
+    }
+
+    fn opens(&self) -> bool {
+        self.is(Kind::Symbol, &["(", "[", "{"])
+    }
+
+    fn closes(&self) -> bool {
+        self.is(Kind::Symbol, &[")", "]", "}"])
+    }
+
+    fn is_comparison(&self) -> bool {
+        self.is(Kind::Symbol, &COMPARISONS)
+    }
+
+    /// Whether an operand can end with the token
+    fn ends_operand(&self) -> bool {
+        matches!(self.kind, Kind::Name | Kind::Number | Kind::String)
+            || self.closes()
+            || self.is(Kind::Keyword, &["nil", "true", "false"])
+            || self.is(Kind::Symbol, &["..."])
+    }
+
+    /// Whether the token starts a new operand after one that has ended. Calls like `f "x"` or
+    /// `f { }` and binary operators continue it
+    fn starts_operand(&self) -> bool {
+        matches!(self.kind, Kind::Name | Kind::Number)
+            || self.is(Kind::Keyword, &["nil", "true", "false", "not", "function"])
+            || self.is(Kind::Symbol, &["#", "..."])
+    }
+
+    /// Whether the token separates the operands of a comparison from the rest of the source
+    fn separates(&self) -> bool {
+        match self.kind {
+            Kind::Keyword => !["nil", "true", "false", "not", "function"].contains(&self.text),
+            Kind::Symbol => self.is_comparison() || [",", ";", "=", "::"].contains(&self.text),
+            _ => false,
+        }
+    }
+}
+
+fn tokens(source: &str) -> Vec<Token<'_>> {
+    let s = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+    while idx < s.len() {
+        let start = idx;
+        let kind = if s[idx].is_ascii_whitespace() {
+            idx += 1;
+            continue;
+        } else if let Some(end) = skip_literal(s, idx) {
+            idx = end;
+            if s[start..].starts_with(b"--") {
+                continue;
+            }
+            Kind::String
+        } else if s[idx].is_ascii_digit()
+            || (s[idx] == b'.' && s.get(idx + 1).is_some_and(u8::is_ascii_digit))
+        {
+            idx = number_end(s, idx);
+            Kind::Number
+        } else if is_word_char(s[idx]) {
+            idx = word_end(s, idx);
+            if KEYWORDS.contains(&&source[start..idx]) {
+                Kind::Keyword
+            } else {
+                Kind::Name
+            }
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| s[idx..].starts_with(symbol.as_bytes()));
+            idx += symbol.map_or_else(
+                || source[idx..].chars().next().map_or(1, char::len_utf8),
+                |symbol| symbol.len(),
+            );
+            Kind::Symbol
+        };
+        tokens.push(Token {
+            kind,
+            text: &source[start..idx],
+            start,
+            end: idx,
+        });
+    }
+    tokens
+}
+
+/// Returns the index right after the end of the number starting at `start`
+fn number_end(s: &[u8], start: usize) -> usize {
+    let hex = s[start..].starts_with(b"0x") || s[start..].starts_with(b"0X");
+    let exponent: &[u8] = if hex { b"pP" } else { b"eE" };
+    let mut idx = start;
+    while idx < s.len() {
+        let c = s[idx];
+        let sign = matches!(c, b'+' | b'-') && idx > start && exponent.contains(&s[idx - 1]);
+        if !(c.is_ascii_alphanumeric() || c == b'.' || c == b'_' || sign) {
+            break;
+        }
+        idx += 1;
+    }
+    idx
+}
+
+/// Returns the index of the first token of the left operand of the comparison `op`
+fn operand_start(tokens: &[Token], op: usize) -> usize {
+    let mut first = op;
+    let mut depth = 0;
+    while first > 0 {
+        let prev = &tokens[first - 1];
+        if depth > 0 {
+            if prev.closes() {
+                depth += 1;
+            } else if prev.opens() {
+                depth -= 1;
+            }
+        } else if prev.opens()
+            || prev.separates()
+            || (first < op && prev.ends_operand() && tokens[first].starts_operand())
+        {
+            break;
+        } else if prev.closes() {
+            depth = 1;
+        }
+        first -= 1;
+    }
+    first
+}
+
+/// Returns the index of the last token of the right operand of the comparison `op`
+fn operand_end(tokens: &[Token], op: usize) -> usize {
+    let mut last = op;
+    let mut depth = 0;
+    while let Some(next) = tokens.get(last + 1) {
+        if depth > 0 {
+            if next.opens() {
+                depth += 1;
+            } else if next.closes() {
+                depth -= 1;
+            }
+        } else if next.closes()
+            || next.separates()
+            || (last > op && tokens[last].ends_operand() && next.starts_operand())
+        {
+            break;
+        } else if next.opens() {
+            depth = 1;
+        }
+        last += 1;
+    }
+    last
+}
+
+/// Rewrites the first comparison with both operands, or returns None if there isn't one
+fn rewrite_first(source: &str) -> Option<String> {
+    let tokens = tokens(source);
+    tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| token.is_comparison())
+        .find_map(|(op, token)| {
+            let (first, last) = (operand_start(&tokens, op), operand_end(&tokens, op));
+            if first == op || last == op {
+                return None;
+            }
+            let (start, end) = (tokens[first].start, tokens[last].end);
+            Some(format!(
+                "{}{COMPARE}({}, \"{}\", {}){}",
+                &source[..start],
+                &source[start..tokens[op - 1].end],
+                token.text,
+                &source[tokens[op + 1].start..end],
+                &source[end..]
+            ))
+        })
+}
+
+/// Rewrites the comparisons in the source of a formula as calls to [COMPARE]
+pub fn translate(source: &str) -> Cow<'_, str> {
+    let mut source = Cow::Borrowed(source);
+    while let Some(rewritten) = rewrite_first(&source) {
+        source = Cow::Owned(rewritten);
+    }
+    source
+}
+
+#[cfg(test)]
+mod test {
+    use super::translate;
+
+    #[test]
+    fn rewritten_comparisons() {
+        let rewritten = [
+            ("A0 > 0", r#"__compare(A0, ">", 0)"#),
+            (
+                "IF(A0 + 1 >= 2, 'a', x == -1.5e-3)",
+                r#"IF(__compare(A0 + 1, ">=", 2), 'a', __compare(x, "==", -1.5e-3))"#,
+            ),
+            (
+                "a < b and not t:f(1)[2] ~= 'x' .. y",
+                r#"__compare(a, "<", b) and __compare(not t:f(1)[2], "~=", 'x' .. y)"#,
+            ),
+            ("x or #t<=0", r#"x or __compare(#t, "<=", 0)"#),
+            ("a < b < c", r#"__compare(__compare(a, "<", b), "<", c)"#),
+            (
+                "function() local v = x == 1 if v then return y end end",
+                r#"function() local v = __compare(x, "==", 1) if v then return y end end"#,
+            ),
+            ("x = a > b y = 1", r#"x = __compare(a, ">", b) y = 1"#),
+        ];
+        for (source, expected) in rewritten {
+            assert_eq!(translate(source), expected);
+        }
+        let unchanged = ["'a < b' -- c > d", "[[<]] .. x", "A0_B1:map(f)"];
+        for source in unchanged {
+            assert_eq!(translate(source), source);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! Decimal numbers in Lua.
+//!
+//! In the decimal mode (see [NumberMode](crate::evaluator::NumberMode)) the numbers that aren't
+//! integers are passed to formulas as decimals. Arithmetic with decimals and Lua numbers gives
+//! decimals, so `A0 + 0.2` is `0.3` if `A0` is `0.1`. The comparisons written in formulas compare
+//! them with Lua numbers too (`A0 > 0`, see [comparisons](super::comparisons)), Lua functions
+//! only compare them with other decimals. `DECIMAL(value)` makes a decimal from a number or text
+//! (`DECIMAL("0.1")` is exact) and `d:tonumber()` converts one to a Lua number
+
+use mlua::{FromLua, Function, Lua, MetaMethod, UserData, UserDataMethods, Value};
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+
+use crate::evaluator::{
+    ErrorCode, TableValue,
+    lua::{comparisons, first_error, functions::numeric::Numeric},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecimalError {
+    #[error("invalid operands of {0}")]
+    InvalidOperands(&'static str),
+    #[error("the result is too big")]
+    Overflow,
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("not a number: {0}")]
+    NotANumber(String),
+}
+
+impl From<DecimalError> for TableValue {
+    fn from(value: DecimalError) -> Self {
+        let code = match value {
+            DecimalError::DivisionByZero => ErrorCode::DivisionByZero,
+            _ => ErrorCode::Value,
+        };
+        TableValue::coded_error(code, value)
+    }
+}
+
+/// A decimal in Lua, see the [module](self) docs
+#[derive(Debug, Clone, Copy)]
+pub struct LuaDecimal(pub Decimal);
+
+/// Compares two values with the operator named by its second argument
+const LUA_COMPARE: &str = r#"
+local operators = {
+    ["=="] = function(a, b) return a == b end,
+    ["~="] = function(a, b) return a ~= b end,
+    ["<"] = function(a, b) return a < b end,
+    ["<="] = function(a, b) return a <= b end,
+    [">"] = function(a, b) return a > b end,
+    [">="] = function(a, b) return a >= b end,
+}
+return function(a, op, b) return operators[op](a, b) end
+"#;
+
+pub fn register(lua: &Lua) -> mlua::Result<()> {
+    lua.globals().set(
+        "DECIMAL",
+        lua.create_function(|_, value: TableValue| {
+            if value.is_err() {
+                return Ok(value);
+            }
+            Ok(Decimal::from_value(&value).map_or_else(
+                || DecimalError::NotANumber(value.to_string()).into(),
+                TableValue::Decimal,
+            ))
+        })?,
+    )?;
+
+    let lua_compare: Function = lua.load(LUA_COMPARE).set_name("compare").eval()?;
+    lua.globals().set(
+        comparisons::COMPARE,
+        lua.create_function(move |lua, (a, op, b): (Value, mlua::String, Value)| {
+            let decimals = match is_decimal(&a) || is_decimal(&b) {
+                true => (decimal_operand(lua, &a)?, decimal_operand(lua, &b)?),
+                false => (None, None),
+            };
+            let (Some(x), Some(y)) = decimals else {
+                return lua_compare.call::<Value>((a, op, b));
+            };
+            let result = match &*op.as_bytes() {
+                b"==" => x == y,
+                b"~=" => x != y,
+                b"<" => x < y,
+                b"<=" => x <= y,
+                b">" => x > y,
+                b">=" => x >= y,
+                _ => return lua_compare.call::<Value>((a, op, b)),
+            };
+            Ok(Value::Boolean(result))
+        })?,
+    )
+}
+
+fn is_decimal(value: &Value) -> bool {
+    value
+        .as_userdata()
+        .is_some_and(|data| data.is::<LuaDecimal>())
+}
+
+/// Returns the decimal a Lua number or a decimal compares as. Numbers are rounded like the
+/// values of the formulas in the decimal mode, so that `0.1 + 0.2` is `0.3`
+fn decimal_operand(lua: &Lua, value: &Value) -> mlua::Result<Option<Decimal>> {
+    if !(is_decimal(value) || value.is_number() || value.is_integer()) {
+        return Ok(None);
+    }
+    let value = TableValue::from_lua(value.clone(), lua)?.into_decimal_mode();
+    Ok(value.as_decimal())
+}
+
+type Operator = fn(Decimal, Decimal) -> Option<Decimal>;
+
+/// Divides like `operator` does, failing with a division by zero if `b` is 0
+fn divide(a: Decimal, b: Decimal, operator: Operator) -> TableValue {
+    if b.is_zero() {
+        return DecimalError::DivisionByZero.into();
+    }
+    operator(a, b).map_or_else(|| DecimalError::Overflow.into(), TableValue::Decimal)
+}
+
+impl UserData for LuaDecimal {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("tonumber", |_, this, ()| Ok(this.0.to_f64()));
+
+        let operators: [(MetaMethod, &'static str, Operator); 3] = [
+            (MetaMethod::Add, "addition", Decimal::checked_add),
+            (MetaMethod::Sub, "subtraction", Decimal::checked_sub),
+            (MetaMethod::Mul, "multiplication", Decimal::checked_mul),
+        ];
+        for (method, name, operator) in operators {
+            methods.add_meta_function(method, move |_, (a, b): (TableValue, TableValue)| {
+                if let Some(err) = first_error(&a, &b) {
+                    return Ok(err);
+                }
+                let (Some(a), Some(b)) = (a.as_decimal(), b.as_decimal()) else {
+                    return Ok(DecimalError::InvalidOperands(name).into());
+                };
+                Ok(operator(a, b)
+                    .map_or_else(|| DecimalError::Overflow.into(), TableValue::Decimal))
+            });
+        }
+        let divisions: [(MetaMethod, &'static str, Operator); 2] = [
+            (MetaMethod::Div, "division", Decimal::checked_div),
+            (MetaMethod::Mod, "modulo", |a, b| {
+                // Like Lua's modulo, the result has the sign of the divisor
+                let rem = a.checked_rem(b)?;
+                if !rem.is_zero() && rem.is_sign_negative() != b.is_sign_negative() {
+                    rem.checked_add(b)
+                } else {
+                    Some(rem)
+                }
+            }),
+        ];
+        for (method, name, operator) in divisions {
+            methods.add_meta_function(method, move |_, (a, b): (TableValue, TableValue)| {
+                if let Some(err) = first_error(&a, &b) {
+                    return Ok(err);
+                }
+                let (Some(a), Some(b)) = (a.as_decimal(), b.as_decimal()) else {
+                    return Ok(DecimalError::InvalidOperands(name).into());
+                };
+                Ok(divide(a, b, operator))
+            });
+        }
+        // Powers are rarely exact, so they are calculated with floating point numbers
+        methods.add_meta_function(MetaMethod::Pow, |_, (a, b): (TableValue, TableValue)| {
+            if let Some(err) = first_error(&a, &b) {
+                return Ok(err);
+            }
+            let (Some(a), Some(b)) = (a.as_number(), b.as_number()) else {
+                return Ok(DecimalError::InvalidOperands("exponentiation").into());
+            };
+            Ok(TableValue::Number(a.powf(b)))
+        });
+        methods.add_meta_method(MetaMethod::Unm, |_, this, _: Value| {
+            Ok(TableValue::Decimal(-this.0))
+        });
+        methods.add_meta_function(MetaMethod::Concat, |_, (a, b): (TableValue, TableValue)| {
+            Ok(first_error(&a, &b)
+                .unwrap_or_else(|| TableValue::from_stringable(format!("{a}{b}"))))
+        });
+        methods.add_meta_function(MetaMethod::Eq, |_, (a, b): (TableValue, TableValue)| {
+            Ok(a == b)
+        });
+        methods.add_meta_function(MetaMethod::Lt, |_, (a, b): (TableValue, TableValue)| {
+            Ok(a < b)
+        });
+        methods.add_meta_function(MetaMethod::Le, |_, (a, b): (TableValue, TableValue)| {
+            Ok(a <= b)
+        });
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(TableValue::Decimal(this.0).to_string())
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::evaluator::{
+        NumberMode,
+        lua::test_support::{table_in, value},
+    };
+
+    fn eval(formula: &str) -> String {
+        let cells = [
+            ((0, 0), "0.1"),
+            ((0, 1), "0.2"),
+            ((0, 2), "1e-3"),
+            ((1, 0), "=error('boom')"),
+        ];
+        value(
+            &table_in(NumberMode::Decimal, &cells, ((2, 0), formula)),
+            (2, 0),
+        )
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn decimal_arithmetic() {
+        assert_eq!(eval("=A0 + A1"), "0.3");
+        assert_eq!(eval("=0.1 + 0.2"), "0.3");
+        assert_eq!(eval("=A0 * 3 - 0.3"), "0");
+        assert_eq!(eval("=-A0 % 0.3"), "0.2");
+        assert_eq!(eval("=A2 * 1000"), "1");
+        assert_eq!(eval("=1 / 3"), "0.333333333333333");
+        assert_eq!(eval("=A0 + A1 == DECIMAL('0.3')"), "true");
+        assert_eq!(eval("=A0 < DECIMAL(0.15) and A1 >= A0"), "true");
+        assert_eq!(eval("=math.floor(A1:tonumber() * 10)"), "2");
+        assert_eq!(eval("='x' .. A0"), "x0.1");
+        assert_eq!(eval("=SUM(A0_A2, 0.1) - 0.401"), "0");
+        assert_eq!(eval("=AVERAGE(A0_A1)"), "0.15");
+        assert_eq!(eval("=ROUND(2.345, 2) + ROUND(-1250, -2)"), "-1297.65");
+        assert_eq!(eval("=PRODUCT(A0_A1) + ABS(-A2)"), "0.021");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn decimal_comparisons() {
+        assert_eq!(eval("=A0 > 0"), "true");
+        assert_eq!(eval("=0 < A0 and A0 <= 1 and A1 >= A0"), "true");
+        assert_eq!(eval("=A0 == 0.1 and A0 ~= 0.2"), "true");
+        assert_eq!(eval("=A0 + A1 == 0.1 + 0.2"), "true");
+        assert_eq!(eval("=IF(A1 > 0.15, 'big', 'small')"), "big");
+        assert_eq!(
+            eval("=A0_A1:map(function(v) return v < 0.15 end)[2]"),
+            "false"
+        );
+        assert_eq!(eval("=A0 == 'x'"), "false");
+        assert_eq!(eval("='a' < 'b' and #{1} > 0"), "true");
+        assert!(eval("=A0 < 'x'").contains("attempt to compare"));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn decimal_errors() {
+        assert!(eval("=A0 / 0").starts_with("#DIV/0!"));
+        assert_eq!(eval("=A0 + 'x'"), "#VALUE!: invalid operands of addition");
+        assert!(eval("=A0 + B0").contains("boom"));
+        assert_eq!(eval("=DECIMAL('x')"), "#VALUE!: not a number: x");
+        assert_eq!(
+            eval("=PRODUCT(DECIMAL('1e20'), DECIMAL('1e20'))"),
+            "#VALUE!: the result is too big"
+        );
+    }
+}
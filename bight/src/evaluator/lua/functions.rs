@@ -0,0 +1,655 @@
+//! Spreadsheet functions available to the formulas as globals.
+//!
+//! Functions taking several values (like `SUM` or `CONCAT`) also accept ranges (`A0_B2`, `A0:B2`
+//! or `A:A`) and tables (like the ones returned by `map`) and use their values row by row. Text
+//! is always a value, even if it looks like a range, but the arguments that are only ranges (like
+//! the one of `COUNTIF`) accept them as text (`"A0:B2"`) too. Numeric functions skip empty values
+//! and text that isn't a number, and return the first error among the values. Lua booleans count
+//! as 1 and 0
+
+mod lookup;
+pub(super) mod numeric;
+
+use std::sync::Arc;
+
+use mlua::{FromLua, IntoLua, Lua, Value, Variadic};
+use rust_decimal::Decimal;
+
+use crate::{
+    evaluator::{
+        ErrorCode, NumberMode, TableError, TableValue,
+        lua::{
+            current_cell,
+            functions::numeric::{Numeric, numbers},
+            range::Range,
+        },
+    },
+    table::slice::SlicePos,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum FunctionError {
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("not a number: {0}")]
+    NotANumber(String),
+    #[error("not a logical value: {0}")]
+    NotLogical(String),
+    #[error("no logical values")]
+    NoLogicalValues,
+    #[error("invalid argument: {0}")]
+    InvalidArgument(&'static str),
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("index out of range: {0}")]
+    OutOfRange(i64),
+    #[error("the result is too big")]
+    Overflow,
+}
+
+impl FunctionError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            Self::DivisionByZero => ErrorCode::DivisionByZero,
+            Self::NotANumber(_)
+            | Self::NotLogical(_)
+            | Self::NoLogicalValues
+            | Self::InvalidArgument(_)
+            | Self::Overflow => ErrorCode::Value,
+            Self::NotFound(_) => ErrorCode::NotAvailable,
+            Self::OutOfRange(_) => ErrorCode::Reference,
+        }
+    }
+}
+
+impl From<FunctionError> for TableValue {
+    fn from(value: FunctionError) -> Self {
+        TableValue::coded_error(value.code(), value)
+    }
+}
+
+/// The result of a function: a value or an error value, which is returned to Lua as a value too
+type FnResult<T> = Result<T, TableValue>;
+
+pub fn register(lua: &Lua) -> mlua::Result<()> {
+    let globals = lua.globals();
+    globals.set("SUM", lua.create_async_function(sum)?)?;
+    globals.set("AVERAGE", lua.create_async_function(average)?)?;
+    globals.set("MIN", lua.create_async_function(min)?)?;
+    globals.set("MAX", lua.create_async_function(max)?)?;
+    globals.set("PRODUCT", lua.create_async_function(product)?)?;
+    globals.set("COUNT", lua.create_async_function(count)?)?;
+    globals.set("COUNTA", lua.create_async_function(counta)?)?;
+    globals.set("COUNTIF", lua.create_async_function(countif)?)?;
+    globals.set("SUMIF", lua.create_async_function(sumif)?)?;
+    globals.set("AND", lua.create_async_function(and)?)?;
+    globals.set("OR", lua.create_async_function(or)?)?;
+    globals.set("CONCAT", lua.create_async_function(concat)?)?;
+    globals.set("NOT", lua.create_function(not)?)?;
+    globals.set("IF", lua.create_function(r#if)?)?;
+    globals.set("IFERROR", lua.create_function(iferror)?)?;
+    globals.set("ROUND", lua.create_function(round)?)?;
+    globals.set("ABS", lua.create_function(abs)?)?;
+    globals.set("LEFT", lua.create_function(left)?)?;
+    globals.set("RIGHT", lua.create_function(right)?)?;
+    globals.set("MID", lua.create_function(mid)?)?;
+    globals.set("LEN", lua.create_function(len)?)?;
+    globals.set("UPPER", lua.create_function(upper)?)?;
+    globals.set("LOWER", lua.create_function(lower)?)?;
+    globals.set("TRIM", lua.create_function(trim)?)?;
+    globals.set(
+        "NA",
+        lua.create_function(|_, ()| {
+            Ok(TableValue::Err(TableError::Code(ErrorCode::NotAvailable)))
+        })?,
+    )?;
+    globals.set(
+        "REF",
+        lua.create_function(|_, ()| Ok(TableValue::Err(TableError::Code(ErrorCode::Reference))))?,
+    )?;
+    lookup::register(lua)
+}
+
+/// Converts a value passed to a function. Booleans become 1 and 0
+fn arg_value(value: Value, lua: &Lua) -> mlua::Result<TableValue> {
+    match value {
+        Value::Nil => Ok(TableValue::Empty),
+        Value::Boolean(b) => Ok(TableValue::Number(if b { 1.0 } else { 0.0 })),
+        value => TableValue::from_lua(value, lua),
+    }
+}
+
+/// Returns the values of the arguments with the ranges replaced by the values of their cells and
+/// the tables by their values. Text that looks like a range (which may come from a cell) stays
+/// text
+async fn expand(lua: &Lua, args: Variadic<Value>) -> mlua::Result<Vec<TableValue>> {
+    let mut values = Vec::new();
+    for arg in args {
+        let range = match &arg {
+            Value::UserData(_) => SlicePos::from_lua(arg.clone(), lua).ok(),
+            _ => None,
+        };
+        match range {
+            Some(range) => {
+                let info = current_cell(lua)?;
+                for pos in range.positions() {
+                    values.push(info.get(pos).await.into());
+                }
+            }
+            None => match arg_value(arg, lua)? {
+                TableValue::Array(array) => values.extend(array.values().cloned()),
+                value => values.push(value),
+            },
+        }
+    }
+    Ok(values)
+}
+
+fn number(value: &TableValue) -> Option<f64> {
+    match value {
+        TableValue::Text(s) => s.trim().parse().ok().filter(|n: &f64| n.is_finite()),
+        value => value.as_number(),
+    }
+}
+
+/// Converts a single argument that must be a number
+fn number_arg<N: Numeric>(value: Value, lua: &Lua) -> mlua::Result<FnResult<N>> {
+    let value = arg_value(value, lua)?;
+    Ok(match value {
+        TableValue::Err(_) => Err(value),
+        TableValue::Empty => Ok(N::ZERO),
+        value => {
+            N::from_value(&value).ok_or_else(|| FunctionError::NotANumber(value.to_string()).into())
+        }
+    })
+}
+
+/// Converts an optional argument that must be a non-negative integer
+fn count_arg(value: Option<Value>, default: usize, lua: &Lua) -> mlua::Result<FnResult<usize>> {
+    let Some(value) = value else {
+        return Ok(Ok(default));
+    };
+    Ok(number_arg::<f64>(value, lua)?.and_then(|n| {
+        if n < 0.0 {
+            Err(FunctionError::InvalidArgument("negative count").into())
+        } else {
+            Ok(n as usize)
+        }
+    }))
+}
+
+/// Converts a single argument that is used as text
+fn text_arg(value: Value, lua: &Lua) -> mlua::Result<FnResult<String>> {
+    if let Value::Boolean(b) = value {
+        return Ok(Ok(b.to_string()));
+    }
+    let value = arg_value(value, lua)?;
+    Ok(if value.is_err() {
+        Err(value)
+    } else {
+        Ok(value.to_string())
+    })
+}
+
+/// Returns whether a value is true: numbers other than 0 are. Text and empty values are `None`
+fn logical(value: &TableValue) -> FnResult<Option<bool>> {
+    match value {
+        TableValue::Err(_) => Err(value.clone()),
+        TableValue::Bool(b) => Ok(Some(*b)),
+        value => Ok(value.as_number().map(|n| n != 0.0)),
+    }
+}
+
+/// Makes the Lua value of a result
+fn result<T: IntoLua>(res: FnResult<T>, lua: &Lua) -> mlua::Result<Value> {
+    match res {
+        Ok(value) => value.into_lua(lua),
+        Err(err) => err.into_lua(lua),
+    }
+}
+
+/// A calculation with the numbers among some values, with floating point numbers and with
+/// decimals
+type Aggregate = (
+    fn(Vec<f64>) -> FnResult<TableValue>,
+    fn(Vec<Decimal>) -> FnResult<TableValue>,
+);
+
+/// Calculates with the numbers among the values in the cell's number mode
+fn calculate(
+    lua: &Lua,
+    values: &[TableValue],
+    (float, decimal): Aggregate,
+) -> mlua::Result<TableValue> {
+    let res = match current_cell(lua)?.number_mode() {
+        NumberMode::Float => numbers(values).and_then(float),
+        NumberMode::Decimal => numbers(values).and_then(decimal),
+    };
+    Ok(res.unwrap_or_else(|err| err))
+}
+
+async fn aggregate(lua: &Lua, args: Variadic<Value>, f: Aggregate) -> mlua::Result<TableValue> {
+    let values = expand(lua, args).await?;
+    calculate(lua, &values, f)
+}
+
+async fn sum(lua: Lua, args: Variadic<Value>) -> mlua::Result<TableValue> {
+    aggregate(&lua, args, (numeric::sum, numeric::sum)).await
+}
+
+async fn average(lua: Lua, args: Variadic<Value>) -> mlua::Result<TableValue> {
+    aggregate(&lua, args, (numeric::average, numeric::average)).await
+}
+
+async fn min(lua: Lua, args: Variadic<Value>) -> mlua::Result<TableValue> {
+    aggregate(&lua, args, (numeric::min, numeric::min)).await
+}
+
+async fn max(lua: Lua, args: Variadic<Value>) -> mlua::Result<TableValue> {
+    aggregate(&lua, args, (numeric::max, numeric::max)).await
+}
+
+async fn product(lua: Lua, args: Variadic<Value>) -> mlua::Result<TableValue> {
+    aggregate(&lua, args, (numeric::product, numeric::product)).await
+}
+
+/// Counts the numbers. Errors are not counted
+async fn count(lua: Lua, args: Variadic<Value>) -> mlua::Result<usize> {
+    let values = expand(&lua, args).await?;
+    Ok(values.iter().filter(|v| number(v).is_some()).count())
+}
+
+/// Counts the values that aren't empty, including errors
+async fn counta(lua: Lua, args: Variadic<Value>) -> mlua::Result<usize> {
+    let values = expand(&lua, args).await?;
+    Ok(values
+        .iter()
+        .filter(|v| !matches!(v, TableValue::Empty))
+        .count())
+}
+
+/// Returns the values of an argument that can only be a range, so that it can be given as text
+/// (`"A0_B3"`) too
+async fn expand_range(lua: &Lua, range: Value) -> mlua::Result<Vec<TableValue>> {
+    let range = match &range {
+        Value::String(_) => match SlicePos::from_lua(range.clone(), lua) {
+            Ok(pos) => Value::UserData(lua.create_userdata(Range(pos))?),
+            Err(_) => range,
+        },
+        _ => range,
+    };
+    expand(lua, Variadic::from_iter([range])).await
+}
+
+async fn countif(lua: Lua, (range, criterion): (Value, Value)) -> mlua::Result<usize> {
+    let criterion = Criterion::new(&arg_value(criterion, &lua)?);
+    let values = expand_range(&lua, range).await?;
+    Ok(values.iter().filter(|v| criterion.matches(v)).count())
+}
+
+/// Sums the numbers in `sum_range` (or `range` if it's not given) for which the value at the same
+/// position in `range` matches the criterion
+async fn sumif(
+    lua: Lua,
+    (range, criterion, sum_range): (Value, Value, Option<Value>),
+) -> mlua::Result<TableValue> {
+    let criterion = Criterion::new(&arg_value(criterion, &lua)?);
+    let values = expand_range(&lua, range).await?;
+    let summed = match sum_range {
+        Some(sum_range) => expand_range(&lua, sum_range).await?,
+        None => values.clone(),
+    };
+    let matching: Vec<_> = values
+        .iter()
+        .zip(summed)
+        .filter(|(v, _)| criterion.matches(v))
+        .map(|(_, s)| s)
+        .collect();
+    calculate(&lua, &matching, (numeric::sum, numeric::sum))
+}
+
+/// Combines the logical values, skipping text and empty values. Fails if there are none
+async fn logical_fold(
+    lua: &Lua,
+    args: Variadic<Value>,
+    f: impl Fn(bool, bool) -> bool,
+    init: bool,
+) -> mlua::Result<Value> {
+    let values = expand(lua, args).await?;
+    let mut res = None;
+    for value in &values {
+        match logical(value) {
+            Ok(Some(b)) => res = Some(f(res.unwrap_or(init), b)),
+            Ok(None) => {}
+            Err(err) => return err.into_lua(lua),
+        }
+    }
+    result(
+        res.ok_or_else(|| FunctionError::NoLogicalValues.into()),
+        lua,
+    )
+}
+
+async fn and(lua: Lua, args: Variadic<Value>) -> mlua::Result<Value> {
+    logical_fold(&lua, args, |a, b| a && b, true).await
+}
+
+async fn or(lua: Lua, args: Variadic<Value>) -> mlua::Result<Value> {
+    logical_fold(&lua, args, |a, b| a || b, false).await
+}
+
+/// Joins the text of all the values
+async fn concat(lua: Lua, args: Variadic<Value>) -> mlua::Result<TableValue> {
+    let values = expand(&lua, args).await?;
+    if let Some(err) = values.iter().find(|v| v.is_err()) {
+        return Ok(err.clone());
+    }
+    let text: String = values.iter().map(|v| v.to_string()).collect();
+    Ok(TableValue::Text(text.into()))
+}
+
+/// Converts a condition: nil and false are false, numbers other than 0 are true
+fn condition(value: Value, lua: &Lua) -> mlua::Result<FnResult<bool>> {
+    let value = match value {
+        Value::Nil => return Ok(Ok(false)),
+        Value::Boolean(b) => return Ok(Ok(b)),
+        value => TableValue::from_lua(value, lua)?,
+    };
+    Ok(logical(&value)
+        .and_then(|b| b.ok_or_else(|| FunctionError::NotLogical(value.to_string()).into())))
+}
+
+fn not(lua: &Lua, value: Value) -> mlua::Result<Value> {
+    result(condition(value, lua)?.map(|b| !b), lua)
+}
+
+/// Returns `then` if the condition is true, `otherwise` (false if it's not given) if it isn't
+fn r#if(lua: &Lua, (cond, then, otherwise): (Value, Value, Option<Value>)) -> mlua::Result<Value> {
+    match condition(cond, lua)? {
+        Ok(true) => Ok(then),
+        Ok(false) => Ok(otherwise.unwrap_or(Value::Boolean(false))),
+        Err(err) => err.into_lua(lua),
+    }
+}
+
+fn iferror(lua: &Lua, (value, fallback): (Value, Value)) -> mlua::Result<Value> {
+    if arg_value(value.clone(), lua)?.is_err() {
+        Ok(fallback)
+    } else {
+        Ok(value)
+    }
+}
+
+/// Rounds half away from zero. Negative `digits` round to tens, hundreds and so on
+fn round(lua: &Lua, (value, digits): (Value, Option<i32>)) -> mlua::Result<Value> {
+    fn round_in<N: Numeric>(lua: &Lua, value: Value, digits: i32) -> mlua::Result<Value> {
+        let res = number_arg::<N>(value, lua)?.and_then(|n| {
+            n.round_to(digits)
+                .map(N::into_value)
+                .ok_or_else(|| FunctionError::Overflow.into())
+        });
+        result(res, lua)
+    }
+    let digits = digits.unwrap_or(0);
+    match current_cell(lua)?.number_mode() {
+        NumberMode::Float => round_in::<f64>(lua, value, digits),
+        NumberMode::Decimal => round_in::<Decimal>(lua, value, digits),
+    }
+}
+
+fn abs(lua: &Lua, value: Value) -> mlua::Result<Value> {
+    match current_cell(lua)?.number_mode() {
+        NumberMode::Float => result(number_arg::<f64>(value, lua)?.map(Numeric::abs), lua),
+        NumberMode::Decimal => result(
+            number_arg::<Decimal>(value, lua)?.map(|n| n.abs().into_value()),
+            lua,
+        ),
+    }
+}
+
+/// Returns the first `count` (1 if it's not given) characters
+fn left(lua: &Lua, (text, count): (Value, Option<Value>)) -> mlua::Result<Value> {
+    let count = count_arg(count, 1, lua)?;
+    let res =
+        text_arg(text, lua)?.and_then(|text| Ok(text.chars().take(count?).collect::<String>()));
+    result(res, lua)
+}
+
+/// Returns the last `count` (1 if it's not given) characters
+fn right(lua: &Lua, (text, count): (Value, Option<Value>)) -> mlua::Result<Value> {
+    let count = count_arg(count, 1, lua)?;
+    let res = text_arg(text, lua)?.and_then(|text| {
+        let skip = text.chars().count().saturating_sub(count?);
+        Ok(text.chars().skip(skip).collect::<String>())
+    });
+    result(res, lua)
+}
+
+/// Returns `count` characters starting from the `start`th one (counting from 1)
+fn mid(lua: &Lua, (text, start, count): (Value, Value, Value)) -> mlua::Result<Value> {
+    let start = count_arg(Some(start), 1, lua)?;
+    let count = count_arg(Some(count), 0, lua)?;
+    let res = text_arg(text, lua)?.and_then(|text| {
+        let start = start?
+            .checked_sub(1)
+            .ok_or(FunctionError::InvalidArgument("start must be at least 1"))?;
+        Ok(text.chars().skip(start).take(count?).collect::<String>())
+    });
+    result(res, lua)
+}
+
+fn len(lua: &Lua, text: Value) -> mlua::Result<Value> {
+    result(text_arg(text, lua)?.map(|text| text.chars().count()), lua)
+}
+
+fn upper(lua: &Lua, text: Value) -> mlua::Result<Value> {
+    result(text_arg(text, lua)?.map(|text| text.to_uppercase()), lua)
+}
+
+fn lower(lua: &Lua, text: Value) -> mlua::Result<Value> {
+    result(text_arg(text, lua)?.map(|text| text.to_lowercase()), lua)
+}
+
+/// Removes the spaces at the ends and replaces the runs of spaces inside with single ones
+fn trim(lua: &Lua, text: Value) -> mlua::Result<Value> {
+    let res =
+        text_arg(text, lua)?.map(|text| text.split_whitespace().collect::<Vec<_>>().join(" "));
+    result(res, lua)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn test(self, ord: std::cmp::Ordering) -> bool {
+        use std::cmp::Ordering::*;
+        match self {
+            Self::Eq => ord == Equal,
+            Self::Ne => ord != Equal,
+            Self::Lt => ord == Less,
+            Self::Le => ord != Greater,
+            Self::Gt => ord == Greater,
+            Self::Ge => ord != Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Number(f64),
+    /// Compared case-insensitively, so it's stored in lowercase
+    Text(String),
+    Empty,
+    /// Other types are compared with the values of the same type
+    Value(TableValue),
+}
+
+/// A criterion of `COUNTIF` and `SUMIF`: a value to be equal to, or a comparison like `">=10"`
+/// or `"<>done"`. Text is compared case-insensitively and `""` matches empty cells
+#[derive(Debug, Clone, PartialEq)]
+struct Criterion {
+    comparison: Comparison,
+    operand: Operand,
+}
+
+impl Criterion {
+    fn new(value: &TableValue) -> Self {
+        let text = match value {
+            TableValue::Text(text) => text.to_string(),
+            TableValue::Empty => String::new(),
+            value => {
+                return Self {
+                    comparison: Comparison::Eq,
+                    operand: number(value)
+                        .map_or_else(|| Operand::Value(value.clone()), Operand::Number),
+                };
+            }
+        };
+        let prefixes = [
+            ("<=", Comparison::Le),
+            (">=", Comparison::Ge),
+            ("<>", Comparison::Ne),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+            ("=", Comparison::Eq),
+        ];
+        let (comparison, rest) = prefixes
+            .iter()
+            .find_map(|(prefix, comparison)| Some((*comparison, text.strip_prefix(prefix)?)))
+            .unwrap_or((Comparison::Eq, &text));
+        let operand = if rest.is_empty() {
+            Operand::Empty
+        } else {
+            match TableValue::parse_literal(&Arc::from(rest)) {
+                TableValue::Text(text) => Operand::Text(text.to_lowercase()),
+                value => number(&value).map_or(Operand::Value(value), Operand::Number),
+            }
+        };
+        Self {
+            comparison,
+            operand,
+        }
+    }
+
+    fn matches(&self, value: &TableValue) -> bool {
+        let ord = match (&self.operand, value) {
+            (_, TableValue::Err(_)) => None,
+            (Operand::Empty, TableValue::Empty) => Some(std::cmp::Ordering::Equal),
+            (Operand::Empty, _) => None,
+            (Operand::Number(n), value) => number(value).and_then(|v| v.partial_cmp(n)),
+            (Operand::Text(text), TableValue::Text(s)) if number(value).is_none() => {
+                Some(s.to_lowercase().as_str().cmp(text.as_str()))
+            }
+            (Operand::Text(_), _) => None,
+            (Operand::Value(operand), value) => value.partial_cmp(operand),
+        };
+        match ord {
+            Some(ord) => self.comparison.test(ord),
+            // Values that can't be compared with the operand are only "not equal" to it
+            None => self.comparison == Comparison::Ne,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::evaluator::lua::test_support::eval_with;
+
+    /// Evaluates `formula` next to a column with a number, a number formula, text, an empty cell
+    /// and a negative number, a column with 10 to 50 and an error
+    fn eval(formula: &str) -> String {
+        let cells = [
+            ((0, 0), "1"),
+            ((0, 1), "=2.5"),
+            ((0, 2), "text"),
+            ((0, 4), "=-4"),
+            ((1, 0), "10"),
+            ((1, 1), "20"),
+            ((1, 2), "30"),
+            ((1, 3), "40"),
+            ((1, 4), "50"),
+            ((1, 9), "100"),
+            ((2, 0), "=error('boom')"),
+            ((4, 0), "9:30"),
+        ];
+        eval_with(&cells, ((3, 0), formula))
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn aggregates() {
+        assert_eq!(eval(r#"=SUM(A0_A4)"#), "-0.5");
+        assert_eq!(eval(r#"=SUM(A0_A4, 10, true)"#), "10.5");
+        assert_eq!(eval(r#"=AVERAGE(B0_B4)"#), "30");
+        assert_eq!(eval(r#"=AVERAGE(A2_A3)"#), "#DIV/0!: division by zero");
+        assert_eq!(eval(r#"=MIN(A0_A4)"#), "-4");
+        assert_eq!(eval(r#"=MAX(A0_A4)"#), "2.5");
+        assert_eq!(eval(r#"=PRODUCT(A0_A4)"#), "-10");
+        assert_eq!(eval(r#"=COUNT(A0_C0)"#), "2");
+        assert_eq!(eval(r#"=COUNTA(A0_A4, C0_C0)"#), "5");
+        assert!(eval(r#"=SUM(A0_C0)"#).contains("boom"));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn conditional_aggregates() {
+        assert_eq!(eval(r#"=COUNTIF("A0_A4", ">0")"#), "2");
+        assert_eq!(eval(r#"=COUNTIF("A0_A4", "TEXT")"#), "1");
+        assert_eq!(eval(r#"=COUNTIF("A0_A4", "<>1")"#), "4");
+        assert_eq!(eval(r#"=COUNTIF("A0_A4", "")"#), "1");
+        assert_eq!(eval(r#"=COUNTIF("B0_B4", 30)"#), "1");
+        assert_eq!(eval(r#"=SUMIF("A0_A4", ">0")"#), "3.5");
+        assert_eq!(eval(r#"=SUMIF("A0_A4", "text", "B0_B4")"#), "30");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn scalars_and_logic() {
+        assert_eq!(eval("=ROUND(2.5)"), "3");
+        assert_eq!(eval("=ROUND(-2.5)"), "-3");
+        assert_eq!(eval("=ROUND(3.14159, 2)"), "3.14");
+        assert_eq!(eval("=ROUND(1234, -2)"), "1200");
+        assert_eq!(eval("=ABS(A4)"), "4");
+        assert_eq!(eval(r#"=IF(A1 > 0, "pos", "neg")"#), "pos");
+        assert_eq!(eval(r#"=IF(A4 > 0, "pos")"#), "false");
+        assert_eq!(eval("=IFERROR(C0 + 1, 0)"), "0");
+        assert_eq!(eval("=IFERROR(A0, 0)"), "1");
+        assert!(eval("=IF(C0, 1, 2)").contains("boom"));
+        assert_eq!(eval(r#"=AND(A0_A4)"#), "true");
+        assert_eq!(eval(r#"=AND(A0, 0)"#), "false");
+        assert_eq!(eval(r#"=OR(0, false, "x")"#), "false");
+        assert_eq!(eval(r#"=OR(A2_A3)"#), "#VALUE!: no logical values");
+        assert_eq!(eval("=NOT(A3)"), "true");
+        assert_eq!(eval("=IFERROR(#REF!, 0) + 1"), "1");
+        assert_eq!(eval("=#REF!"), "#REF!");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn text() {
+        assert_eq!(eval(r#"=CONCAT(A0_A3, "!", 5)"#), "12.5text!5");
+        assert!(eval(r#"=CONCAT("A0", C0)"#).contains("boom"));
+        // Text that looks like a range is still text
+        assert_eq!(eval(r#"=CONCAT("at ", E0)"#), "at 9:30");
+        assert_eq!(eval(r#"=COUNTA("B0", E0)"#), "2");
+        assert_eq!(eval(r#"=LEFT("héllo", 2)"#), "hé");
+        assert_eq!(eval(r#"=RIGHT("héllo")"#), "o");
+        assert_eq!(eval(r#"=MID("héllo", 2, 3)"#), "éll");
+        assert_eq!(
+            eval(r#"=MID("héllo", 0, 3)"#),
+            "#VALUE!: invalid argument: start must be at least 1"
+        );
+        assert_eq!(eval(r#"=LEN("héllo")"#), "5");
+        assert_eq!(eval(r#"=UPPER(A2)"#), "TEXT");
+        assert_eq!(eval(r#"=LOWER("MiXeD")"#), "mixed");
+        assert_eq!(eval(r#"=TRIM("  a   b  ")"#), "a b");
+        assert_eq!(eval(r#"=C0 .. "x""#), eval("=C0"));
+    }
+}
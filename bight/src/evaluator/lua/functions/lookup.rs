@@ -0,0 +1,536 @@
+//! Functions finding a key in a row or a column of a range.
+//!
+//! A lookup only reads the cells it needs (a binary search reads a few of them), so the formula
+//! only depends on these cells. That's enough for the result to stay correct: as long as the cells
+//! that were read don't change, the search reads the same cells again and finds the same match.
+//!
+//! Numbers (including text that is a number) are compared as numbers and are smaller than text,
+//! which is compared case-insensitively. Other values are compared with the values of the same
+//! type, errors are never matched and an empty key matches empty cells. Approximate matches use a
+//! binary search, so they expect the searched cells to be sorted, and treat the cells that can't be
+//! compared with the key as larger
+
+use std::cmp::Ordering;
+
+use mlua::{IntoLua, Lua, Value};
+
+use super::{FnResult, FunctionError, arg_value, number, result};
+use crate::{
+    evaluator::{TableValue, interaction::CellInfo, lua::current_cell},
+    table::{cell::CellPos, slice::SlicePos},
+};
+
+pub fn register(lua: &Lua) -> mlua::Result<()> {
+    let globals = lua.globals();
+    globals.set("VLOOKUP", lua.create_async_function(vlookup)?)?;
+    globals.set("HLOOKUP", lua.create_async_function(hlookup)?)?;
+    globals.set("MATCH", lua.create_async_function(r#match)?)?;
+    globals.set("XLOOKUP", lua.create_async_function(xlookup)?)?;
+    globals.set("INDEX", lua.create_async_function(index)?)?;
+    Ok(())
+}
+
+/// Compares a cell with the key. `None` if they can't be compared
+fn compare(value: &TableValue, key: &TableValue) -> Option<Ordering> {
+    match (number(value), number(key)) {
+        (Some(a), Some(b)) => a.partial_cmp(&b),
+        (Some(_), None) => matches!(key, TableValue::Text(_)).then_some(Ordering::Less),
+        (None, Some(_)) => matches!(value, TableValue::Text(_)).then_some(Ordering::Greater),
+        (None, None) => match (value, key) {
+            (TableValue::Text(a), TableValue::Text(b)) => {
+                Some(a.to_lowercase().cmp(&b.to_lowercase()))
+            }
+            (value, key) => value.partial_cmp(key),
+        },
+    }
+}
+
+/// A row or a column of cells that is searched
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    start: CellPos,
+    len: usize,
+    horizontal: bool,
+}
+
+impl Line {
+    fn column(range: SlicePos) -> Self {
+        Self {
+            start: range.start,
+            len: range.height(),
+            horizontal: false,
+        }
+    }
+
+    fn row(range: SlicePos) -> Self {
+        Self {
+            start: range.start,
+            len: range.width(),
+            horizontal: true,
+        }
+    }
+
+    /// Makes a line of a range that is a single row or column
+    fn new(range: SlicePos) -> FnResult<Self> {
+        if range.width() == 1 {
+            Ok(Self::column(range))
+        } else if range.height() == 1 {
+            Ok(Self::row(range))
+        } else {
+            Err(FunctionError::InvalidArgument("the range must be a single row or column").into())
+        }
+    }
+
+    fn pos(&self, idx: usize) -> CellPos {
+        if self.horizontal {
+            (self.start.x + idx, self.start.y).into()
+        } else {
+            (self.start.x, self.start.y + idx).into()
+        }
+    }
+
+    async fn get(&self, info: &CellInfo, idx: usize) -> TableValue {
+        info.get(self.pos(idx)).await.into()
+    }
+
+    /// Returns the first cell equal to the key, or the last one if `reverse` is set
+    async fn find(&self, info: &CellInfo, key: &TableValue, reverse: bool) -> Option<usize> {
+        for i in 0..self.len {
+            let idx = if reverse { self.len - 1 - i } else { i };
+            if compare(&self.get(info, idx).await, key) == Some(Ordering::Equal) {
+                return Some(idx);
+            }
+        }
+        None
+    }
+
+    /// Returns the first cell equal to the key in the search order, or the closest cell smaller
+    /// (`Ordering::Less`) or larger (`Ordering::Greater`) than the key if there is none
+    async fn find_closest(
+        &self,
+        info: &CellInfo,
+        key: &TableValue,
+        side: Ordering,
+        reverse: bool,
+    ) -> Option<usize> {
+        let mut closest: Option<(usize, TableValue)> = None;
+        for i in 0..self.len {
+            let idx = if reverse { self.len - 1 - i } else { i };
+            let value = self.get(info, idx).await;
+            match compare(&value, key) {
+                Some(Ordering::Equal) => return Some(idx),
+                Some(ord) if ord == side => {
+                    let closer = closest
+                        .as_ref()
+                        .is_none_or(|(_, best)| compare(&value, best) == Some(side.reverse()));
+                    if closer {
+                        closest = Some((idx, value));
+                    }
+                }
+                _ => {}
+            }
+        }
+        closest.map(|(idx, _)| idx)
+    }
+
+    /// Returns the last cell that is not larger than the key (or not smaller if `descending` is
+    /// set), expecting the cells to be sorted
+    async fn binary_search(
+        &self,
+        info: &CellInfo,
+        key: &TableValue,
+        descending: bool,
+    ) -> Option<usize> {
+        let past = if descending {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        };
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match compare(&self.get(info, mid).await, key) {
+                Some(ord) if ord != past => low = mid + 1,
+                _ => high = mid,
+            }
+        }
+        low.checked_sub(1)
+    }
+}
+
+fn not_found(key: &TableValue) -> TableValue {
+    FunctionError::NotFound(key.to_string()).into()
+}
+
+/// Checks a 1-based index and makes it 0-based
+fn index_arg(idx: i64, len: usize) -> FnResult<usize> {
+    usize::try_from(idx)
+        .ok()
+        .and_then(|idx| idx.checked_sub(1))
+        .filter(|idx| *idx < len)
+        .ok_or_else(|| FunctionError::OutOfRange(idx).into())
+}
+
+/// Finds the key in the first column (or row) of the range and returns the cell in the
+/// `offset`th column (or row) of the range in the same row (or column)
+async fn table_lookup(
+    lua: &Lua,
+    (key, range, offset, approximate): (Value, SlicePos, i64, Option<bool>),
+    horizontal: bool,
+) -> mlua::Result<Value> {
+    let info = current_cell(lua)?;
+    let key = arg_value(key, lua)?;
+    if key.is_err() {
+        return key.into_lua(lua);
+    }
+    let (line, across) = if horizontal {
+        (Line::row(range), range.height())
+    } else {
+        (Line::column(range), range.width())
+    };
+    let offset = match index_arg(offset, across) {
+        Ok(offset) => offset,
+        Err(err) => return err.into_lua(lua),
+    };
+    let idx = if approximate.unwrap_or(true) {
+        line.binary_search(&info, &key, false).await
+    } else {
+        line.find(&info, &key, false).await
+    };
+    let Some(idx) = idx else {
+        return not_found(&key).into_lua(lua);
+    };
+    let pos = line.pos(idx);
+    let pos = if horizontal {
+        (pos.x, pos.y + offset)
+    } else {
+        (pos.x + offset, pos.y)
+    };
+    TableValue::from(info.get(pos.into()).await).into_lua(lua)
+}
+
+/// `VLOOKUP(key, range, column[, approximate])` finds the key in the first column of the range
+/// and returns the cell of the row it's in in the `column`th column (counting from 1).
+/// `approximate` is true by default: the last row not larger than the key is found in the sorted
+/// column
+async fn vlookup(lua: Lua, args: (Value, SlicePos, i64, Option<bool>)) -> mlua::Result<Value> {
+    table_lookup(&lua, args, false).await
+}
+
+/// `HLOOKUP(key, range, row[, approximate])` is [vlookup] with rows and columns swapped
+async fn hlookup(lua: Lua, args: (Value, SlicePos, i64, Option<bool>)) -> mlua::Result<Value> {
+    table_lookup(&lua, args, true).await
+}
+
+/// `MATCH(key, range[, type])` returns the position (counting from 1) of the key in a range that
+/// is a single row or column. Type 0 finds the first equal cell, 1 (the default) the last cell not
+/// larger than the key in an ascending range and -1 the last cell not smaller than the key in a
+/// descending range
+async fn r#match(
+    lua: Lua,
+    (key, range, match_type): (Value, SlicePos, Option<i64>),
+) -> mlua::Result<Value> {
+    let info = current_cell(&lua)?;
+    let key = arg_value(key, &lua)?;
+    if key.is_err() {
+        return key.into_lua(&lua);
+    }
+    let line = match Line::new(range) {
+        Ok(line) => line,
+        Err(err) => return err.into_lua(&lua),
+    };
+    let idx = match match_type.unwrap_or(1) {
+        0 => line.find(&info, &key, false).await,
+        1 => line.binary_search(&info, &key, false).await,
+        -1 => line.binary_search(&info, &key, true).await,
+        _ => {
+            return TableValue::from(FunctionError::InvalidArgument("unknown match type"))
+                .into_lua(&lua);
+        }
+    };
+    result(idx.map(|idx| idx + 1).ok_or_else(|| not_found(&key)), &lua)
+}
+
+/// `XLOOKUP(key, lookup_range, return_range[, if_not_found[, match_mode[, search_mode]]])` finds
+/// the key in `lookup_range` and returns the cell at the same position in `return_range`. Both
+/// must be a single row or column of the same length. If the key isn't found `if_not_found` is
+/// returned if it's given.
+///
+/// Match mode 0 (the default) finds an equal cell, -1 and 1 also accept the closest smaller or
+/// larger cell. Search mode 1 (the default) searches from the first cell, -1 from the last one, 2
+/// and -2 use a binary search in an ascending or descending range
+async fn xlookup(
+    lua: Lua,
+    (key, lookup_range, return_range, if_not_found, match_mode, search_mode): (
+        Value,
+        SlicePos,
+        SlicePos,
+        Option<Value>,
+        Option<i64>,
+        Option<i64>,
+    ),
+) -> mlua::Result<Value> {
+    let info = current_cell(&lua)?;
+    let key = arg_value(key, &lua)?;
+    if key.is_err() {
+        return key.into_lua(&lua);
+    }
+    let lines = Line::new(lookup_range).and_then(|lookup| {
+        let ret = Line::new(return_range)?;
+        if lookup.len == ret.len {
+            Ok((lookup, ret))
+        } else {
+            Err(FunctionError::InvalidArgument("the ranges must be of the same length").into())
+        }
+    });
+    let (lookup, ret) = match lines {
+        Ok(lines) => lines,
+        Err(err) => return err.into_lua(&lua),
+    };
+    let side = match match_mode.unwrap_or(0) {
+        0 => Ordering::Equal,
+        -1 => Ordering::Less,
+        1 => Ordering::Greater,
+        _ => {
+            return TableValue::from(FunctionError::InvalidArgument("unknown match mode"))
+                .into_lua(&lua);
+        }
+    };
+    let search_mode = search_mode.unwrap_or(1);
+    let idx = match search_mode {
+        1 | -1 if side == Ordering::Equal => lookup.find(&info, &key, search_mode == -1).await,
+        1 | -1 => {
+            lookup
+                .find_closest(&info, &key, side, search_mode == -1)
+                .await
+        }
+        2 | -2 => {
+            let descending = search_mode == -2;
+            // The last cell on the key's side of the range, the cells after it are on the other
+            let last = lookup.binary_search(&info, &key, descending).await;
+            let last_side = if descending {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+            let is_equal = match last {
+                Some(idx) => compare(&lookup.get(&info, idx).await, &key) == Some(Ordering::Equal),
+                None => false,
+            };
+            if is_equal || side == last_side {
+                last.filter(|_| is_equal || side != Ordering::Equal)
+            } else if side == Ordering::Equal {
+                None
+            } else {
+                Some(last.map_or(0, |idx| idx + 1)).filter(|idx| *idx < lookup.len)
+            }
+        }
+        _ => {
+            return TableValue::from(FunctionError::InvalidArgument("unknown search mode"))
+                .into_lua(&lua);
+        }
+    };
+    match (idx, if_not_found) {
+        (Some(idx), _) => ret.get(&info, idx).await.into_lua(&lua),
+        (None, Some(value)) if !value.is_nil() => Ok(value),
+        (None, _) => not_found(&key).into_lua(&lua),
+    }
+}
+
+/// `INDEX(range, row[, column])` returns the cell in the `row`th row and the `column`th column of
+/// the range, counting from 1. If the range is a single row, `INDEX(range, column)` works too
+async fn index(
+    lua: Lua,
+    (range, row, column): (SlicePos, i64, Option<i64>),
+) -> mlua::Result<Value> {
+    let info = current_cell(&lua)?;
+    let (row, column) = match column {
+        Some(column) => (row, column),
+        None if range.height() == 1 => (1, row),
+        None => (row, 1),
+    };
+    let pos = index_arg(row, range.height()).and_then(|y| {
+        let x = index_arg(column, range.width())?;
+        Ok(CellPos::from((range.start.x + x, range.start.y + y)))
+    });
+    match pos {
+        Ok(pos) => TableValue::from(info.get(pos).await).into_lua(&lua),
+        Err(err) => err.into_lua(&lua),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::{
+        evaluator::{
+            EvaluatorTable,
+            lua::test_support::{Cell, eval_with, table_with},
+        },
+        table::{Table, cell::CellPos},
+    };
+
+    /// A table of fruits (sorted) and prices in A0:C3, a descending column in D0:D3 and a table
+    /// with a header row in A5:C6
+    const CELLS: [Cell; 21] = [
+        ((0, 0), "apple"),
+        ((0, 1), "banana"),
+        ((0, 2), "cherry"),
+        ((0, 3), "date"),
+        ((1, 0), "10"),
+        ((1, 1), "20"),
+        ((1, 2), "=30"),
+        ((1, 3), "40"),
+        ((2, 0), "red"),
+        ((2, 1), "yellow"),
+        ((2, 2), "=error('boom')"),
+        ((3, 0), "40"),
+        ((3, 1), "30"),
+        ((3, 2), "20"),
+        ((3, 3), "10"),
+        ((0, 5), "1"),
+        ((1, 5), "5"),
+        ((2, 5), "10"),
+        ((0, 6), "x"),
+        ((1, 6), "y"),
+        ((2, 6), "z"),
+    ];
+
+    fn table(formula: &str) -> EvaluatorTable {
+        table_with(&CELLS, ((5, 0), formula))
+    }
+
+    fn eval(formula: &str) -> String {
+        eval_with(&CELLS, ((5, 0), formula))
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn table_lookups() {
+        assert_eq!(eval(r#"=VLOOKUP("banana", "A0_C3", 2, false)"#), "20");
+        assert_eq!(eval(r#"=VLOOKUP("BANANA", "A0_C3", 3, false)"#), "yellow");
+        assert_eq!(eval(r#"=VLOOKUP("blueberry", "A0_C3", 2)"#), "20");
+        assert_eq!(eval(r#"=VLOOKUP("zucchini", "A0_C3", 2)"#), "40");
+        assert_eq!(
+            eval(r#"=VLOOKUP("aardvark", "A0_C3", 2)"#),
+            "#N/A: not found: aardvark"
+        );
+        assert_eq!(
+            eval(r#"=VLOOKUP("fig", "A0_C3", 2, false)"#),
+            "#N/A: not found: fig"
+        );
+        assert_eq!(
+            eval(r#"=VLOOKUP("apple", "A0_C3", 4, false)"#),
+            "#REF!: index out of range: 4"
+        );
+        assert!(eval(r#"=VLOOKUP("cherry", "A0_C3", 3, false)"#).contains("boom"));
+        assert_eq!(eval(r#"=HLOOKUP(7, "A5_C6", 2)"#), "y");
+        assert_eq!(eval(r#"=HLOOKUP("10", "A5_C6", 2, false)"#), "z");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn match_and_index() {
+        assert_eq!(eval(r#"=MATCH("cherry", "A0_A3", 0)"#), "3");
+        assert_eq!(eval(r#"=MATCH(25, "B0_B3")"#), "2");
+        assert_eq!(eval(r#"=MATCH(25, "D0_D3", -1)"#), "2");
+        assert_eq!(eval(r#"=MATCH(5, "A5_C5", 0)"#), "2");
+        assert_eq!(eval(r#"=MATCH(5, "B0_B3")"#), "#N/A: not found: 5");
+        assert_eq!(
+            eval(r#"=MATCH(5, "A0_B1")"#),
+            "#VALUE!: invalid argument: the range must be a single row or column"
+        );
+        assert_eq!(eval(r#"=INDEX("A0_C3", 3, 2)"#), "30");
+        assert_eq!(eval(r#"=INDEX("A0_A3", 2)"#), "banana");
+        assert_eq!(eval(r#"=INDEX("A5_C5", 3)"#), "10");
+        assert_eq!(
+            eval(r#"=INDEX("A0_A3", 5)"#),
+            "#REF!: index out of range: 5"
+        );
+        assert_eq!(
+            eval(r#"=INDEX("A0_C3", 1, 0)"#),
+            "#REF!: index out of range: 0"
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn xlookup() {
+        assert_eq!(eval(r#"=XLOOKUP("date", "A0_A3", "B0_B3")"#), "40");
+        assert_eq!(eval(r#"=XLOOKUP("fig", "A0_A3", "B0_B3", "none")"#), "none");
+        assert_eq!(
+            eval(r#"=XLOOKUP("fig", "A0_A3", "B0_B3")"#),
+            "#N/A: not found: fig"
+        );
+        assert_eq!(eval(r#"=XLOOKUP(5, "A5_C5", "A6_C6")"#), "y");
+        assert_eq!(eval(r#"=XLOOKUP(25, "B0_B3", "A0_A3", nil, 1)"#), "cherry");
+        assert_eq!(eval(r#"=XLOOKUP(25, "B0_B3", "A0_A3", nil, -1)"#), "banana");
+        assert_eq!(eval(r#"=XLOOKUP(25, "D0_D3", "A0_A3", nil, -1)"#), "cherry");
+        assert_eq!(
+            eval(r#"=XLOOKUP(20, "D0_D3", "A0_A3", nil, 0, -1)"#),
+            "cherry"
+        );
+        assert_eq!(
+            eval(r#"=XLOOKUP(25, "B0_B3", "A0_A3", nil, 1, 2)"#),
+            "cherry"
+        );
+        assert_eq!(
+            eval(r#"=XLOOKUP(25, "B0_B3", "A0_A3", nil, -1, 2)"#),
+            "banana"
+        );
+        assert_eq!(
+            eval(r#"=XLOOKUP(30, "B0_B3", "A0_A3", nil, 0, 2)"#),
+            "cherry"
+        );
+        assert_eq!(
+            eval(r#"=XLOOKUP(25, "D0_D3", "A0_A3", nil, 1, -2)"#),
+            "banana"
+        );
+        assert_eq!(
+            eval(r#"=XLOOKUP(25, "D0_D3", "A0_A3", nil, -1, -2)"#),
+            "cherry"
+        );
+        assert_eq!(
+            eval(r#"=XLOOKUP(50, "B0_B3", "A0_A3", nil, 1, 2)"#),
+            "#N/A: not found: 50"
+        );
+        assert_eq!(
+            eval(r#"=XLOOKUP(25, "B0_B3", "A0_A3", nil, 0, 2)"#),
+            "#N/A: not found: 25"
+        );
+        assert_eq!(
+            eval(r#"=XLOOKUP(1, "B0_B3", "A0_A2")"#),
+            "#VALUE!: invalid argument: the ranges must be of the same length"
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn consulted_dependencies() {
+        let dependencies = |formula: &str| -> HashSet<CellPos> {
+            let table = table(formula);
+            table.dependencies[&CellPos::from((5, 0))].clone()
+        };
+        let cells = |cells: &[(usize, usize)]| -> HashSet<CellPos> {
+            cells.iter().map(|&pos| pos.into()).collect()
+        };
+
+        assert_eq!(
+            dependencies(r#"=VLOOKUP("banana", "A0_C3", 2, false)"#),
+            cells(&[(0, 0), (0, 1), (1, 1)])
+        );
+        // The binary search never reads the last cell
+        assert_eq!(
+            dependencies(r#"=MATCH(15, "B0_B3")"#),
+            cells(&[(1, 2), (1, 1), (1, 0)])
+        );
+
+        let mut table = table(r#"=VLOOKUP("banana", "A0_C3", 2, false)"#);
+        table.set_source((0, 3), Some("banana"));
+        assert!(!table.is_pending((5, 0)));
+        table.set_source((0, 0), Some("banana"));
+        table.evaluate();
+        assert_eq!(table.get((5, 0).into()).unwrap().to_string(), "10");
+    }
+}
@@ -0,0 +1,154 @@
+//! The numbers the numeric functions calculate with: floating point numbers, or decimals in the
+//! decimal mode (see [NumberMode](crate::evaluator::NumberMode))
+
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::evaluator::TableValue;
+
+use super::{FnResult, FunctionError, number};
+
+pub trait Numeric: Copy + PartialOrd {
+    const ZERO: Self;
+    /// Converts a value like [number] does
+    fn from_value(value: &TableValue) -> Option<Self>;
+    fn from_count(count: usize) -> Self;
+    /// Returns None if the result doesn't fit
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn checked_mul(self, other: Self) -> Option<Self>;
+    fn checked_div(self, other: Self) -> Option<Self>;
+    fn abs(self) -> Self;
+    /// Rounds half away from zero to `digits` digits after the point, or to tens, hundreds and
+    /// so on if `digits` is negative
+    fn round_to(self, digits: i32) -> Option<Self>;
+    fn into_value(self) -> TableValue;
+}
+
+impl Numeric for f64 {
+    const ZERO: Self = 0.0;
+    fn from_value(value: &TableValue) -> Option<Self> {
+        number(value)
+    }
+    fn from_count(count: usize) -> Self {
+        count as f64
+    }
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Some(self + other)
+    }
+    fn checked_mul(self, other: Self) -> Option<Self> {
+        Some(self * other)
+    }
+    fn checked_div(self, other: Self) -> Option<Self> {
+        Some(self / other)
+    }
+    fn abs(self) -> Self {
+        f64::abs(self)
+    }
+    fn round_to(self, digits: i32) -> Option<Self> {
+        let factor = 10f64.powi(digits);
+        Some((self * factor).round() / factor)
+    }
+    fn into_value(self) -> TableValue {
+        TableValue::Number(self)
+    }
+}
+
+impl Numeric for Decimal {
+    const ZERO: Self = Decimal::ZERO;
+    fn from_value(value: &TableValue) -> Option<Self> {
+        match value {
+            TableValue::Text(s) => {
+                let s = s.trim();
+                Decimal::from_str_exact(s)
+                    .or_else(|_| Decimal::from_scientific(s))
+                    .ok()
+            }
+            value => value.as_decimal(),
+        }
+    }
+    fn from_count(count: usize) -> Self {
+        Decimal::from(count)
+    }
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Decimal::checked_add(self, other)
+    }
+    fn checked_mul(self, other: Self) -> Option<Self> {
+        Decimal::checked_mul(self, other)
+    }
+    fn checked_div(self, other: Self) -> Option<Self> {
+        Decimal::checked_div(self, other)
+    }
+    fn abs(self) -> Self {
+        Decimal::abs(&self)
+    }
+    fn round_to(self, digits: i32) -> Option<Self> {
+        let strategy = RoundingStrategy::MidpointAwayFromZero;
+        if digits >= 0 {
+            return Some(self.round_dp_with_strategy(digits.unsigned_abs(), strategy));
+        }
+        let factor = Decimal::from(10i64.checked_pow(digits.unsigned_abs())?);
+        (self / factor)
+            .round_dp_with_strategy(0, strategy)
+            .checked_mul(factor)
+    }
+    fn into_value(self) -> TableValue {
+        TableValue::Decimal(self)
+    }
+}
+
+/// Returns the numbers among the values or the first error
+pub fn numbers<N: Numeric>(values: &[TableValue]) -> FnResult<Vec<N>> {
+    let mut numbers = Vec::new();
+    for value in values {
+        if value.is_err() {
+            return Err(value.clone());
+        }
+        numbers.extend(N::from_value(value));
+    }
+    Ok(numbers)
+}
+
+pub fn sum<N: Numeric>(numbers: Vec<N>) -> FnResult<TableValue> {
+    numbers
+        .into_iter()
+        .try_fold(N::ZERO, N::checked_add)
+        .map(N::into_value)
+        .ok_or_else(|| FunctionError::Overflow.into())
+}
+
+/// Fails with a division by zero if there are no numbers
+pub fn average<N: Numeric>(numbers: Vec<N>) -> FnResult<TableValue> {
+    if numbers.is_empty() {
+        return Err(FunctionError::DivisionByZero.into());
+    }
+    let count = N::from_count(numbers.len());
+    numbers
+        .into_iter()
+        .try_fold(N::ZERO, N::checked_add)
+        .and_then(|sum| sum.checked_div(count))
+        .map(N::into_value)
+        .ok_or_else(|| FunctionError::Overflow.into())
+}
+
+/// Returns 0 if there are no numbers
+pub fn min<N: Numeric>(numbers: Vec<N>) -> FnResult<TableValue> {
+    let min = numbers.into_iter().reduce(|a, b| if b < a { b } else { a });
+    Ok(min.unwrap_or(N::ZERO).into_value())
+}
+
+/// Returns 0 if there are no numbers
+pub fn max<N: Numeric>(numbers: Vec<N>) -> FnResult<TableValue> {
+    let max = numbers.into_iter().reduce(|a, b| if b > a { b } else { a });
+    Ok(max.unwrap_or(N::ZERO).into_value())
+}
+
+/// Returns 0 if there are no numbers
+pub fn product<N: Numeric>(numbers: Vec<N>) -> FnResult<TableValue> {
+    let mut numbers = numbers.into_iter();
+    let Some(first) = numbers.next() else {
+        return Ok(N::ZERO.into_value());
+    };
+    numbers
+        .try_fold(first, N::checked_mul)
+        .map(N::into_value)
+        .ok_or_else(|| FunctionError::Overflow.into())
+}
@@ -0,0 +1,274 @@
+use mlua::{
+    FromLua, FromLuaMulti, IntoLua, Lua, MetaMethod, MultiValue, UserData, UserDataMethods, Value,
+};
+
+use crate::{
+    evaluator::{TableValue, lua::current_cell},
+    table::{cell::CellPos, slice::SlicePos},
+};
+
+/// Name of the registry value with the methods of ranges written in Lua
+const LUA_METHODS: &str = "bight.range_methods";
+
+/// A range of cells in Lua. Globals named like `A0_B3` are ranges, `RANGE(x1, y1, x2, y2)` makes
+/// one from the positions of its corners and `RANGE("A0:B3")` from a reference, which may be a
+/// whole column like `A:A` (see [OpenSlicePos](crate::table::slice::OpenSlicePos)). The cells are
+/// read when they are used, so the formula only depends on the cells it reads:
+///
+/// - `r:width()` and `r:height()` return the size of the range and `#r` the number of its cells
+/// - `r[i]` returns the value of the `i`th cell (counting from 1 row by row)
+/// - `for i, value in r:values()` iterates over the values row by row, `value` is `nil` for empty
+///   cells
+/// - `for i, row in r:rows()` and `for i, col in r:cols()` iterate over the rows and the columns
+///   as ranges
+/// - `r:map(f)` returns the results of `f(value, i)` for all the cells in a table with the field
+///   `n` set to their number, like `table.pack` does
+/// - `r:filter(f)` returns the values that aren't empty and for which `f(value)` is true
+///
+/// The functions that take only ranges (like `VLOOKUP`) accept them as strings like `"A0_B3"` too
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range(pub SlicePos);
+
+impl Range {
+    fn len(&self) -> usize {
+        self.0.width() * self.0.height()
+    }
+
+    /// Returns the position of the `idx`th cell, counting from 0 row by row
+    fn pos(&self, idx: usize) -> Option<CellPos> {
+        let width = self.0.width();
+        (idx < self.len())
+            .then(|| (self.0.start.x + idx % width, self.0.start.y + idx / width).into())
+    }
+
+    fn row(&self, y: usize) -> Self {
+        let y = self.0.start.y + y;
+        Self(SlicePos::new((self.0.start.x, y), (self.0.end.x, y + 1)))
+    }
+
+    fn col(&self, x: usize) -> Self {
+        let x = self.0.start.x + x;
+        Self(SlicePos::new((x, self.0.start.y), (x + 1, self.0.end.y)))
+    }
+}
+
+pub fn register(lua: &Lua) -> mlua::Result<()> {
+    let methods: mlua::Table = lua.load(include_str!("../../range.lua")).eval()?;
+    lua.set_named_registry_value(LUA_METHODS, methods)?;
+    lua.globals().set(
+        "RANGE",
+        lua.create_function(|lua, args: MultiValue| {
+            if args.len() == 1 {
+                return Ok(Range(SlicePos::from_lua_multi(args, lua)?));
+            }
+            let (x1, y1, x2, y2) = <(usize, usize, usize, usize)>::from_lua_multi(args, lua)?;
+            let range = SlicePos::new((x1, y1), (x2, y2));
+            Ok(Range(SlicePos::new(
+                range.start,
+                (range.end.x + 1, range.end.y + 1),
+            )))
+        })?,
+    )
+}
+
+/// Returns the `idx`th value (counting from 1) and the next index, which makes the function a
+/// stateless iterator like the one of `ipairs`
+async fn next_value(
+    lua: Lua,
+    (range, idx): (Range, usize),
+) -> mlua::Result<(Option<usize>, TableValue)> {
+    let Some(pos) = range.pos(idx) else {
+        return Ok((None, TableValue::Empty));
+    };
+    let value = current_cell(&lua)?.get(pos).await.into();
+    Ok((Some(idx + 1), value))
+}
+
+impl UserData for Range {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("width", |_, this, ()| Ok(this.0.width()));
+        methods.add_method("height", |_, this, ()| Ok(this.0.height()));
+        methods.add_method("values", |lua, this, ()| {
+            Ok((lua.create_async_function(next_value)?, *this, 0))
+        });
+        methods.add_method("rows", |lua, this, ()| {
+            let next = lua.create_function(|_, (range, y): (Range, usize)| {
+                let more = y < range.0.height();
+                Ok((more.then_some(y + 1), more.then(|| range.row(y))))
+            })?;
+            Ok((next, *this, 0))
+        });
+        methods.add_method("cols", |lua, this, ()| {
+            let next = lua.create_function(|_, (range, x): (Range, usize)| {
+                let more = x < range.0.width();
+                Ok((more.then_some(x + 1), more.then(|| range.col(x))))
+            })?;
+            Ok((next, *this, 0))
+        });
+
+        methods.add_meta_method(MetaMethod::Len, |_, this, ()| Ok(this.len()));
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            let end = CellPos::from((this.0.end.x - 1, this.0.end.y - 1));
+            Ok(format!("{}_{end}", this.0.start))
+        });
+        // Methods that call Lua functions are written in Lua, so that the functions run in the
+        // formula's thread, where the limits apply
+        methods.add_async_meta_method(MetaMethod::Index, |lua, this, key: Value| async move {
+            if key.is_string() {
+                let methods: mlua::Table = lua.named_registry_value(LUA_METHODS)?;
+                return methods.get::<Value>(key);
+            }
+            let Some(pos) = usize::from_lua(key, &lua)
+                .ok()
+                .and_then(|idx| idx.checked_sub(1))
+                .and_then(|idx| this.pos(idx))
+            else {
+                return Ok(Value::Nil);
+            };
+            TableValue::from(current_cell(&lua)?.get(pos).await).into_lua(&lua)
+        });
+    }
+}
+
+impl FromLua for Range {
+    fn from_lua(value: Value, _lua: &Lua) -> mlua::Result<Self> {
+        match value {
+            Value::UserData(ud) => Ok(*ud.borrow::<Self>()?),
+            value => Err(mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "Range".into(),
+                message: None,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        evaluator::{
+            EvaluatorTable, TableError, TableValue,
+            lua::test_support::{Cell, eval_with, table_with},
+        },
+        table::Table,
+    };
+
+    /// A 2x3 range: 1 and 2, 3 and empty, text and an error
+    const CELLS: [Cell; 5] = [
+        ((0, 0), "1"),
+        ((1, 0), "=2"),
+        ((0, 1), "=3"),
+        ((0, 2), "text"),
+        ((1, 2), "=error('boom')"),
+    ];
+
+    /// Evaluates `formula` next to the range
+    fn table(formula: &str) -> EvaluatorTable {
+        table_with(&CELLS, ((3, 0), formula))
+    }
+
+    fn eval(formula: &str) -> String {
+        eval_with(&CELLS, ((3, 0), formula))
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn range_values() {
+        assert_eq!(eval("=tostring(A0_B2)"), "A0_B2");
+        assert_eq!(eval("=tostring(RANGE(1, 2, 0, 0))"), "A0_B2");
+        assert_eq!(eval("=A0_B2:width() * 10 + A0_B2:height()"), "23");
+        assert_eq!(eval("=#A0_B2"), "6");
+        assert_eq!(eval("=A0_B2[3] + A0_B2[2]"), "5");
+        assert_eq!(eval("=A0_B2[4] == nil and A0_B2[7] == nil"), "true");
+        assert_eq!(eval("=SUM(A0_B1) + MATCH(3, A0_A1, 0)"), "8");
+        let values = "=(function()
+            local s = ''
+            for i, v in A0_B2:values() do s = s .. i .. '=' .. type(v) .. ' ' end
+            return s
+        end)()";
+        assert_eq!(
+            eval(values),
+            "1=number 2=number 3=number 4=nil 5=string 6=userdata "
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn rows_and_columns() {
+        let rows = "=(function()
+            local s = ''
+            for i, row in A0_B2:rows() do s = s .. i .. ':' .. tostring(row) .. ' ' end
+            return s
+        end)()";
+        assert_eq!(eval(rows), "1:A0_B0 2:A1_B1 3:A2_B2 ");
+        let cols = "=(function()
+            local sums = {}
+            for _, col in A0_B1:cols() do sums[#sums + 1] = SUM(col) end
+            return table.concat(sums, ',')
+        end)()";
+        assert_eq!(eval(cols), "4,2");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn map_and_filter() {
+        let doubled = "=(function()
+            local t = A0_A1:map(function(v, i) return v * 2 + i end)
+            return t.n .. ':' .. t[1] .. ',' .. t[2]
+        end)()";
+        assert_eq!(eval(doubled), "2:3,8");
+        let filtered = "=table.concat(A0_B1:filter(function(v) return tonumber(v) > 1 end), ',')";
+        assert_eq!(eval(filtered), "2,3");
+        assert!(matches!(
+            table("=A0_B2:map(function() while true do end end)")
+                .get((3, 0).into())
+                .unwrap(),
+            TableValue::Err(TableError::TimedOut)
+        ));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn dependencies_are_tracked() {
+        let mut table = table("=A0_A1:map(function(v) return v end)[2] + A0_B2[2]");
+        assert_eq!(table.get((3, 0).into()).unwrap().to_string(), "5");
+        table.set_source((0, 1), Some("=10"));
+        table.evaluate();
+        assert_eq!(table.get((3, 0).into()).unwrap().to_string(), "12");
+        table.set_source((0, 2), Some("other"));
+        assert!(!table.is_pending((3, 0)));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn spreadsheet_references() {
+        let mut table = EvaluatorTable::default();
+        let cells = [
+            ((0, 0), "1"),
+            ((0, 1), "2"),
+            ((1, 5), "=SUM(A:A)"),
+            ((2, 5), "=$A$1 * 10 + SUM(A0:$A1)"),
+            ((3, 5), "=COUNT(RANGE('0:1')) + #RANGE('A1:A')"),
+        ];
+        for (pos, source) in cells {
+            table.set_source(pos, Some(source));
+        }
+        table.evaluate();
+        let value = |table: &EvaluatorTable, pos: (usize, usize)| {
+            table.get(pos.into()).unwrap().to_string()
+        };
+        assert_eq!(value(&table, (1, 5)), "3");
+        assert_eq!(value(&table, (2, 5)), "23");
+        assert_eq!(value(&table, (3, 5)), "7");
+
+        // Whole columns grow with the table
+        table.set_source((0, 9), Some("5"));
+        assert!(table.is_pending((1, 5)) && table.is_pending((3, 5)));
+        assert!(!table.is_pending((2, 5)));
+        table.evaluate();
+        assert_eq!(value(&table, (1, 5)), "8");
+        table.set_source((0, 3), Some("4"));
+        table.evaluate();
+        assert_eq!(value(&table, (1, 5)), "12");
+    }
+}
@@ -0,0 +1,522 @@
+//! Translation of the references other spreadsheets use in formulas to Lua, and adjusting of the
+//! references when formulas are copied or the cells they refer to move.
+//!
+//! Ranges like `A1:B3`, `A:A` or `3:5` become `RANGE("A1:B3")`, absolute cells like `$A$1`
+//! become `A1` and `#REF!` becomes a reference error, so formulas like `SUM($A1:B3)` can be
+//! pasted from other spreadsheets. Strings, comments, fields (`t.A1`), method calls (`r:map(f)`)
+//! and the names of called functions (`LOG10(x)`) are left as they are
+
+use std::{borrow::Cow, fmt::Display};
+
+use crate::table::{
+    Axis, LineChange,
+    cell::CellPos,
+    slice::{OpenSlicePos, SlicePos},
+};
+
+/// What a reference shifted off the table becomes
+const REF_ERROR: &str = "#REF!";
+
+pub(super) fn is_word_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'$'
+}
+
+/// Returns the length of the opening of a long bracket (`[[` or `[==[`) at the start of `s` and
+/// its level
+fn long_bracket(s: &[u8]) -> Option<(usize, usize)> {
+    let level = s.get(1..)?.iter().take_while(|&&c| c == b'=').count();
+    (s[0] == b'[' && s.get(level + 1) == Some(&b'[')).then_some((level + 2, level))
+}
+
+/// Returns the index right after the end of the string or comment starting at `start`, or None
+/// if there isn't one there
+pub(super) fn skip_literal(s: &[u8], start: usize) -> Option<usize> {
+    let rest = &s[start..];
+    let closing = |level: usize, from: usize| {
+        let close = format!("]{}]", "=".repeat(level));
+        s[from..]
+            .windows(close.len())
+            .position(|w| w == close.as_bytes())
+            .map_or(s.len(), |idx| from + idx + close.len())
+    };
+    if rest.starts_with(b"--") {
+        if let Some((len, level)) = long_bracket(&rest[2..]) {
+            return Some(closing(level, start + 2 + len));
+        }
+        let end = rest.iter().position(|&c| c == b'\n').unwrap_or(rest.len());
+        return Some(start + end);
+    }
+    if let Some((len, level)) = long_bracket(rest) {
+        return Some(closing(level, start + len));
+    }
+    let quote = *rest.first().filter(|&&c| c == b'"' || c == b'\'')?;
+    let mut idx = start + 1;
+    while idx < s.len() && s[idx] != quote && s[idx] != b'\n' {
+        idx += if s[idx] == b'\\' { 2 } else { 1 };
+    }
+    Some((idx + 1).min(s.len()))
+}
+
+pub(super) fn word_end(s: &[u8], start: usize) -> usize {
+    start + s[start..].iter().take_while(|&&c| is_word_char(c)).count()
+}
+
+/// Whether a name followed by the text is called (`f(...)`, `f"..."` or `a:b{...}`)
+fn is_call(rest: &[u8]) -> bool {
+    let rest = rest.trim_ascii_start();
+    matches!(rest.first(), Some(b'(' | b'"' | b'\'' | b'{')) || long_bracket(rest).is_some()
+}
+
+/// Whether the name starting at `idx` is a field (`t.A1`), a method (`r:A1()`) or a label
+/// (`::A1::`). Names concatenated with `..` aren't fields
+fn is_field(s: &[u8], idx: usize) -> bool {
+    let before = |n: usize| idx.checked_sub(n).map(|i| s[i]);
+    match before(1) {
+        Some(b'.') => before(2) != Some(b'.'),
+        Some(b':') => before(2) == Some(b':') || is_call(&s[word_end(s, idx)..]),
+        _ => false,
+    }
+}
+
+/// A reference found in the source of a formula
+enum Reference<'a> {
+    /// A name like `A1`, `$A$1` or `A0_B2`
+    Name(&'a str),
+    /// A range with a colon like `A1:B3`, `A:A` or `3:5`
+    Range(&'a str),
+    /// `#REF!`, a reference that was shifted off the table
+    Error,
+}
+
+/// Replaces the references in the source of a formula with the text `replace` returns for them,
+/// keeping the ones it returns None for
+fn rewrite(source: &str, mut replace: impl FnMut(Reference) -> Option<String>) -> Cow<'_, str> {
+    let s = source.as_bytes();
+    let mut rewritten = String::new();
+    // The end of the part of the source that was copied to `rewritten`
+    let mut copied = 0;
+    let mut idx = 0;
+    while idx < s.len() {
+        if let Some(end) = skip_literal(s, idx) {
+            idx = end;
+            continue;
+        }
+        let (reference, end) = if s[idx..].starts_with(REF_ERROR.as_bytes()) {
+            (Reference::Error, idx + REF_ERROR.len())
+        } else if !is_word_char(s[idx]) {
+            idx += 1;
+            continue;
+        } else if is_field(s, idx) {
+            idx = word_end(s, idx);
+            continue;
+        } else {
+            let end = word_end(s, idx);
+            let range_end = (s.get(end) == Some(&b':'))
+                .then(|| word_end(s, end + 1))
+                .filter(|&range_end| !is_call(&s[range_end..]));
+            match range_end {
+                Some(range_end) => (Reference::Range(&source[idx..range_end]), range_end),
+                // Names of the functions that are called (like `LOG10(x)`) aren't cells
+                None if is_call(&s[end..]) => {
+                    idx = end;
+                    continue;
+                }
+                None => (Reference::Name(&source[idx..end]), end),
+            }
+        };
+        match replace(reference) {
+            Some(replacement) => {
+                rewritten.push_str(&source[copied..idx]);
+                rewritten.push_str(&replacement);
+                copied = end;
+                idx = end;
+            }
+            None => idx = end,
+        }
+    }
+    if copied == 0 {
+        return Cow::Borrowed(source);
+    }
+    rewritten.push_str(&source[copied..]);
+    Cow::Owned(rewritten)
+}
+
+/// Translates the references in the source of a formula, see the [module](self) docs
+pub fn translate(source: &str) -> Cow<'_, str> {
+    rewrite(source, |reference| match reference {
+        Reference::Range(range) => range
+            .parse::<OpenSlicePos>()
+            .ok()
+            .map(|_| format!("RANGE(\"{range}\")")),
+        Reference::Name(name) => {
+            (name.contains('$') && is_cell(name)).then(|| name.replace('$', ""))
+        }
+        Reference::Error => Some(String::from("REF()")),
+    })
+}
+
+/// Whether the name is a cell like `A1` or `$A$1`. Names of columns like `SUM` aren't
+fn is_cell(name: &str) -> bool {
+    name.trim_start_matches('$')
+        .starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.ends_with(|c: char| c.is_ascii_digit())
+        && name.parse::<CellPos>().is_ok()
+}
+
+/// A column or a row of a corner of a reference
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    idx: usize,
+    /// Whether it is marked with `$`
+    absolute: bool,
+}
+
+/// A corner of a reference: a cell (`A1`), a column (`A`) or a row (`1`), written with optional
+/// `$` before the column and the row
+#[derive(Debug, Clone, Copy)]
+struct Corner {
+    column: Option<Line>,
+    row: Option<Line>,
+}
+
+impl Corner {
+    fn parse(corner: &str) -> Option<Self> {
+        let (column_absolute, rest) = match corner.strip_prefix('$') {
+            Some(rest) => (true, rest),
+            None => (false, corner),
+        };
+        let letters = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let (column, row) = rest.split_at(letters);
+        let (row_absolute, row) = match row.strip_prefix('$') {
+            Some(row) => (true, row),
+            // The `$` of a row without a column (`$3`) is the row's
+            None => (column_absolute && column.is_empty(), row),
+        };
+        let column = match column {
+            "" => None,
+            column => Some(Line {
+                idx: CellPos::column_from_name(column).ok()?,
+                absolute: column_absolute,
+            }),
+        };
+        let row = match row {
+            "" => None,
+            row => Some(Line {
+                idx: row.parse().ok()?,
+                absolute: row_absolute,
+            }),
+        };
+        Some(Self { column, row })
+    }
+
+    fn line(&mut self, axis: Axis) -> &mut Option<Line> {
+        match axis {
+            Axis::Column => &mut self.column,
+            Axis::Row => &mut self.row,
+        }
+    }
+
+    /// Returns the cell if the corner has both a column and a row
+    fn cell(self) -> Option<CellPos> {
+        Some((self.column?.idx, self.row?.idx).into())
+    }
+
+    /// Moves the corner of a cell, keeping the `$` markers
+    fn set_cell(&mut self, pos: CellPos) {
+        for (axis, idx) in [(Axis::Column, pos.x), (Axis::Row, pos.y)] {
+            if let Some(line) = self.line(axis) {
+                line.idx = idx;
+            }
+        }
+    }
+
+    /// Shifts the relative column and row, returning None if they are shifted off the table
+    fn shift(mut self, dx: isize, dy: isize) -> Option<Self> {
+        for (axis, by) in [(Axis::Column, dx), (Axis::Row, dy)] {
+            if let Some(line) = self.line(axis).as_mut().filter(|line| !line.absolute) {
+                line.idx = line.idx.checked_add_signed(by)?;
+            }
+        }
+        Some(self)
+    }
+}
+
+impl Display for Corner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let marker = |line: Line| if line.absolute { "$" } else { "" };
+        if let Some(column) = self.column {
+            write!(f, "{}{}", marker(column), CellPos::column_name(column.idx))?;
+        }
+        if let Some(row) = self.row {
+            write!(f, "{}{}", marker(row), row.idx)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the cells and the ranges in the source of a formula with the ones `map` returns for
+/// their corners (the cell, or the start and the end of the range), or with `#REF!` if it returns
+/// None. Names written in lowercase (like `x1`) are taken for Lua variables and left as they are
+fn map_references(
+    source: &str,
+    map: impl Fn(Corner, Option<Corner>) -> Option<(Corner, Option<Corner>)>,
+) -> Cow<'_, str> {
+    let is_uppercase = |s: &str| !s.chars().any(|c| c.is_ascii_lowercase());
+    rewrite(source, |reference| {
+        let (start, end, separator) = match reference {
+            Reference::Range(range) if range.parse::<OpenSlicePos>().is_ok() => {
+                let (start, end) = range.split_once(':')?;
+                (start, Some(end), ':')
+            }
+            Reference::Name(name) => match name.split_once('_') {
+                Some((start, end)) if is_cell(start) && is_cell(end) => (start, Some(end), '_'),
+                None if is_cell(name) => (name, None, '_'),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        if !is_uppercase(start) || !end.is_none_or(is_uppercase) {
+            return None;
+        }
+        let end = match end {
+            Some(end) => Some(Corner::parse(end)?),
+            None => None,
+        };
+        let mapped = match map(Corner::parse(start)?, end) {
+            Some((start, Some(end))) => format!("{start}{separator}{end}"),
+            Some((start, None)) => start.to_string(),
+            None => String::from(REF_ERROR),
+        };
+        Some(mapped)
+    })
+}
+
+/// Shifts the references in the source of a formula by `dx` columns and `dy` rows, like copying
+/// it to a cell `dx` columns and `dy` rows away does. The columns and rows marked with `$` stay
+/// and the references shifted off the table become `#REF!`
+pub fn shift(source: &str, dx: isize, dy: isize) -> Cow<'_, str> {
+    map_references(source, |start, end| {
+        let end = match end {
+            Some(end) => Some(end.shift(dx, dy)?),
+            None => None,
+        };
+        Some((start.shift(dx, dy)?, end))
+    })
+}
+
+/// Adjusts the references in the source of a formula to the cells moved by inserting or deleting
+/// columns or rows, including the ones marked with `$`. Ranges grow and shrink with the lines
+/// inserted and deleted inside them, and the references to deleted cells become `#REF!`
+pub fn adjust(source: &str, change: LineChange) -> Cow<'_, str> {
+    let axis = change.axis();
+    map_references(source, |mut start, end| {
+        let Some(mut end) = end else {
+            let line = start.line(axis).as_mut()?;
+            line.idx = change.move_line(axis, line.idx)?;
+            return Some((start, None));
+        };
+        // Whole columns (`A:A`) and rows (`3:3`) don't change along the other axis
+        if let Some(first) = start.line(axis).as_mut() {
+            let last = end.line(axis).as_mut();
+            let (first_idx, last_idx) =
+                change.move_range(axis, first.idx, last.as_ref().map(|last| last.idx))?;
+            first.idx = first_idx;
+            if let (Some(last), Some(idx)) = (last, last_idx) {
+                last.idx = idx;
+            }
+        }
+        Some((start, Some(end)))
+    })
+}
+
+/// Adjusts the references in the source of a formula to the cells of `area` moved by `dx` columns
+/// and `dy` rows, including the ones marked with `$`. Ranges only move if they are inside the
+/// area, and the references to the cells the moved ones replace become `#REF!`
+pub fn relocate(source: &str, area: SlicePos, dx: isize, dy: isize) -> Cow<'_, str> {
+    let moved = |pos: CellPos| -> Option<CellPos> {
+        Some((pos.x.checked_add_signed(dx)?, pos.y.checked_add_signed(dy)?).into())
+    };
+    let target = moved(area.start)
+        .map(|start| SlicePos::new(start, (start.x + area.width(), start.y + area.height())));
+    map_references(source, |mut start, end| {
+        let Some(mut end) = end else {
+            if let Some(pos) = start.cell() {
+                if area.is_inside(pos) {
+                    start.set_cell(moved(pos)?);
+                } else if target.is_some_and(|target| target.is_inside(pos)) {
+                    return None;
+                }
+            }
+            return Some((start, None));
+        };
+        if let (Some(first), Some(last)) = (start.cell(), end.cell())
+            && area.is_inside(first)
+            && area.is_inside(last)
+        {
+            start.set_cell(moved(first)?);
+            end.set_cell(moved(last)?);
+        }
+        Some((start, Some(end)))
+    })
+}
+
+/// Renames the columns of the references in the source of a formula written when the letters of
+/// the columns were digits from 0 to 25, so that `BA` was the column after `Z` and `AB` was `B`.
+/// The columns too large to be named become `#REF!`
+pub fn rename_old_columns(source: &str) -> Cow<'_, str> {
+    let old_column = |line: &mut Line| {
+        line.idx = CellPos::column_name(line.idx)
+            .bytes()
+            .try_fold(0usize, |x, c| {
+                x.checked_mul(26)?.checked_add((c - b'A') as usize)
+            })?;
+        Some(())
+    };
+    map_references(source, |mut start, mut end| {
+        for corner in std::iter::once(&mut start).chain(end.as_mut()) {
+            if let Some(column) = corner.column.as_mut() {
+                old_column(column)?;
+            }
+        }
+        Some((start, end))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{adjust, relocate, rename_old_columns, shift, translate};
+    use crate::table::{Axis, LineChange, slice::SlicePos};
+
+    #[test]
+    fn translated_references() {
+        assert_eq!(translate("SUM(A1:B3)"), r#"SUM(RANGE("A1:B3"))"#);
+        assert_eq!(
+            translate("$A$1 + SUM($A:$A, 3:3) * A$2"),
+            r#"A1 + SUM(RANGE("$A:$A"), RANGE("3:3")) * A2"#
+        );
+        assert_eq!(translate("B2:C"), r#"RANGE("B2:C")"#);
+        assert_eq!(translate("#REF! + 1"), "REF() + 1");
+        assert_eq!(translate(r#""x"..$A$1 .. $B$2"#), r#""x"..A1 .. B2"#);
+        assert_eq!(translate("'x'..A1:B2"), r#"'x'..RANGE("A1:B2")"#);
+        let unchanged = [
+            "A0_B2:map(function(v) return v end)",
+            "r:filter { } + r:width () + s:rep'x'",
+            "t.A1:B3()",
+            "'A1:B3' .. \"$A$1\" .. [[A:A]]",
+            "1 -- A1:B3\n+ 2 --[==[ $A$1 ]==]",
+            "A1_B3 + x1 + 1.5",
+            "goto x; ::a::",
+            "goto A1; ::A1::",
+        ];
+        for source in unchanged {
+            assert_eq!(translate(source), source);
+        }
+    }
+
+    #[test]
+    fn shifted_references() {
+        assert_eq!(shift("=A1+B1", 0, 1), "=A2+B2");
+        assert_eq!(shift("=$A$1 + $A1 + A$1", 2, 3), "=$A$1 + $A4 + C$1");
+        assert_eq!(
+            shift("=SUM(A0_B2, A1:B3, C:C, 3:5, B2:C)", 1, 1),
+            "=SUM(B1_C3, B2:C4, D:D, 4:6, C3:D)"
+        );
+        assert_eq!(shift("=SUM($A:A, $3:3)", 1, 1), "=SUM($A:B, $3:4)");
+        assert_eq!(shift("=Z1 + AA1", 1, 0), "=AA1 + AB1");
+        assert_eq!(shift("=A1 + B1 + A1_B2", -1, 0), "=#REF! + A1 + #REF!");
+        assert_eq!(shift("=A1:A3 + #REF!", 0, -2), "=#REF! + #REF!");
+        assert_eq!(shift("=LEN(MAX1)", 1, 1), "=LEN(MAY2)");
+        assert_eq!(shift("=A1..B1", 1, 1), "=B2..C2");
+        assert_eq!(shift(r#"="x"..A1 .. t.A1"#, 0, 1), r#"="x"..A2 .. t.A1"#);
+        let unchanged = [
+            "=SUM(x1, t.A1, 'A1')",
+            "=ABS(LEN(x)) + LOG10(100) + ATAN2 (1, 2) + MAX1'x'",
+        ];
+        for source in unchanged {
+            assert_eq!(shift(source, 1, 1), source);
+        }
+        assert_eq!(
+            shift("=SUM(x1, t.A1, 'A1', A1_B2:map(f))", 1, 1),
+            "=SUM(x1, t.A1, 'A1', B2_C3:map(f))"
+        );
+    }
+
+    #[test]
+    fn adjusted_references() {
+        let insert = LineChange::Insert {
+            axis: Axis::Row,
+            at: 2,
+            count: 3,
+        };
+        assert_eq!(
+            adjust(
+                "=A1 + $A$2 + SUM(A1:B3, C:C, 2:2, B3:C, A0_A1) + x2",
+                insert
+            ),
+            "=A1 + $A$5 + SUM(A1:B6, C:C, 5:5, B6:C, A0_A1) + x2"
+        );
+        assert_eq!(
+            adjust("=LOG10(A2) + MAX9(1)", insert),
+            "=LOG10(A5) + MAX9(1)"
+        );
+        assert_eq!(adjust("=A1..A2..'x'..A3", insert), "=A1..A5..'x'..A6");
+        let insert = LineChange::Insert {
+            axis: Axis::Column,
+            at: 1,
+            count: 1,
+        };
+        assert_eq!(
+            adjust("=A1 + B1 + SUM(A:C, 3:3)", insert),
+            "=A1 + C1 + SUM(A:D, 3:3)"
+        );
+
+        let delete = LineChange::Delete {
+            axis: Axis::Row,
+            at: 2,
+            count: 2,
+        };
+        assert_eq!(
+            adjust("=A1 + A2 + A3 + A4 + SUM(A1:A4, A2:A3, A3:B9, 3:3)", delete),
+            "=A1 + #REF! + #REF! + A2 + SUM(A1:A2, #REF!, A2:B7, #REF!)"
+        );
+        let delete = LineChange::Delete {
+            axis: Axis::Column,
+            at: 0,
+            count: 2,
+        };
+        assert_eq!(
+            adjust("=SUM(A:C, B1_D2, 3:3) + $C$1", delete),
+            "=SUM(A:A, A1_B2, 3:3) + $A$1"
+        );
+    }
+
+    #[test]
+    fn relocated_references() {
+        // B1:C2 moved to D3:E4
+        let area = SlicePos::new((1, 1), (3, 3));
+        assert_eq!(
+            relocate(
+                "=B1 + $C$2 + A1 + SUM(B1:C2, B1_B2, A1:C2, B:B)",
+                area,
+                2,
+                2
+            ),
+            "=D3 + $E$4 + A1 + SUM(D3:E4, D3_D4, A1:C2, B:B)"
+        );
+        assert_eq!(relocate("=D3 + E5 + C2", area, 2, 2), "=#REF! + E5 + E4");
+        assert_eq!(relocate("=b1 + 'B1'", area, 2, 2), "=b1 + 'B1'");
+        assert_eq!(relocate("=C2(1) + C2", area, 2, 2), "=C2(1) + E4");
+        assert_eq!(relocate("='x'..B1..A1", area, 2, 2), "='x'..D3..A1");
+    }
+
+    #[test]
+    fn renamed_old_columns() {
+        assert_eq!(
+            rename_old_columns("=BA1 + SUM($AB$2:ZZ3, A:BA, 3:4) + Z0_BZ9 + t.BA1"),
+            "=AA1 + SUM($B$2:YZ3, A:AA, 3:4) + Z0_AZ9 + t.BA1"
+        );
+        assert_eq!(rename_old_columns("=A1 + 'BA1'"), "=A1 + 'BA1'");
+    }
+}
@@ -0,0 +1,35 @@
+//! Tables for the tests of the formulas
+
+use crate::{
+    evaluator::{EvaluatorTable, NumberMode},
+    table::Table,
+};
+
+/// A cell with its source
+pub type Cell<'a> = ((usize, usize), &'a str);
+
+/// Makes a table of the cells and the formula in the number mode, evaluated
+pub fn table_in(mode: NumberMode, cells: &[Cell], formula: Cell) -> EvaluatorTable {
+    let mut table = EvaluatorTable::default();
+    table.set_number_mode(mode);
+    for &(pos, source) in cells.iter().chain([&formula]) {
+        table.set_source(pos, Some(source));
+    }
+    table.evaluate();
+    table
+}
+
+/// Makes a table of the cells and the formula, evaluated
+pub fn table_with(cells: &[Cell], formula: Cell) -> EvaluatorTable {
+    table_in(NumberMode::default(), cells, formula)
+}
+
+/// Returns the value of the formula in a table of the cells as text
+pub fn eval_with(cells: &[Cell], formula: Cell) -> String {
+    value(&table_with(cells, formula), formula.0)
+}
+
+/// Returns the value of the cell as text
+pub fn value(table: &EvaluatorTable, pos: (usize, usize)) -> String {
+    table.get(pos.into()).unwrap().to_string()
+}
@@ -0,0 +1,269 @@
+//! Dates with time and durations in Lua.
+//!
+//! `DATE(year, month, day[, hour, minute, second])` makes a date and `DURATION(hours[, minutes[,
+//! seconds]])` a duration. A date has the fields `year`, `month`, `day`, `hour`, `minute` and
+//! `second`, a duration has `seconds`. Dates and durations can be compared with values of the same
+//! type and added and subtracted like spreadsheets do: subtracting dates gives a duration and
+//! numbers added to dates are days. Durations can be multiplied and divided by numbers
+
+use jiff::{SignedDuration, civil::DateTime};
+use mlua::{Lua, MetaMethod, UserData, UserDataFields, UserDataMethods, Value};
+
+use crate::evaluator::{ErrorCode, TableValue, lua::first_error};
+
+const SECONDS_PER_DAY: f64 = 24.0 * 60.0 * 60.0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TimeError {
+    #[error("invalid operands of {0}")]
+    InvalidOperands(&'static str),
+    #[error("the result is out of range")]
+    OutOfRange,
+    #[error(transparent)]
+    InvalidDate(#[from] jiff::Error),
+}
+
+impl From<TimeError> for TableValue {
+    fn from(value: TimeError) -> Self {
+        TableValue::coded_error(ErrorCode::Value, value)
+    }
+}
+
+/// A date in Lua, see the [module](self) docs
+#[derive(Debug, Clone, Copy)]
+pub struct LuaDateTime(pub DateTime);
+
+/// A duration in Lua, see the [module](self) docs
+#[derive(Debug, Clone, Copy)]
+pub struct LuaDuration(pub SignedDuration);
+
+pub fn register(lua: &Lua) -> mlua::Result<()> {
+    let globals = lua.globals();
+    globals.set("DATE", lua.create_function(date)?)?;
+    globals.set("DURATION", lua.create_function(duration)?)?;
+    Ok(())
+}
+
+fn date(
+    _: &Lua,
+    (year, month, day, hour, minute, second): (i16, i8, i8, Option<i8>, Option<i8>, Option<i8>),
+) -> mlua::Result<TableValue> {
+    let datetime = DateTime::new(
+        year,
+        month,
+        day,
+        hour.unwrap_or(0),
+        minute.unwrap_or(0),
+        second.unwrap_or(0),
+        0,
+    );
+    Ok(match datetime {
+        Ok(datetime) => TableValue::DateTime(datetime),
+        Err(err) => TimeError::from(err).into(),
+    })
+}
+
+fn duration(
+    _: &Lua,
+    (hours, minutes, seconds): (f64, Option<f64>, Option<f64>),
+) -> mlua::Result<TableValue> {
+    let seconds = hours * 3600.0 + minutes.unwrap_or(0.0) * 60.0 + seconds.unwrap_or(0.0);
+    Ok(seconds_to_duration(seconds)
+        .map(TableValue::Duration)
+        .unwrap_or_else(|| TimeError::OutOfRange.into()))
+}
+
+fn seconds_to_duration(seconds: f64) -> Option<SignedDuration> {
+    SignedDuration::try_from_secs_f64(seconds).ok()
+}
+
+/// Adds a duration (or a number of days) to a date
+fn shift(datetime: DateTime, by: &TableValue, negate: bool) -> Option<TableValue> {
+    let by = match by {
+        TableValue::Duration(d) => *d,
+        by => seconds_to_duration(by.as_number()? * SECONDS_PER_DAY)?,
+    };
+    let by = if negate { by.checked_neg()? } else { by };
+    Some(
+        datetime
+            .checked_add(by)
+            .map(TableValue::DateTime)
+            .unwrap_or_else(|_| TimeError::OutOfRange.into()),
+    )
+}
+
+fn add(a: TableValue, b: TableValue) -> Option<TableValue> {
+    use TableValue::{DateTime, Duration};
+    match (&a, &b) {
+        (DateTime(dt), by) | (by, DateTime(dt)) => shift(*dt, by, false),
+        (Duration(a), Duration(b)) => Some(
+            a.checked_add(*b)
+                .map_or_else(|| TimeError::OutOfRange.into(), TableValue::Duration),
+        ),
+        _ => None,
+    }
+}
+
+fn sub(a: TableValue, b: TableValue) -> Option<TableValue> {
+    use TableValue::{DateTime, Duration};
+    match (&a, &b) {
+        (DateTime(a), DateTime(b)) => Some(Duration(a.duration_since(*b))),
+        (DateTime(dt), by) => shift(*dt, by, true),
+        (Duration(a), Duration(b)) => Some(
+            a.checked_sub(*b)
+                .map_or_else(|| TimeError::OutOfRange.into(), TableValue::Duration),
+        ),
+        _ => None,
+    }
+}
+
+fn mul(a: TableValue, b: TableValue) -> Option<TableValue> {
+    let ((TableValue::Duration(d), n) | (n, TableValue::Duration(d))) = (&a, &b) else {
+        return None;
+    };
+    let seconds = d.as_secs_f64() * n.as_number()?;
+    Some(
+        seconds_to_duration(seconds)
+            .map_or_else(|| TimeError::OutOfRange.into(), TableValue::Duration),
+    )
+}
+
+fn div(a: TableValue, b: TableValue) -> Option<TableValue> {
+    let TableValue::Duration(d) = a else {
+        return None;
+    };
+    match b {
+        TableValue::Duration(by) if by.is_zero() => Some(TableValue::coded_error(
+            ErrorCode::DivisionByZero,
+            TimeError::OutOfRange,
+        )),
+        TableValue::Duration(by) => Some(TableValue::Number(d.div_duration_f64(by))),
+        by => {
+            let seconds = d.as_secs_f64() / by.as_number()?;
+            Some(
+                seconds_to_duration(seconds)
+                    .map_or_else(|| TimeError::OutOfRange.into(), TableValue::Duration),
+            )
+        }
+    }
+}
+
+/// Adds the operators shared by dates and durations. Errors in the operands are propagated
+fn add_operators<T, M: UserDataMethods<T>>(methods: &mut M) {
+    type Operator = fn(TableValue, TableValue) -> Option<TableValue>;
+    let operators: [(MetaMethod, &'static str, Operator); 4] = [
+        (MetaMethod::Add, "addition", add),
+        (MetaMethod::Sub, "subtraction", sub),
+        (MetaMethod::Mul, "multiplication", mul),
+        (MetaMethod::Div, "division", div),
+    ];
+    for (method, name, operator) in operators {
+        methods.add_meta_function(method, move |_, (a, b): (TableValue, TableValue)| {
+            if let Some(err) = first_error(&a, &b) {
+                return Ok(err);
+            }
+            Ok(operator(a, b).unwrap_or_else(|| TimeError::InvalidOperands(name).into()))
+        });
+    }
+    methods.add_meta_function(MetaMethod::Unm, |_, (a, _): (TableValue, Value)| {
+        Ok(match a {
+            TableValue::Duration(d) => d
+                .checked_neg()
+                .map_or_else(|| TimeError::OutOfRange.into(), TableValue::Duration),
+            _ => TimeError::InvalidOperands("negation").into(),
+        })
+    });
+    methods.add_meta_function(MetaMethod::Concat, |_, (a, b): (TableValue, TableValue)| {
+        Ok(first_error(&a, &b).unwrap_or_else(|| TableValue::from_stringable(format!("{a}{b}"))))
+    });
+    methods.add_meta_function(MetaMethod::Eq, |_, (a, b): (TableValue, TableValue)| {
+        Ok(a == b)
+    });
+    methods.add_meta_function(MetaMethod::Lt, |_, (a, b): (TableValue, TableValue)| {
+        Ok(a < b)
+    });
+    methods.add_meta_function(MetaMethod::Le, |_, (a, b): (TableValue, TableValue)| {
+        Ok(a <= b)
+    });
+}
+
+impl UserData for LuaDateTime {
+    fn add_fields<F: UserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("year", |_, this| Ok(this.0.year()));
+        fields.add_field_method_get("month", |_, this| Ok(this.0.month()));
+        fields.add_field_method_get("day", |_, this| Ok(this.0.day()));
+        fields.add_field_method_get("hour", |_, this| Ok(this.0.hour()));
+        fields.add_field_method_get("minute", |_, this| Ok(this.0.minute()));
+        fields.add_field_method_get("second", |_, this| Ok(this.0.second()));
+    }
+
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        add_operators(methods);
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(TableValue::DateTime(this.0).to_string())
+        });
+    }
+}
+
+impl UserData for LuaDuration {
+    fn add_fields<F: UserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("seconds", |_, this| Ok(this.0.as_secs_f64()));
+    }
+
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        add_operators(methods);
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| {
+            Ok(TableValue::Duration(this.0).to_string())
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::evaluator::lua::test_support::eval_with;
+
+    fn eval(formula: &str) -> String {
+        let cells = [((0, 0), "2024-05-06 07:08:09"), ((1, 0), "=error('boom')")];
+        eval_with(&cells, ((2, 0), formula))
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn date_arithmetic() {
+        assert_eq!(eval("=DATE(2024, 1, 31) + 1"), "2024-02-01");
+        assert_eq!(eval("=0.5 + DATE(2024, 1, 31)"), "2024-01-31 12:00:00");
+        assert_eq!(
+            eval("=DATE(2024, 3, 1, 12) - DATE(2024, 2, 28)"),
+            "60:00:00"
+        );
+        assert_eq!(
+            eval("=DATE(2024, 3, 1) - DURATION(0, 0, 1)"),
+            "2024-02-29 23:59:59"
+        );
+        assert_eq!(eval("=DURATION(1, 30) * 2"), "3:00:00");
+        assert_eq!(eval("=-DURATION(0, 0, 1.5)"), "-0:00:01.5");
+        assert_eq!(eval("=DURATION(3) / DURATION(0, 30)"), "6");
+        assert_eq!(eval("=DATE(2024, 1, 1) < DATE(2024, 1, 2)"), "true");
+        assert_eq!(eval("=DATE(2024, 1, 1) == DATE(2024, 1, 1, 0)"), "true");
+        assert_eq!(eval("='due ' .. DATE(2024, 1, 1)"), "due 2024-01-01");
+        assert_eq!(
+            eval("=A0.year * 100 + A0.month + A0.second / 100"),
+            "202405.09"
+        );
+        assert_eq!(eval("=(A0 + DURATION(1)).hour"), "8");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Runs Lua
+    fn date_errors() {
+        assert!(eval("=DATE(2024, 2, 30)").starts_with("#VALUE!: "));
+        assert_eq!(
+            eval("=DATE(2024, 1, 1) + 'x'"),
+            "#VALUE!: invalid operands of addition"
+        );
+        assert!(eval("=DURATION(1) / DURATION(0)").starts_with("#DIV/0!"));
+        assert!(eval("=A0 + B0").contains("boom"));
+        assert_eq!(eval("=A0 + NA()"), "#N/A");
+        assert_eq!(eval("=NA().code"), "#N/A");
+    }
+}
@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::{borrow::Cow, path::Path, sync::Arc};
 
 use rkyv::{
     Archive, Deserialize, Serialize, access, deserialize,
@@ -6,7 +6,11 @@ use rkyv::{
     to_bytes,
 };
 
-use crate::evaluator::SourceTable;
+use crate::{
+    editor::history::UndoTree,
+    evaluator::{NumberMode, SourceTable, lua::references::rename_old_columns},
+    table::layout::Layout,
+};
 
 #[derive(Archive, Serialize, Deserialize)]
 #[repr(C)]
@@ -42,6 +46,125 @@ impl BightFileV1 {
     const VERSION: u64 = 2;
 }
 
+#[derive(Archive, Serialize, Deserialize, Default)]
+pub struct BightFileV2 {
+    pub source: SourceTable,
+    /// Undo history, only present if it was saved with the workbook
+    pub history: Option<UndoTree>,
+}
+
+impl BightFileV2 {
+    const VERSION: u64 = 3;
+}
+
+impl From<BightFileV1> for BightFileV2 {
+    fn from(value: BightFileV1) -> Self {
+        Self {
+            source: value.source,
+            history: None,
+        }
+    }
+}
+
+#[derive(Archive, Serialize, Deserialize, Default)]
+pub struct BightFileV3 {
+    pub source: SourceTable,
+    /// Undo history, only present if it was saved with the workbook
+    pub history: Option<UndoTree>,
+    /// Column widths and row heights
+    pub layout: Layout,
+}
+
+impl BightFileV3 {
+    const VERSION: u64 = 4;
+}
+
+impl From<BightFileV2> for BightFileV3 {
+    fn from(value: BightFileV2) -> Self {
+        Self {
+            source: value.source,
+            history: value.history,
+            layout: Layout::default(),
+        }
+    }
+}
+
+#[derive(Archive, Serialize, Deserialize, Default)]
+pub struct BightFileV4 {
+    pub source: SourceTable,
+    /// Undo history, only present if it was saved with the workbook
+    pub history: Option<UndoTree>,
+    /// Column widths and row heights
+    pub layout: Layout,
+    /// How the numbers in the sources are evaluated
+    pub number_mode: NumberMode,
+}
+
+impl BightFileV4 {
+    const VERSION: u64 = 5;
+}
+
+impl From<BightFileV3> for BightFileV4 {
+    fn from(value: BightFileV3) -> Self {
+        Self {
+            source: value.source,
+            history: value.history,
+            layout: value.layout,
+            number_mode: NumberMode::Float,
+        }
+    }
+}
+
+/// The same as [BightFileV4], but the columns in the formulas are named in bijective base 26
+/// (`AA` is the column after `Z`)
+#[derive(Archive, Serialize, Deserialize, Default)]
+pub struct BightFileV5 {
+    pub source: SourceTable,
+    /// Undo history, only present if it was saved with the workbook
+    pub history: Option<UndoTree>,
+    /// Column widths and row heights
+    pub layout: Layout,
+    /// How the numbers in the sources are evaluated
+    pub number_mode: NumberMode,
+}
+
+impl BightFileV5 {
+    const VERSION: u64 = 6;
+}
+
+/// Renames the columns in a formula saved before [BightFileV5], see [rename_old_columns]
+fn rename_columns(source: &Arc<str>) -> Arc<str> {
+    if !source.starts_with('=') {
+        return source.clone();
+    }
+    match rename_old_columns(source) {
+        Cow::Borrowed(_) => source.clone(),
+        Cow::Owned(renamed) => renamed.into(),
+    }
+}
+
+impl From<BightFileV4> for BightFileV5 {
+    fn from(value: BightFileV4) -> Self {
+        let mut history = value.history;
+        if let Some(history) = history.as_mut() {
+            history.map_sources(rename_columns);
+        }
+        Self {
+            source: value
+                .source
+                .iter()
+                .map(|(pos, source)| (*pos, rename_columns(source)))
+                .collect(),
+            history,
+            layout: value.layout,
+            number_mode: value.number_mode,
+        }
+    }
+}
+
+/// The latest version of the file format
+pub type BightFile = BightFileV5;
+
 #[derive(Debug, thiserror::Error)]
 pub enum FileLoadError {
     #[error(transparent)]
@@ -55,11 +178,11 @@ pub enum FileLoadError {
     UnsupportedVersion(u64),
 }
 
-pub fn load(path: &Path) -> Result<SourceTable, FileLoadError> {
+pub fn load(path: &Path) -> Result<BightFile, FileLoadError> {
     let bytes = std::fs::read(path)?;
 
     if bytes.is_empty() {
-        return Ok(SourceTable::new());
+        return Ok(BightFile::default());
     }
 
     if bytes.len() < PADDED_HEADER_SIZE {
@@ -77,7 +200,24 @@ pub fn load(path: &Path) -> Result<SourceTable, FileLoadError> {
         BightFileV1::VERSION => {
             let archived = access::<ArchivedBightFileV1, rancor::Error>(data_bytes)?;
             let data = deserialize::<BightFileV1, rancor::Error>(archived)?;
-            Ok(data.source)
+            Ok(BightFileV4::from(BightFileV3::from(BightFileV2::from(data))).into())
+        }
+        BightFileV2::VERSION => {
+            let archived = access::<ArchivedBightFileV2, rancor::Error>(data_bytes)?;
+            let data = deserialize::<BightFileV2, rancor::Error>(archived)?;
+            Ok(BightFileV4::from(BightFileV3::from(data)).into())
+        }
+        BightFileV3::VERSION => {
+            let archived = access::<ArchivedBightFileV3, rancor::Error>(data_bytes)?;
+            Ok(BightFileV4::from(deserialize::<BightFileV3, rancor::Error>(archived)?).into())
+        }
+        BightFileV4::VERSION => {
+            let archived = access::<ArchivedBightFileV4, rancor::Error>(data_bytes)?;
+            Ok(deserialize::<BightFileV4, rancor::Error>(archived)?.into())
+        }
+        BightFileV5::VERSION => {
+            let archived = access::<ArchivedBightFileV5, rancor::Error>(data_bytes)?;
+            Ok(deserialize::<BightFileV5, rancor::Error>(archived)?)
         }
         _ => Err(FileLoadError::UnsupportedVersion(version)),
     }
@@ -89,12 +229,113 @@ pub enum FileSaveError {
     IoErrror(#[from] std::io::Error),
 }
 
-pub fn save(path: &Path, table: SourceTable) -> Result<(), std::io::Error> {
-    let header = BightHeaderPadded::new(BightFileV1::VERSION);
+pub fn save(path: &Path, data: &BightFile) -> Result<(), std::io::Error> {
+    let header = BightHeaderPadded::new(BightFile::VERSION);
     let mut bytes = to_bytes::<rancor::Error>(&header).unwrap();
 
-    let data = BightFileV1 { source: table };
-    bytes.extend_from_slice(&to_bytes::<rancor::Error>(&data).unwrap());
+    bytes.extend_from_slice(&to_bytes::<rancor::Error>(data).unwrap());
     std::fs::write(path, bytes)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        editor::history::{Change, History},
+        table::{cell::CellPos, layout::DEFAULT_WIDTH},
+    };
+
+    use super::*;
+
+    #[test]
+    fn history_round_trip() {
+        let mut history = History::default();
+        history.record(Change {
+            pos: (1, 2).into(),
+            before: None,
+            after: Some("=1+1".into()),
+        });
+        let mut source = SourceTable::new();
+        source.insert((1, 2).into(), "=1+1".into());
+
+        let path = std::env::temp_dir().join(format!("bight-test-{}.bight", std::process::id()));
+        let mut layout = Layout::default();
+        layout.set_width(3, 20);
+        let data = BightFile {
+            source,
+            history: Some(history.tree().clone()),
+            layout,
+            number_mode: NumberMode::Decimal,
+        };
+        save(&path, &data).unwrap();
+        let loaded = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.source, data.source);
+        assert_eq!(loaded.layout, data.layout);
+        assert_eq!(loaded.number_mode, NumberMode::Decimal);
+        let mut history = History::with_tree(loaded.history.unwrap());
+        let undone: Vec<_> = history.undo().unwrap().collect();
+        assert_eq!(undone, vec![((1, 2).into(), None)]);
+    }
+
+    #[test]
+    fn old_column_names_are_renamed() {
+        let mut history = History::default();
+        history.record(Change {
+            pos: (0, 0).into(),
+            before: Some("=AB1".into()),
+            after: Some("=BA1 + SUM(A1:AB2)".into()),
+        });
+        let mut source = SourceTable::new();
+        source.insert((0, 0).into(), "=BA1 + SUM(A1:AB2)".into());
+        source.insert((1, 0).into(), "BA1".into());
+        let data = BightFileV4 {
+            source,
+            history: Some(history.tree().clone()),
+            ..Default::default()
+        };
+
+        let path = std::env::temp_dir().join(format!("bight-v4-{}.bight", std::process::id()));
+        let header = BightHeaderPadded::new(BightFileV4::VERSION);
+        let mut bytes = to_bytes::<rancor::Error>(&header).unwrap();
+        bytes.extend_from_slice(&to_bytes::<rancor::Error>(&data).unwrap());
+        std::fs::write(&path, bytes).unwrap();
+        let loaded = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // `BA` was the column after `Z`, which is `AA` now, and `AB` was `B`
+        let source = |x: usize| loaded.source[&CellPos::from((x, 0))].to_string();
+        assert_eq!(source(0), "=AA1 + SUM(A1:B2)");
+        assert_eq!(source(1), "BA1");
+        let mut history = History::with_tree(loaded.history.unwrap());
+        let undone: Vec<_> = history.undo().unwrap().collect();
+        assert_eq!(undone, vec![((0, 0).into(), Some("=B1".into()))]);
+    }
+
+    #[test]
+    fn resized_widths_are_saved() {
+        let path = std::env::temp_dir().join(format!("bight-width-{}.bight", std::process::id()));
+        let mut layout = Layout::default();
+        layout.set_default_width(12);
+        layout.set_width(1, 20);
+        layout.set_width(2, 12);
+        layout.set_width(3, DEFAULT_WIDTH);
+        let data = BightFile {
+            layout,
+            ..Default::default()
+        };
+        save(&path, &data).unwrap();
+        let loaded = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The resized columns keep their widths, even the ones as wide as the default width was,
+        // and the others take the default width of the editor opening the file
+        let mut layout = loaded.layout;
+        layout.set_default_width(15);
+        assert_eq!(layout.width(1), 20);
+        assert_eq!(layout.width(2), 12);
+        assert_eq!(layout.width(3), DEFAULT_WIDTH);
+        assert_eq!(layout.width(0), 15);
+    }
+}
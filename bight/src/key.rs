@@ -1,7 +1,7 @@
 pub mod sequence;
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
-use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers, MediaKeyCode, ModifierKeyCode};
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
 pub struct Key {
@@ -14,46 +14,244 @@ impl Key {
             event: KeyEvent::from(KeyCode::Char(c)),
         }
     }
+    /// Returns the char this key types, if it is a char key without modifiers other than shift
+    pub fn char(&self) -> Option<char> {
+        match self.event.code {
+            KeyCode::Char(c) if (self.event.modifiers - KeyModifiers::SHIFT).is_empty() => Some(c),
+            _ => None,
+        }
+    }
+    pub fn code(&self) -> KeyCode {
+        self.event.code
+    }
+    pub fn modifiers(&self) -> KeyModifiers {
+        self.event.modifiers
+    }
     fn format(&self) -> KeyString {
         use KeyString::{Escape, Plain};
 
         let mods = self.event.modifiers;
         let code = self.event.code;
 
-        let mut plain = true;
         let mut s = String::new();
-
-        mods.iter_names().for_each(|x| {
-            match x.1 {
-                KeyModifiers::SHIFT => match code {
-                    KeyCode::Char(c) if c != '<' => {
-                        return;
-                    }
-                    _ => s += "S-",
-                },
-                KeyModifiers::CONTROL => s += "C-",
-                KeyModifiers::ALT => s += "A-",
-                KeyModifiers::SUPER => todo!(),
-                KeyModifiers::HYPER => todo!(),
-                KeyModifiers::META => s += "M-",
-                _ => unreachable!(),
-            };
-            plain = false;
-        });
+        for (_, modifier) in mods.iter_names() {
+            // The case of a char already shows whether shift was pressed
+            if modifier == KeyModifiers::SHIFT
+                && matches!(code, KeyCode::Char(c) if c.is_uppercase())
+            {
+                continue;
+            }
+            s.push(modifier_char(modifier));
+            s.push('-');
+        }
+        let plain = s.is_empty();
 
         match code {
-            KeyCode::Char(c) => match c {
-                '<' => {
-                    s += "lt";
-                    plain = false;
+            KeyCode::Char(c) if plain && !c.is_whitespace() && !c.is_control() && c != '<' => {
+                return Plain(String::from(c));
+            }
+            KeyCode::Char(c) if c.is_control() || (c.is_whitespace() && c != ' ') => {
+                s += &format!("Char-{}", c as u32);
+            }
+            KeyCode::Char(c) if c != ' ' && c != '<' => s.push(c),
+            KeyCode::F(n) => s += &format!("F{n}"),
+            KeyCode::Media(m) => s += &format!("Media{m:?}"),
+            KeyCode::Modifier(m) => s += &format!("{m:?}"),
+            _ => {
+                let (name, _) = NAMED_KEYS
+                    .iter()
+                    .find(|(_, c)| *c == code)
+                    .expect("Every other key code has a name");
+                s += name;
+            }
+        }
+
+        Escape(s)
+    }
+}
+
+fn modifier_char(modifier: KeyModifiers) -> char {
+    match modifier {
+        KeyModifiers::SHIFT => 'S',
+        KeyModifiers::CONTROL => 'C',
+        KeyModifiers::ALT => 'A',
+        KeyModifiers::SUPER => 'D',
+        KeyModifiers::HYPER => 'H',
+        KeyModifiers::META => 'M',
+        _ => unreachable!("iter_names only yields single modifiers"),
+    }
+}
+
+fn parse_modifier(c: char) -> Option<KeyModifiers> {
+    Some(match c.to_ascii_uppercase() {
+        'S' => KeyModifiers::SHIFT,
+        'C' => KeyModifiers::CONTROL,
+        'A' => KeyModifiers::ALT,
+        'D' => KeyModifiers::SUPER,
+        'H' => KeyModifiers::HYPER,
+        'M' => KeyModifiers::META,
+        _ => return None,
+    })
+}
+
+/// Names of the keys in the `<...>` notation. The first name of a key is used when formatting
+const NAMED_KEYS: &[(&str, KeyCode)] = &[
+    ("BS", KeyCode::Backspace),
+    ("CR", KeyCode::Enter),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Tab", KeyCode::Tab),
+    // Terminals send shift-tab as a separate key, it is normalized to always have the shift
+    // modifier, so it is formatted as `<S-Tab>`
+    ("Tab", KeyCode::BackTab),
+    ("Del", KeyCode::Delete),
+    ("Insert", KeyCode::Insert),
+    ("Nul", KeyCode::Null),
+    ("Esc", KeyCode::Esc),
+    ("CapsLock", KeyCode::CapsLock),
+    ("ScrollLock", KeyCode::ScrollLock),
+    ("NumLock", KeyCode::NumLock),
+    ("PrintScreen", KeyCode::PrintScreen),
+    ("Pause", KeyCode::Pause),
+    ("Menu", KeyCode::Menu),
+    ("KeypadBegin", KeyCode::KeypadBegin),
+    ("Space", KeyCode::Char(' ')),
+    ("lt", KeyCode::Char('<')),
+    // Aliases
+    ("Backspace", KeyCode::Backspace),
+    ("Enter", KeyCode::Enter),
+    ("Return", KeyCode::Enter),
+    ("Delete", KeyCode::Delete),
+    ("Escape", KeyCode::Esc),
+    ("Bslash", KeyCode::Char('\\')),
+    ("Bar", KeyCode::Char('|')),
+];
+
+const MEDIA_KEYS: &[MediaKeyCode] = &[
+    MediaKeyCode::Play,
+    MediaKeyCode::Pause,
+    MediaKeyCode::PlayPause,
+    MediaKeyCode::Reverse,
+    MediaKeyCode::Stop,
+    MediaKeyCode::FastForward,
+    MediaKeyCode::Rewind,
+    MediaKeyCode::TrackNext,
+    MediaKeyCode::TrackPrevious,
+    MediaKeyCode::Record,
+    MediaKeyCode::LowerVolume,
+    MediaKeyCode::RaiseVolume,
+    MediaKeyCode::MuteVolume,
+];
+
+const MODIFIER_KEYS: &[ModifierKeyCode] = &[
+    ModifierKeyCode::LeftShift,
+    ModifierKeyCode::LeftControl,
+    ModifierKeyCode::LeftAlt,
+    ModifierKeyCode::LeftSuper,
+    ModifierKeyCode::LeftHyper,
+    ModifierKeyCode::LeftMeta,
+    ModifierKeyCode::RightShift,
+    ModifierKeyCode::RightControl,
+    ModifierKeyCode::RightAlt,
+    ModifierKeyCode::RightSuper,
+    ModifierKeyCode::RightHyper,
+    ModifierKeyCode::RightMeta,
+    ModifierKeyCode::IsoLevel3Shift,
+    ModifierKeyCode::IsoLevel5Shift,
+];
+
+/// Parses the name of a key inside `<...>` (without modifiers). Named keys are case insensitive
+fn parse_key_name(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c));
+    }
+    if let Some((_, code)) = NAMED_KEYS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+    {
+        return Some(*code);
+    }
+    let lower = name.to_ascii_lowercase();
+    if let Some(n) = lower.strip_prefix('f')
+        && let Ok(n) = n.parse()
+    {
+        return Some(KeyCode::F(n));
+    }
+    if let Some(n) = lower.strip_prefix("char-") {
+        return n.parse().ok().and_then(char::from_u32).map(KeyCode::Char);
+    }
+    if let Some(m) = lower.strip_prefix("media") {
+        return MEDIA_KEYS
+            .iter()
+            .find(|k| format!("{k:?}").eq_ignore_ascii_case(m))
+            .map(|k| KeyCode::Media(*k));
+    }
+    MODIFIER_KEYS
+        .iter()
+        .find(|k| format!("{k:?}").eq_ignore_ascii_case(name))
+        .map(|k| KeyCode::Modifier(*k))
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum KeyParseError {
+    #[error("Empty key")]
+    Empty,
+    #[error("Unknown key: <{0}>")]
+    UnknownKey(String),
+    #[error("Unknown modifier: {0}-")]
+    UnknownModifier(char),
+    #[error("Expected a single key, found: {0}")]
+    NotSingleKey(String),
+}
+
+impl Key {
+    /// Parses the inside of `<...>`: modifiers followed by a key name, like `C-S-Tab`
+    pub fn parse_notation(notation: &str) -> Result<Self, KeyParseError> {
+        if notation.is_empty() {
+            return Err(KeyParseError::Empty);
+        }
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = notation;
+        loop {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next(), chars.as_str()) {
+                (Some(m), Some('-'), name) if !name.is_empty() => {
+                    modifiers |= parse_modifier(m).ok_or(KeyParseError::UnknownModifier(m))?;
+                    rest = name;
                 }
-                _ => s += &String::from(c),
-            },
-            KeyCode::Esc => s += "Esc",
-            _ => todo!("handle other keycodes"),
+                _ => break,
+            }
         }
+        let mut code =
+            parse_key_name(rest).ok_or_else(|| KeyParseError::UnknownKey(notation.to_owned()))?;
+        if code == KeyCode::Tab && modifiers.contains(KeyModifiers::SHIFT) {
+            code = KeyCode::BackTab;
+        }
+        Ok(KeyEvent::new(code, modifiers).into())
+    }
+}
 
-        if plain { Plain(s) } else { Escape(s) }
+impl FromStr for Key {
+    type Err = KeyParseError;
+    /// Parses a single key, either a plain char or a key in the `<...>` notation
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (None, _) => Err(KeyParseError::Empty),
+            (Some(c), None) => Ok(Self::from_char(c)),
+            _ => s
+                .strip_prefix('<')
+                .and_then(|s| s.strip_suffix('>'))
+                .ok_or_else(|| KeyParseError::NotSingleKey(s.to_owned()))
+                .and_then(Self::parse_notation),
+        }
     }
 }
 
@@ -67,7 +265,10 @@ impl Display for Key {
 }
 
 impl From<KeyEvent> for Key {
-    fn from(value: KeyEvent) -> Self {
+    fn from(mut value: KeyEvent) -> Self {
+        if value.code == KeyCode::BackTab {
+            value.modifiers |= KeyModifiers::SHIFT;
+        }
         Self { event: value }
     }
 }
@@ -106,3 +307,57 @@ impl KeyString {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn notation_round_trip() {
+        let keys = [
+            KeyEvent::new(KeyCode::Char('a'), KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT),
+            KeyEvent::new(KeyCode::Char('a'), KeyModifiers::SHIFT),
+            KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL),
+            KeyEvent::new(KeyCode::Char('j'), KeyModifiers::ALT),
+            KeyEvent::new(KeyCode::Char('<'), KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Char(' '), KeyModifiers::CONTROL),
+            KeyEvent::new(KeyCode::Char('\t'), KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::BackTab, KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::F(5), KeyModifiers::SUPER | KeyModifiers::HYPER),
+            KeyEvent::new(KeyCode::Up, KeyModifiers::META),
+            KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Media(MediaKeyCode::Pause), KeyModifiers::NONE),
+            KeyEvent::new(
+                KeyCode::Modifier(ModifierKeyCode::LeftShift),
+                KeyModifiers::SHIFT,
+            ),
+        ];
+        for event in keys {
+            let key = Key::from(event);
+            let notation = key.to_string();
+            assert_eq!(notation.parse::<Key>(), Ok(key), "{notation}");
+        }
+        assert_eq!(Key::from(keys[3]).to_string(), "<C-w>");
+        assert_eq!(Key::from(keys[8]).to_string(), "<S-Tab>");
+        assert_eq!(Key::from(keys[5]).to_string(), "<lt>");
+    }
+
+    #[test]
+    fn notation_errors() {
+        assert_eq!(
+            "<X-a>".parse::<Key>(),
+            Err(KeyParseError::UnknownModifier('X'))
+        );
+        assert_eq!(
+            "<C-Nope>".parse::<Key>(),
+            Err(KeyParseError::UnknownKey("C-Nope".into()))
+        );
+        assert_eq!("".parse::<Key>(), Err(KeyParseError::Empty));
+        assert_eq!(
+            "ab".parse::<Key>(),
+            Err(KeyParseError::NotSingleKey("ab".into()))
+        );
+        assert_eq!("<c-cr>".parse::<Key>(), "<C-CR>".parse::<Key>());
+    }
+}
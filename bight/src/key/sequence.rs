@@ -1,4 +1,4 @@
-use crate::key::Key;
+use crate::key::{Key, KeyParseError};
 
 #[derive(Debug, thiserror::Error, PartialEq, Eq)]
 pub enum SequenceMatchError {
@@ -17,6 +17,10 @@ impl SequenceMatchError {
 pub trait MatchSequence {
     type Output;
     fn try_match(&self, sequence: &[Key]) -> Result<Self::Output, SequenceMatchError>;
+    /// Whether this is a binding of exactly the given sequence (used to unbind it)
+    fn is_bound_to(&self, _sequence: &[Key]) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -39,15 +43,20 @@ impl<T: Clone> MatchSequence for SequenceBinding<T> {
     type Output = T;
     fn try_match(&self, sequence: &[Key]) -> Result<Self::Output, SequenceMatchError> {
         for (idx, expected_key) in self.sequence.iter().enumerate() {
-            let key = sequence.get(idx).ok_or(SequenceMatchError::CanBeContined {
-                hint: format_sequence(&self.sequence[idx..]),
-            })?;
+            let key = sequence
+                .get(idx)
+                .ok_or_else(|| SequenceMatchError::CanBeContined {
+                    hint: format_sequence(&self.sequence[idx..]),
+                })?;
             if key != expected_key {
                 return Err(SequenceMatchError::CannotBeContined);
             }
         }
         Ok(self.item.clone())
     }
+    fn is_bound_to(&self, sequence: &[Key]) -> bool {
+        self.sequence == sequence
+    }
 }
 
 pub fn format_sequence(sequence: &[Key]) -> String {
@@ -56,15 +65,96 @@ pub fn format_sequence(sequence: &[Key]) -> String {
         .fold(String::new(), |s, k| format!("{}{}", s, k))
 }
 
-#[derive(Debug, thiserror::Error)]
-pub enum SequenceParseError {}
+/// The key `<leader>` stands for unless another one is set
+pub const DEFAULT_LEADER: char = '\\';
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum SequenceParseError {
+    #[error("Unclosed '<' at {0}, use <lt> for a literal '<'")]
+    Unclosed(usize),
+    #[error(transparent)]
+    KeyParseError(#[from] KeyParseError),
+}
 
+/// Parses a sequence in vim-like key notation, e.g. `<C-w>j`, `<S-Tab>`, `<leader>w`. `<leader>`
+/// is replaced with [DEFAULT_LEADER]
 pub fn parse_key_sequence(sequence: &str) -> Result<Vec<Key>, SequenceParseError> {
+    parse_key_sequence_with_leader(sequence, &Key::from_char(DEFAULT_LEADER))
+}
+
+/// Same as [parse_key_sequence], but `<leader>` is replaced with the given key
+pub fn parse_key_sequence_with_leader(
+    sequence: &str,
+    leader: &Key,
+) -> Result<Vec<Key>, SequenceParseError> {
     let mut result = Vec::new();
 
-    for c in sequence.chars() {
-        result.push(Key::from_char(c));
+    let mut rest = sequence;
+    while let Some(c) = rest.chars().next() {
+        if c != '<' {
+            result.push(Key::from_char(c));
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let offset = sequence.len() - rest.len();
+        // The key's name can't be empty, so in `<>>` and `<C->>` the first '>' is the key
+        let end = rest
+            .match_indices('>')
+            .map(|(idx, _)| idx)
+            .find(|&idx| has_key_name(&rest[1..idx]))
+            .ok_or(SequenceParseError::Unclosed(offset))?;
+        let notation = &rest[1..end];
+        if notation.eq_ignore_ascii_case("leader") {
+            result.push(leader.clone());
+        } else {
+            result.push(Key::parse_notation(notation)?);
+        }
+        rest = &rest[end + 1..];
     }
 
     Ok(result)
 }
+
+/// Checks that a notation is not empty and isn't just modifiers like `C-`
+fn has_key_name(notation: &str) -> bool {
+    let mut rest = notation;
+    while let [_, b'-', _, ..] = rest.as_bytes() {
+        rest = &rest[2..];
+    }
+    rest == "-" || (!rest.is_empty() && !rest.ends_with('-'))
+}
+
+#[cfg(test)]
+mod test {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    use super::*;
+
+    #[test]
+    fn parse_sequence() {
+        let key = |code, modifiers| Key::from(KeyEvent::new(code, modifiers));
+        let seq = parse_key_sequence("<C-w>j<lt><leader><CR><S-Tab><C->>").unwrap();
+        assert_eq!(
+            seq,
+            vec![
+                key(KeyCode::Char('w'), KeyModifiers::CONTROL),
+                Key::from_char('j'),
+                Key::from_char('<'),
+                Key::from_char(DEFAULT_LEADER),
+                key(KeyCode::Enter, KeyModifiers::NONE),
+                key(KeyCode::BackTab, KeyModifiers::SHIFT),
+                key(KeyCode::Char('>'), KeyModifiers::CONTROL),
+            ]
+        );
+        assert_eq!(format_sequence(&seq), "<C-w>j<lt>\\<CR><S-Tab><C->>");
+
+        assert_eq!(
+            parse_key_sequence("ab<C-w"),
+            Err(SequenceParseError::Unclosed(2))
+        );
+        assert!(matches!(
+            parse_key_sequence("<Nope>"),
+            Err(SequenceParseError::KeyParseError(_))
+        ));
+    }
+}
@@ -1,10 +1,12 @@
 pub mod app;
 pub mod callback;
 pub mod clipboard;
+pub mod config;
 pub mod csv;
 pub mod editor;
 pub mod evaluator;
 pub mod file;
 pub mod key;
+pub mod plugin;
 pub mod table;
 pub mod term;
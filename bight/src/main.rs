@@ -1,63 +1,138 @@
 use std::io::{Write, stdout};
 
 use bight::{
-    app::AppState,
-    callback::{EditorStateCallback, OnKeyEventCallback as CB},
+    app::{AppState, MAX_MAPPED_KEYS},
+    callback::EditorStateCallback,
+    config,
     editor::{
         EditorState,
         bindings::{
             EditorBindings,
             vim_default::{
-                add_clipboard_binding, add_io_bindings, add_mode_bindings, add_move_callbacks,
+                add_cell_edit_bindings, add_clipboard_binding, add_command_line_bindings,
+                add_fill_bindings, add_history_bindings, add_io_bindings, add_layout_bindings,
+                add_line_bindings, add_mode_bindings, add_move_callbacks, add_scroll_bindings,
+                add_visual_bindings,
             },
         },
     },
+    evaluator::BackgroundEvaluation,
     key::Key,
+    plugin,
     table::slice::table::TableSlice,
-    term::view::{DrawRect, editor},
+    term::{
+        key_event_stream,
+        view::{DrawRect, editor},
+    },
 };
 use crossterm::terminal::{self, ClearType};
 use edit::Builder;
+use futures::StreamExt;
 
-fn main() {
+#[tokio::main]
+async fn main() {
     env_logger::init();
 
     let mut editor = EditorState::default();
-    let mut app = AppState { run: true };
+    let mut app = AppState::new();
 
     let mut bindings = EditorBindings::default();
 
     add_io_bindings(&mut bindings);
+    add_command_line_bindings(&mut bindings);
+    add_cell_edit_bindings(&mut bindings);
+    add_visual_bindings(&mut bindings);
+    add_history_bindings(&mut bindings);
     add_clipboard_binding(&mut bindings);
     add_value_callbacks(&mut bindings);
     add_move_callbacks(&mut bindings);
+    add_scroll_bindings(&mut bindings);
+    add_layout_bindings(&mut bindings);
+    add_line_bindings(&mut bindings);
+    add_fill_bindings(&mut bindings);
     add_mode_bindings(&mut bindings);
 
+    if let Some(path) = config::config_path()
+        && let Err(e) = config::load(&path, &mut bindings, &mut editor)
+    {
+        editor.message = Some(format!("Error in config: {}", plugin::error_message(&e)));
+    }
+
+    if let Some(path) = std::env::args_os().nth(1)
+        && let Err(e) = editor.open_file(path)
+    {
+        editor.message = Some(e.to_string());
+    }
+
     let mut sequence = Vec::new();
     let mut stdout = stdout();
 
     crossterm::execute!(stdout, crossterm::terminal::EnterAlternateScreen).unwrap();
     crossterm::terminal::enable_raw_mode().unwrap();
 
-    draw(&editor, &sequence);
+    let mut keys = Box::pin(key_event_stream());
+    let mut evaluation: Option<BackgroundEvaluation> = None;
+    let mut mapped_keys = 0;
     while app.run {
-        let event = crossterm::event::read().expect("idk what error can occur here");
-        let Ok(key) = event.try_into() else {
-            continue;
-        };
-        sequence.push(key);
-        if let Some(cb) = bindings.handle_sequence(&mut sequence, editor.mode) {
-            match cb {
-                CB::EditorStateChanage(cb) => (cb.0)(&mut editor),
-                CB::AppStateChange(cb) => (cb.0)(&mut app),
+        let key = if let Some(key) = app.input.pop_front() {
+            mapped_keys += 1;
+            if mapped_keys > MAX_MAPPED_KEYS {
+                app.input.clear();
+                sequence.clear();
+                editor.message = Some(String::from("Recursive mapping"));
+                continue;
             }
+            key
+        } else {
+            mapped_keys = 0;
 
-            editor.table.evaluate();
-        }
+            // Values calculated from the source that was edited since are dropped, and the
+            // evaluation is restarted with the new source
+            if evaluation
+                .as_ref()
+                .is_some_and(|ev| ev.is_outdated(&editor.table))
+                && let Some(ev) = evaluation.take()
+            {
+                ev.cancel();
+            }
+            if evaluation.is_none() && !editor.table.is_evaluated() {
+                evaluation = Some(BackgroundEvaluation::spawn(editor.table.evaluation()));
+            }
+            draw(&mut editor, &sequence);
 
-        draw(&editor, &sequence);
+            let evaluated = async {
+                match evaluation.as_mut() {
+                    Some(ev) => ev.await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                key = keys.next() => {
+                    let Some(key) = key else {
+                        break;
+                    };
+                    key
+                }
+                evaluated = evaluated => {
+                    editor.table.apply(evaluated);
+                    evaluation = None;
+                    continue;
+                }
+            }
+        };
+        sequence.push(key.clone());
+        if let Some((cb, count)) = bindings.handle_counted_sequence(&mut sequence, editor.mode) {
+            editor.message = None;
+            editor.count = count;
+            cb.call(&mut app, &mut editor);
+        } else if editor.mode.is_text() && sequence.is_empty() {
+            editor.handle_text_key(&key);
+        }
     }
 
+    if let Some(ev) = evaluation {
+        ev.cancel();
+    }
     terminal::disable_raw_mode().unwrap();
     crossterm::execute!(
         stdout,
@@ -67,10 +142,11 @@ fn main() {
     .unwrap();
 }
 
-fn draw(editor: &EditorState, sequence: &[Key]) {
+fn draw(editor: &mut EditorState, sequence: &[Key]) {
     let mut stdout = stdout();
-    let data = TableSlice::new(((0, 0), (50, 50)), &editor.table);
     let rect = DrawRect::full_term();
+    editor::update_viewport(editor, rect);
+    let data = TableSlice::new(editor.viewport(), &editor.table);
     editor::draw(&mut stdout, rect, editor, sequence, data);
     stdout.flush().unwrap();
 }
@@ -86,8 +162,10 @@ fn add_value_callbacks(editor: &mut EditorBindings) {
                 let v: &str = if let Some(v) = v { v } else { "" };
                 let mut builder = Builder::new();
                 builder.suffix(".bcell");
-                let new_source = edit::edit_with_builder(v, &builder).unwrap();
-                state.table.set_source(pos, Some(new_source));
+                match edit::edit_with_builder(v, &builder) {
+                    Ok(new_source) => state.set_source(pos, Some(new_source)),
+                    Err(e) => state.message = Some(e.to_string()),
+                }
             }),
         )
         .unwrap();
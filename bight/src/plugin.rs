@@ -0,0 +1,191 @@
+use std::cell::RefCell;
+
+use mlua::{Function, Lua};
+
+use crate::{
+    app::AppState,
+    callback::FullStateCallback,
+    editor::{EditorState, command},
+    table::Table,
+};
+
+/// A Lua function that is called with the editor API as its first argument:
+///
+/// - `ed.get_cursor()` returns the column and the row of the cursor (starting from 0)
+/// - `ed.set_cursor(x, y)` moves the cursor
+/// - `ed.get_source(x, y)` and `ed.get_value(x, y)` return the source and the value of a cell.
+///   The value is `nil` while the cell is pending evaluation, which is after the call if it was
+///   just changed unless `ed.evaluate()` is called
+/// - `ed.evaluate()` evaluates the changed cells, blocking until it's done
+/// - `ed.set_source(x, y, source)` sets the source of a cell, `nil` clears it
+/// - `ed.get_selection()` returns the top-left and the bottom-right corners of the selection
+///   (`x1, y1, x2, y2`), which is the cursor outside of the visual modes
+/// - `ed.command(line)` runs an ex command
+/// - `ed.message(text)` shows a message in the status line
+///
+/// The API is only valid during the call
+#[derive(Debug, Clone)]
+pub struct LuaCallback {
+    lua: Lua,
+    function: Function,
+}
+
+impl LuaCallback {
+    pub fn new(lua: Lua, function: Function) -> Self {
+        Self { lua, function }
+    }
+
+    /// Calls the function with the editor API and an optional argument. All the changes made to
+    /// the table are undone as a single step
+    pub fn call(
+        &self,
+        app: &mut AppState,
+        editor: &mut EditorState,
+        arg: Option<&str>,
+    ) -> mlua::Result<()> {
+        editor.history.begin();
+        let state = RefCell::new((app, &mut *editor));
+        let res = self.lua.scope(|scope| {
+            let api = self.lua.create_table()?;
+            api.set(
+                "get_cursor",
+                scope.create_function(|_, ()| {
+                    let cursor = state.borrow().1.cursor;
+                    Ok((cursor.x, cursor.y))
+                })?,
+            )?;
+            api.set(
+                "set_cursor",
+                scope.create_function(|_, (x, y): (usize, usize)| {
+                    state.borrow_mut().1.cursor = (x, y).into();
+                    Ok(())
+                })?,
+            )?;
+            api.set(
+                "get_source",
+                scope.create_function(|_, (x, y): (usize, usize)| {
+                    let state = state.borrow();
+                    Ok(state.1.table.get_source((x, y)).map(|s| s.to_string()))
+                })?,
+            )?;
+            api.set(
+                "set_source",
+                scope.create_function(|_, (x, y, source): (usize, usize, Option<String>)| {
+                    state.borrow_mut().1.set_source((x, y), source);
+                    Ok(())
+                })?,
+            )?;
+            api.set(
+                "get_value",
+                scope.create_function(|_, (x, y): (usize, usize)| {
+                    let state = state.borrow();
+                    Ok(state.1.table.get((x, y).into()).cloned())
+                })?,
+            )?;
+            api.set(
+                "evaluate",
+                scope.create_function(|_, ()| {
+                    state.borrow_mut().1.table.evaluate();
+                    Ok(())
+                })?,
+            )?;
+            api.set(
+                "get_selection",
+                scope.create_function(|_, ()| {
+                    let sel = state.borrow().1.selection();
+                    Ok((sel.start.x, sel.start.y, sel.end.x - 1, sel.end.y - 1))
+                })?,
+            )?;
+            api.set(
+                "command",
+                scope.create_function(|_, line: String| {
+                    let (app, editor) = &mut *state.borrow_mut();
+                    command::run(&line, app, editor).map_err(mlua::Error::external)
+                })?,
+            )?;
+            api.set(
+                "message",
+                scope.create_function(|_, text: String| {
+                    state.borrow_mut().1.message = Some(text);
+                    Ok(())
+                })?,
+            )?;
+
+            self.function.call::<()>((api, arg))
+        });
+        editor.history.commit();
+        res
+    }
+
+    /// Makes a callback for a binding. Errors are shown in the status line
+    pub fn into_callback(self) -> FullStateCallback {
+        FullStateCallback::new(move |app, editor| {
+            if let Err(e) = self.call(app, editor, None) {
+                editor.message = Some(error_message(&e));
+            }
+        })
+    }
+}
+
+/// Returns the first line of an error, Lua errors may contain a traceback that doesn't fit in the
+/// status line
+pub fn error_message(error: &impl ToString) -> String {
+    let error = error.to_string();
+    error.lines().next().unwrap_or_default().to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config,
+        editor::{bindings::EditorBindings, mode::Mode},
+        key::sequence::parse_key_sequence,
+    };
+
+    use super::*;
+
+    #[test]
+    fn lua_binding() {
+        let mut bindings = EditorBindings::default();
+        let mut editor = EditorState::default();
+        let plugin = r#"
+            bight.map("n", "D", function(ed)
+                local x, y = ed.get_cursor()
+                ed.set_source(x, y, "2024-01-01")
+                ed.set_source(x, y + 1, "=" .. (ed.get_value(x, y + 2) or 0) .. "+1")
+                ed.set_cursor(x, y + 1)
+                local pending = ed.get_value(x, y + 1)
+                ed.evaluate()
+                ed.message(tostring(pending) .. " then " .. tostring(ed.get_value(x, y + 1)))
+            end)
+            bight.command("Fail", function(ed, arg) error("failed with " .. arg) end)
+        "#;
+        config::run(plugin, "test", &mut bindings, &mut editor).unwrap();
+        editor.set_source((0, 2), Some("41"));
+        editor.table.evaluate();
+
+        let mut app = AppState::new();
+        let mut sequence = parse_key_sequence("D").unwrap();
+        let cb = bindings
+            .handle_sequence(&mut sequence, Mode::Normal)
+            .unwrap();
+        cb.call(&mut app, &mut editor);
+
+        assert_eq!(
+            editor.table.get_source((0, 0)).unwrap().as_ref(),
+            "2024-01-01"
+        );
+        assert_eq!(editor.table.get_source((0, 1)).unwrap().as_ref(), "=41+1");
+        assert_eq!(editor.cursor, (0, 1).into());
+        // The values of the changed cells are only evaluated during the call by `ed.evaluate()`
+        assert_eq!(editor.message.as_deref(), Some("nil then 42"));
+        assert!(editor.table.is_evaluated());
+
+        editor.undo();
+        assert_eq!(editor.table.get_source((0, 0)), None);
+        assert_eq!(editor.table.get_source((0, 1)), None);
+
+        let err = command::run("Fail here", &mut app, &mut editor).unwrap_err();
+        assert!(error_message(&err).contains("failed with here"));
+    }
+}
@@ -1,9 +1,9 @@
 function POSX()
-	x, _ = POS()
+	local x, _ = POS()
 	return x
 end
 
 function POSY()
-	_, y = POS()
+	local _, y = POS()
 	return y
 end
@@ -0,0 +1,23 @@
+-- Methods of ranges that call Lua functions
+
+local methods = {}
+
+function methods.map(range, f)
+	local results = { n = #range }
+	for i, value in range:values() do
+		results[i] = f(value, i)
+	end
+	return results
+end
+
+function methods.filter(range, f)
+	local results = {}
+	for _, value in range:values() do
+		if value ~= nil and f(value) then
+			results[#results + 1] = value
+		end
+	end
+	return results
+end
+
+return methods
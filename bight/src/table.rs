@@ -1,4 +1,5 @@
 pub mod cell;
+pub mod layout;
 pub mod slice;
 
 use cell::{Cell, CellPos};
@@ -8,6 +9,72 @@ use hashbrown::HashMap;
 
 pub type HashTable<T> = HashMap<CellPos, T>;
 
+/// Whether lines of cells are columns or rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    Column,
+    Row,
+}
+
+/// Columns or rows inserted into or deleted from a table, moving the ones after them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineChange {
+    Insert { axis: Axis, at: usize, count: usize },
+    Delete { axis: Axis, at: usize, count: usize },
+}
+
+impl LineChange {
+    pub fn axis(self) -> Axis {
+        match self {
+            Self::Insert { axis, .. } | Self::Delete { axis, .. } => axis,
+        }
+    }
+
+    /// Returns where the line `idx` along `axis` moves, or None if it is deleted
+    pub fn move_line(self, axis: Axis, idx: usize) -> Option<usize> {
+        match self {
+            _ if axis != self.axis() => Some(idx),
+            Self::Insert { at, count, .. } if idx >= at => idx.checked_add(count),
+            Self::Delete { at, count, .. } if idx >= at => (idx - at >= count).then(|| idx - count),
+            _ => Some(idx),
+        }
+    }
+
+    /// Returns where a cell moves, or None if it is deleted
+    pub fn move_pos(self, pos: CellPos) -> Option<CellPos> {
+        let x = self.move_line(Axis::Column, pos.x)?;
+        let y = self.move_line(Axis::Row, pos.y)?;
+        Some((x, y).into())
+    }
+
+    /// Returns where the lines from `start` to `end` (inclusive, or to the end of the table if
+    /// `end` is None) along `axis` move. The range shrinks if the lines at its edges are deleted
+    /// and grows if lines are inserted inside it. Returns None if all of its lines are deleted
+    pub fn move_range(
+        self,
+        axis: Axis,
+        start: usize,
+        end: Option<usize>,
+    ) -> Option<(usize, Option<usize>)> {
+        let Self::Delete { at, .. } = self else {
+            let end = match end {
+                Some(end) => Some(self.move_line(axis, end)?),
+                None => None,
+            };
+            return Some((self.move_line(axis, start)?, end));
+        };
+        let start = self.move_line(axis, start).unwrap_or(at);
+        let end = match end {
+            Some(end) => Some(match self.move_line(axis, end) {
+                Some(end) => end,
+                None => at.checked_sub(1)?,
+            }),
+            None => None,
+        };
+        end.is_none_or(|end| start <= end).then_some((start, end))
+    }
+}
+
 pub trait Table {
     type Item;
     fn get(&self, pos: CellPos) -> Option<&Self::Item>;
@@ -18,6 +85,13 @@ pub trait TableMut: Table {
     fn set(&mut self, pos: CellPos, item: Option<Self::Item>);
 }
 
+impl<T> Table for HashTable<T> {
+    type Item = T;
+    fn get(&self, pos: CellPos) -> Option<&Self::Item> {
+        HashMap::get(self, &pos)
+    }
+}
+
 #[derive(Debug)]
 pub struct DataTable<I> {
     data: Vec<Vec<Cell<I>>>,
@@ -33,71 +33,89 @@ impl From<(usize, usize)> for CellPos {
 pub enum CellPosParseError {
     #[error("CellPos str contained an invalid digit")]
     InvalidDidit,
+    #[error("CellPos str had no column letters")]
+    MissingColumn,
+    #[error("CellPos str had a column or a row too large to fit")]
+    TooLarge,
 }
 
-const LETTER_BASE: u32 = 26;
+const LETTER_BASE: usize = 26;
 impl FromStr for CellPos {
     type Err = CellPosParseError;
+    /// Parses a position like `A1`. Absolute positions (`$A$1`, `$A1`, `A$1`) are accepted too
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = &absolute_to_relative(s);
         let letters = s
-            .chars()
-            .take_while(|c| c.is_ascii_alphabetic())
-            .map(|c| c.to_ascii_uppercase());
-
-        let mut x = 0usize;
-        for l in letters {
-            x *= LETTER_BASE as usize;
-            x += l
-                .to_digit(LETTER_BASE + 10)
-                .expect("Only letters can be in letters") as usize
-                - 10;
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(s.len());
+        let (column, row) = s.split_at(letters);
+        if !row.chars().all(|c| c.is_ascii_digit()) {
+            return Err(CellPosParseError::InvalidDidit);
         }
-
-        let numbers = s
-            .chars()
-            .skip_while(|c| c.is_ascii_alphabetic())
-            .take_while(|c| c.is_ascii_digit());
+        let x = Self::column_from_name(column)?;
 
         let mut y = 0usize;
-        for n in numbers {
-            y *= 10;
-            y += n.to_digit(10).expect("Only digits can be in numbers") as usize;
+        for n in row.bytes() {
+            y = y
+                .checked_mul(10)
+                .and_then(|y| y.checked_add((n - b'0') as usize))
+                .ok_or(CellPosParseError::TooLarge)?;
         }
+        Ok((x, y).into())
+    }
+}
 
-        let left = s
-            .chars()
-            .skip_while(|c| c.is_ascii_alphabetic())
-            .skip_while(|c| c.is_ascii_digit());
-
-        if left.count() > 0 {
-            Err(CellPosParseError::InvalidDidit)
-        } else {
-            Ok((x, y).into())
+/// Removes the `$` before the column and the row of a position
+fn absolute_to_relative(s: &str) -> std::borrow::Cow<'_, str> {
+    let s = s.strip_prefix('$').unwrap_or(s);
+    match s.find('$') {
+        Some(idx) if idx > 0 && s[..idx].chars().all(|c| c.is_ascii_alphabetic()) => {
+            format!("{}{}", &s[..idx], &s[idx + 1..]).into()
         }
+        _ => s.into(),
     }
 }
 
-impl Display for CellPos {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut x = self.x;
-        if x == 0 {
-            write!(f, "A")?;
-        }
+impl CellPos {
+    /// Returns the letters naming the column `x`: `A` to `Z`, then `AA` to `ZZ`, `AAA` and so on
+    pub fn column_name(x: usize) -> String {
+        let mut x = x;
         let mut chars = Vec::new();
-        while x > 0 {
-            let digit = x % LETTER_BASE as usize;
-            let c = char::from_digit(digit as u32 + 10, LETTER_BASE + 10)
-                .expect("digit is always less that LETTER_BASE")
-                .to_ascii_uppercase();
-            chars.push(c);
-            x /= LETTER_BASE as usize;
+        loop {
+            chars.push((b'A' + (x % LETTER_BASE) as u8) as char);
+            if x < LETTER_BASE {
+                break;
+            }
+            x = x / LETTER_BASE - 1;
         }
-        for c in chars.into_iter().rev() {
-            write!(f, "{c}")?;
+        chars.into_iter().rev().collect()
+    }
+
+    /// Returns the column named by the letters, the inverse of [column_name](Self::column_name).
+    /// Lowercase letters are accepted
+    pub fn column_from_name(name: &str) -> Result<usize, CellPosParseError> {
+        let mut letters = name.bytes().map(|c| {
+            c.is_ascii_alphabetic()
+                .then(|| (c.to_ascii_uppercase() - b'A') as usize)
+                .ok_or(CellPosParseError::InvalidDidit)
+        });
+        let mut x = letters.next().ok_or(CellPosParseError::MissingColumn)??;
+        for digit in letters {
+            let digit = digit?;
+            // Every name one letter shorter comes first, hence the + 1
+            x = x
+                .checked_add(1)
+                .and_then(|x| x.checked_mul(LETTER_BASE))
+                .and_then(|x| x.checked_add(digit))
+                .ok_or(CellPosParseError::TooLarge)?;
         }
-        write!(f, "{}", self.y)?;
+        Ok(x)
+    }
+}
 
-        Ok(())
+impl Display for CellPos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", Self::column_name(self.x), self.y)
     }
 }
 
@@ -151,3 +169,76 @@ impl<I: Default> CellContent<I> {
         Self::Value(I::default())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn column_names() {
+        let names = [
+            (0, "A"),
+            (25, "Z"),
+            (26, "AA"),
+            (51, "AZ"),
+            (52, "BA"),
+            (701, "ZZ"),
+            (702, "AAA"),
+            (16383, "XFD"),
+        ];
+        for (x, name) in names {
+            assert_eq!(CellPos::column_name(x), name);
+            assert_eq!(CellPos::column_from_name(name).unwrap(), x);
+        }
+        assert_eq!(
+            "xfd1048576".parse::<CellPos>().unwrap(),
+            (16383, 1048576).into()
+        );
+        assert_eq!("$AA$10".parse::<CellPos>().unwrap(), (26, 10).into());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Too slow
+    fn column_names_round_trip() {
+        let mut previous = String::new();
+        for x in 0..2_000_000 {
+            let name = CellPos::column_name(x);
+            // Names are ordered by length, then alphabetically
+            assert!((previous.len(), &previous) < (name.len(), &name), "{name}");
+            assert_eq!(CellPos::column_from_name(&name).unwrap(), x);
+            let pos = CellPos { x, y: x * 7 };
+            assert_eq!(pos.to_string().parse::<CellPos>().unwrap(), pos);
+            previous = name;
+        }
+    }
+
+    #[test]
+    fn huge_positions() {
+        for x in [
+            usize::MAX,
+            usize::MAX - 1,
+            usize::MAX / 26,
+            usize::MAX / 26 - 1,
+        ] {
+            let name = CellPos::column_name(x);
+            assert_eq!(CellPos::column_from_name(&name).unwrap(), x);
+        }
+        let last = CellPos::column_name(usize::MAX);
+        assert!(matches!(
+            CellPos::column_from_name(&format!("{last}A")),
+            Err(CellPosParseError::TooLarge)
+        ));
+        assert!(matches!(
+            format!("A{}0", usize::MAX).parse::<CellPos>(),
+            Err(CellPosParseError::TooLarge)
+        ));
+        assert!(matches!(
+            "12".parse::<CellPos>(),
+            Err(CellPosParseError::MissingColumn)
+        ));
+        assert!(matches!(
+            "A1B".parse::<CellPos>(),
+            Err(CellPosParseError::InvalidDidit)
+        ));
+    }
+}
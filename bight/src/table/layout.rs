@@ -0,0 +1,184 @@
+use hashbrown::HashMap;
+use rkyv::{Archive, Deserialize, Serialize, with::Skip};
+
+use crate::table::{Axis, LineChange};
+
+/// Width of a column that wasn't resized if no other default was set, in characters (not
+/// including the border)
+pub const DEFAULT_WIDTH: usize = 9;
+/// Height of a row that wasn't resized, in lines (not including the separator)
+pub const DEFAULT_HEIGHT: usize = 1;
+
+/// Sizes of the columns and rows of a table. The widths of all the resized columns and the
+/// heights of the rows that differ from the default are stored
+#[derive(Debug, Clone, Default, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct Layout {
+    widths: HashMap<usize, usize>,
+    heights: HashMap<usize, usize>,
+    /// Set by the editor from its `colwidth` option, which isn't a property of the file, so it
+    /// isn't saved
+    #[rkyv(with = Skip)]
+    default_width: Option<usize>,
+}
+
+impl Layout {
+    pub fn width(&self, x: usize) -> usize {
+        self.widths
+            .get(&x)
+            .copied()
+            .unwrap_or_else(|| self.default_width())
+    }
+
+    pub fn default_width(&self) -> usize {
+        self.default_width.unwrap_or(DEFAULT_WIDTH)
+    }
+
+    /// Sets the width of the columns that weren't resized
+    pub fn set_default_width(&mut self, width: usize) {
+        self.default_width = Some(width.max(1));
+    }
+
+    pub fn height(&self, y: usize) -> usize {
+        self.heights.get(&y).copied().unwrap_or(DEFAULT_HEIGHT)
+    }
+
+    /// Sets the width of a column. Columns are at least 1 character wide. The width is kept even
+    /// if it is the default one, so that the column doesn't change with the default width
+    pub fn set_width(&mut self, x: usize, width: usize) {
+        self.widths.insert(x, width.max(1));
+    }
+
+    /// Makes a column take the default width again
+    pub fn reset_width(&mut self, x: usize) {
+        self.widths.remove(&x);
+    }
+
+    /// Sets the height of a row. Rows are at least 1 line high
+    pub fn set_height(&mut self, y: usize, height: usize) {
+        set_size(&mut self.heights, y, height, DEFAULT_HEIGHT);
+    }
+
+    /// Moves the sizes of the columns or the rows with the lines inserted or deleted. The inserted
+    /// lines get the default size
+    pub fn change_lines(&mut self, change: LineChange) {
+        let sizes = match change.axis() {
+            Axis::Column => &mut self.widths,
+            Axis::Row => &mut self.heights,
+        };
+        *sizes = sizes
+            .drain()
+            .filter_map(|(idx, size)| Some((change.move_line(change.axis(), idx)?, size)))
+            .collect();
+    }
+
+    /// Returns how many columns starting from `start` fit in `space` characters, counting the
+    /// border to the left of each column. At least one column is always returned
+    pub fn fit_columns(&self, start: usize, space: usize) -> usize {
+        fit(|x| self.width(x) + 1, start, space)
+    }
+
+    /// Returns how many rows starting from `start` fit in `space` lines, counting the separator
+    /// above each row. At least one row is always returned
+    pub fn fit_rows(&self, start: usize, space: usize) -> usize {
+        fit(|y| self.height(y) + 1, start, space)
+    }
+
+    /// Returns the first column of the widest range ending with `last` that fits in `space`
+    /// characters
+    pub fn first_column_fitting(&self, last: usize, space: usize) -> usize {
+        fit_back(|x| self.width(x) + 1, last, space)
+    }
+
+    /// Returns the first row of the highest range ending with `last` that fits in `space` lines
+    pub fn first_row_fitting(&self, last: usize, space: usize) -> usize {
+        fit_back(|y| self.height(y) + 1, last, space)
+    }
+}
+
+fn set_size(sizes: &mut HashMap<usize, usize>, idx: usize, size: usize, default: usize) {
+    let size = size.max(1);
+    if size == default {
+        sizes.remove(&idx);
+    } else {
+        sizes.insert(idx, size);
+    }
+}
+
+fn fit(size: impl Fn(usize) -> usize, start: usize, space: usize) -> usize {
+    let mut used = size(start);
+    let mut count = 1;
+    while used + size(start + count) <= space {
+        used += size(start + count);
+        count += 1;
+    }
+    count
+}
+
+fn fit_back(size: impl Fn(usize) -> usize, last: usize, space: usize) -> usize {
+    let mut used = size(last);
+    let mut first = last;
+    while first > 0 && used + size(first - 1) <= space {
+        first -= 1;
+        used += size(first);
+    }
+    first
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fit_sizes() {
+        let mut layout = Layout::default();
+        layout.set_width(1, 19);
+        layout.set_width(2, 12);
+        layout.set_default_width(12);
+        layout.set_width(3, 12);
+        layout.reset_width(2);
+        layout.set_default_width(DEFAULT_WIDTH);
+        assert_eq!((layout.width(2), layout.width(3)), (DEFAULT_WIDTH, 12));
+        layout.reset_width(3);
+
+        assert_eq!(layout.fit_columns(0, 40), 3);
+        assert_eq!(layout.fit_columns(1, 5), 1);
+        assert_eq!(layout.first_column_fitting(2, 30), 1);
+        assert_eq!(layout.first_column_fitting(2, 100), 0);
+
+        layout.set_height(3, 0);
+        assert_eq!(layout.height(3), 1);
+        layout.set_height(3, 3);
+        assert_eq!(layout.fit_rows(0, 9), 3);
+    }
+
+    #[test]
+    fn changed_lines() {
+        let mut layout = Layout::default();
+        layout.set_width(1, 19);
+        layout.set_width(4, 3);
+        layout.set_height(2, 5);
+        layout.change_lines(LineChange::Insert {
+            axis: Axis::Column,
+            at: 2,
+            count: 2,
+        });
+        assert_eq!(
+            (layout.width(1), layout.width(4), layout.width(6)),
+            (19, DEFAULT_WIDTH, 3)
+        );
+        assert_eq!(layout.height(2), 5);
+
+        layout.change_lines(LineChange::Delete {
+            axis: Axis::Column,
+            at: 0,
+            count: 2,
+        });
+        assert_eq!((layout.width(0), layout.width(4)), (DEFAULT_WIDTH, 3));
+        layout.change_lines(LineChange::Delete {
+            axis: Axis::Row,
+            at: 1,
+            count: 1,
+        });
+        assert_eq!((layout.height(1), layout.height(2)), (5, DEFAULT_HEIGHT));
+    }
+}
@@ -52,6 +52,20 @@ impl SlicePos {
     pub fn rows(&self) -> IdxRange {
         0..(self.end.y - self.start.y)
     }
+
+    pub fn width(&self) -> usize {
+        self.end.x - self.start.x
+    }
+
+    pub fn height(&self) -> usize {
+        self.end.y - self.start.y
+    }
+
+    /// Iterates over the absolute positions of all cells in the slice, row by row
+    pub fn positions(&self) -> impl Iterator<Item = CellPos> + use<> {
+        let (start, end) = (self.start, self.end);
+        (start.y..end.y).flat_map(move |y| (start.x..end.x).map(move |x| (x, y).into()))
+    }
 }
 
 impl<A: Into<CellPos>, B: Into<CellPos>> From<(A, B)> for SlicePos {
@@ -71,23 +85,135 @@ impl From<CellPosParseError> for SlicePosParseError {
 
 impl FromStr for SlicePos {
     type Err = SlicePosParseError;
+    /// Parses a closed range like `A1_B3` or `A1:B3`, see [OpenSlicePos]
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut cells = s.split('_');
-        let mut pos1 = CellPos::from_str(cells.next().ok_or(SlicePosParseError)?)?;
-        let mut pos2 = CellPos::from_str(cells.next().ok_or(SlicePosParseError)?)?;
-        if pos1.x > pos2.x {
-            std::mem::swap(&mut pos1.x, &mut pos2.x);
+        let pos = OpenSlicePos::from_str(s)?;
+        match pos.end {
+            (Some(x), Some(y)) => Ok((pos.start, (x, y)).into()),
+            _ => Err(SlicePosParseError),
         }
-        if pos1.y > pos2.y {
-            std::mem::swap(&mut pos1.y, &mut pos2.y);
+    }
+}
+
+/// A range that may reach the end of the table, as spreadsheets write them: whole columns (`A:C`),
+/// whole rows (`3:5`) and ranges from a cell to the end of a column (`A2:B`). Closed ranges are
+/// written as `A1:B3` or `A1_B3`. The cells may be absolute (`$A$1`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpenSlicePos {
+    pub start: CellPos,
+    /// The exclusive end, None where the range reaches the end of the table
+    pub end: (Option<usize>, Option<usize>),
+}
+
+impl OpenSlicePos {
+    pub fn is_open(&self) -> bool {
+        self.end.0.is_none() || self.end.1.is_none()
+    }
+
+    /// Returns the range ending at `extent` (the exclusive end of the table) where it's open
+    pub fn bound(&self, extent: CellPos) -> SlicePos {
+        let x = self.end.0.unwrap_or(extent.x.max(self.start.x));
+        let y = self.end.1.unwrap_or(extent.y.max(self.start.y));
+        SlicePos::new(self.start, (x, y))
+    }
+}
+
+impl From<SlicePos> for OpenSlicePos {
+    fn from(value: SlicePos) -> Self {
+        Self {
+            start: value.start,
+            end: (Some(value.end.x), Some(value.end.y)),
         }
+    }
+}
 
-        pos2.x += 1;
-        pos2.y += 1;
+/// A corner of a range: a cell, a column or a row
+fn parse_corner(s: &str) -> Result<(Option<usize>, Option<usize>), SlicePosParseError> {
+    let s = s.strip_prefix('$').unwrap_or(s);
+    let letters = s
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    let (column, row) = s.split_at(letters);
+    let row = row
+        .strip_prefix('$')
+        .filter(|rest| !column.is_empty() && !rest.is_empty())
+        .unwrap_or(row);
+    if !row.chars().all(|c| c.is_ascii_digit()) {
+        return Err(SlicePosParseError);
+    }
+    match (column.is_empty(), row.is_empty()) {
+        (true, true) => Err(SlicePosParseError),
+        (false, false) => {
+            let pos = CellPos::from_str(s)?;
+            Ok((Some(pos.x), Some(pos.y)))
+        }
+        (false, true) => Ok((Some(CellPos::from_str(column)?.x), None)),
+        (true, false) => Ok((None, Some(row.parse().map_err(|_| SlicePosParseError)?))),
+    }
+}
+
+impl FromStr for OpenSlicePos {
+    type Err = SlicePosParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((start, end)) = s.split_once('_') {
+            let start = CellPos::from_str(start)?;
+            let end = CellPos::from_str(end)?;
+            return Ok(SlicePos::new(start, (end.x + 1, end.y + 1)).into());
+        }
+        let (start, end) = s.split_once(':').ok_or(SlicePosParseError)?;
+        let (x1, y1) = parse_corner(start)?;
+        let (x2, y2) = parse_corner(end)?;
+        let (x, end_x) = match (x1, x2) {
+            (Some(a), Some(b)) => (a.min(b), Some(a.max(b) + 1)),
+            (None, None) => (0, None),
+            _ => return Err(SlicePosParseError),
+        };
+        let (y, end_y) = match (y1, y2) {
+            (Some(a), Some(b)) => (a.min(b), Some(a.max(b) + 1)),
+            (Some(a), None) if x1.is_some() => (a, None),
+            (None, None) => (0, None),
+            _ => return Err(SlicePosParseError),
+        };
+        Ok(Self {
+            start: (x, y).into(),
+            end: (end_x, end_y),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn closed(s: &str) -> Option<SlicePos> {
+        s.parse().ok()
+    }
+
+    fn open(s: &str) -> Option<OpenSlicePos> {
+        s.parse().ok()
+    }
 
-        if cells.next().is_some() {
-            return Err(SlicePosParseError);
+    #[test]
+    fn references() {
+        let a1_b3 = Some(SlicePos::new((0, 1), (2, 4)));
+        assert_eq!(closed("A1_B3"), a1_b3);
+        assert_eq!(closed("A1:B3"), a1_b3);
+        assert_eq!(closed("$B$3:A1"), a1_b3);
+        assert_eq!(closed("A$1:$B3"), a1_b3);
+        assert_eq!(closed("A:B"), None);
+        assert_eq!(closed("A1"), None);
+        assert_eq!(closed("A1:B3:C4"), None);
+
+        let extent = CellPos::from((5, 10));
+        let bound = |s: &str| open(s).map(|pos| pos.bound(extent));
+        assert_eq!(bound("A:B"), Some(SlicePos::new((0, 0), (2, 10))));
+        assert_eq!(bound("$C:$C"), Some(SlicePos::new((2, 0), (3, 10))));
+        assert_eq!(bound("3:4"), Some(SlicePos::new((0, 3), (5, 5))));
+        assert_eq!(bound("B2:C"), Some(SlicePos::new((1, 2), (3, 10))));
+        assert_eq!(bound("A20:A"), Some(SlicePos::new((0, 20), (1, 20))));
+        assert!(open("A1:B3").is_some_and(|pos| !pos.is_open()));
+        for invalid in ["A:3", "3:A", "A1:3", "$:$", "A1:B$", "1$:2", "A:B:C"] {
+            assert_eq!(open(invalid), None, "{invalid}");
         }
-        Ok((pos1, pos2).into())
     }
 }
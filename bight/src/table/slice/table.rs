@@ -35,6 +35,14 @@ impl<'a, T: Table> TableSlice<'a, T> {
         }
     }
 
+    pub fn pos(&self) -> SlicePos {
+        self.pos
+    }
+
+    pub fn table(&self) -> &'a T {
+        self.table
+    }
+
     pub fn get(&self, pos: impl Into<CellPos>) -> Option<Option<&'a T::Item>> {
         let pos: CellPos = pos.into();
         Some(self.table.get(self.pos.shift_to_pos(pos)?))
@@ -58,7 +58,7 @@ pub struct TableRowSliceIter<'a, T: Table> {
 impl<'a, T: Table> Iterator for TableRowSliceIter<'a, T> {
     type Item = RowSlice<'a, T>;
     fn next(&mut self) -> Option<Self::Item> {
-        let next_row = self.rows.next()?;
+        let next_row = self.slice.pos.start.y + self.rows.next()?;
         Some(
             TableSlice::new(
                 (
@@ -88,7 +88,7 @@ pub struct TableColSliceIter<'a, T: Table> {
 impl<'a, T: Table> Iterator for TableColSliceIter<'a, T> {
     type Item = ColSlice<'a, T>;
     fn next(&mut self) -> Option<Self::Item> {
-        let next_col = self.cols.next()?;
+        let next_col = self.slice.pos.start.x + self.cols.next()?;
         Some(
             TableSlice::new(
                 (
@@ -1,91 +1,229 @@
 pub mod table {
 
-    use crossterm::{cursor::MoveTo, queue, style::Print};
+    use crossterm::{
+        cursor::MoveTo,
+        queue,
+        style::{Attribute, Print, SetAttribute},
+    };
 
     use crate::{
         evaluator::EvaluatorTable,
-        table::{cell::CellPos, slice::table::TableSlice},
+        table::{
+            cell::CellPos,
+            layout::Layout,
+            slice::{SlicePos, table::TableSlice},
+        },
     };
 
     use super::DrawRect;
 
-    pub fn draw_grid(buf: &mut impl std::io::Write, rect: DrawRect) {
-        enum Line {
-            Sep,
-            Cells,
+    /// Width of the row headers (including their right border) needed to label rows up to
+    /// `last_row`
+    pub fn row_header_width(last_row: usize) -> usize {
+        last_row.to_string().len().max(3) + 1
+    }
+
+    /// Returns the part of the rect where the grid is drawn (to the right of the row headers and
+    /// below the column headers)
+    pub fn grid_rect(rect: DrawRect, pos: SlicePos) -> DrawRect {
+        let header_width = row_header_width(pos.end.y.saturating_sub(1)) as u16;
+        DrawRect {
+            start_x: (rect.start_x + header_width).min(rect.end_x),
+            start_y: (rect.start_y + 1).min(rect.end_y),
+            ..rect
         }
-        let width = (rect.end_x + 1 - rect.start_x) as usize;
-        let mut line = Line::Sep;
-        let sep_line = format!("{:-<width$}", "");
-        let cell = String::from("|         ");
-        let cell_line = format!("{:width$}", format!("{}|", cell.repeat(width / cell.len())));
-        for y in rect.start_y..=rect.end_y {
-            queue!(
-                buf,
-                MoveTo(rect.start_x, y),
-                Print(match line {
-                    Line::Sep => {
-                        line = Line::Cells;
-                        &sep_line
-                    }
-                    Line::Cells => {
-                        line = Line::Sep;
-                        &cell_line
-                    }
-                })
-            )
-            .unwrap();
+    }
+
+    /// Returns the offsets of the first character of each column in the slice from the left
+    /// border of the grid
+    fn column_offsets(layout: &Layout, pos: SlicePos) -> Vec<usize> {
+        offsets(pos.start.x..pos.end.x, |x| layout.width(x))
+    }
+
+    /// Returns the offsets of the first line of each row in the slice from the top border of the
+    /// grid
+    fn row_offsets(layout: &Layout, pos: SlicePos) -> Vec<usize> {
+        offsets(pos.start.y..pos.end.y, |y| layout.height(y))
+    }
+
+    fn offsets(range: std::ops::Range<usize>, size: impl Fn(usize) -> usize) -> Vec<usize> {
+        let mut offset = 1;
+        range
+            .map(|i| {
+                let start = offset;
+                offset += size(i) + 1;
+                start
+            })
+            .collect()
+    }
+
+    /// Cuts or pads a line to the given number of characters
+    fn fit_line(line: &str, width: usize) -> String {
+        format!("{line:<width$}").chars().take(width).collect()
+    }
+
+    /// Draws column names above and row numbers to the left of the grid
+    pub fn draw_headers(
+        buf: &mut impl std::io::Write,
+        rect: DrawRect,
+        pos: SlicePos,
+        layout: &Layout,
+    ) {
+        let width = rect.width() as usize;
+        let header_width = row_header_width(pos.end.y.saturating_sub(1));
+
+        let mut line = format!("{:header_width$}", "");
+        for x in pos.start.x..pos.end.x {
+            let name = CellPos::column_name(x);
+            line += &format!("|{name:^w$}", w = layout.width(x));
+        }
+        queue!(
+            buf,
+            MoveTo(rect.start_x, rect.start_y),
+            Print(fit_line(&line, width))
+        )
+        .unwrap();
+
+        let sep = format!("{:-<header_width$}", "");
+        let blank = format!("{:header_width$}", "");
+        let mut labels = Vec::new();
+        for y in pos.start.y..pos.end.y {
+            labels.push(sep.clone());
+            labels.push(format!("{y:>w$} ", w = header_width - 1));
+            labels.extend(std::iter::repeat_n(blank.clone(), layout.height(y) - 1));
+        }
+        labels.push(sep);
+
+        let labels = labels.iter().chain(std::iter::repeat(&blank));
+        for (posy, label) in (rect.start_y + 1..=rect.end_y).zip(labels) {
+            queue!(buf, MoveTo(rect.start_x, posy), Print(label)).unwrap();
+        }
+    }
+
+    /// Draws the borders of the cells in the slice
+    pub fn draw_grid(
+        buf: &mut impl std::io::Write,
+        rect: DrawRect,
+        pos: SlicePos,
+        layout: &Layout,
+    ) {
+        let width = rect.width() as usize;
+        let cols = column_offsets(layout, pos);
+        let grid_width = match cols.last() {
+            Some(&last) => last + layout.width(pos.end.x - 1) + 1,
+            None => 1,
+        };
+        let sep_line = fit_line(&format!("{:-<grid_width$}", ""), width);
+        let mut cell_line: Vec<char> = format!("{:grid_width$}", "").chars().collect();
+        for offset in cols.iter().chain(std::iter::once(&grid_width)) {
+            cell_line[offset - 1] = '|';
+        }
+        let cell_line = fit_line(&cell_line.into_iter().collect::<String>(), width);
+        let empty_line = fit_line("", width);
+
+        let rows = row_offsets(layout, pos);
+        let grid_height = match rows.last() {
+            Some(&last) => last + layout.height(pos.end.y - 1) + 1,
+            None => 1,
+        };
+        for (i, y) in (rect.start_y..=rect.end_y).enumerate() {
+            let line = if i >= grid_height {
+                &empty_line
+            } else if i + 1 == grid_height || rows.contains(&(i + 1)) {
+                &sep_line
+            } else {
+                &cell_line
+            };
+            queue!(buf, MoveTo(rect.start_x, y), Print(line)).unwrap();
         }
     }
 
+    /// Shown in place of the values that are still being calculated
+    const PENDING: &str = "...";
+
+    /// Draws the values of the cells in the slice. A value is cut to fit the size of its cell
     pub fn draw_table(
         buf: &mut impl std::io::Write,
         rect: DrawRect,
         slice: TableSlice<'_, EvaluatorTable>,
+        layout: &Layout,
+        selection: Option<SlicePos>,
     ) {
-        let empty_cell = String::from("         ");
-        let mut posy = rect.start_y + 1;
-        for row in slice.rows() {
-            let mut posx = rect.start_x + 1;
-            for cell in row {
-                queue!(buf, MoveTo(posx, posy),).unwrap();
-                posx += 10; // TODO: make real styling and not hardcoded strs and magic numbers
-                if posx > rect.end_x {
+        let pos = slice.pos();
+        let cols = column_offsets(layout, pos);
+        let rows = row_offsets(layout, pos);
+        for (row_idx, row) in slice.rows().enumerate() {
+            let y = pos.start.y + row_idx;
+            for (col_idx, cell) in row.into_iter().enumerate() {
+                let x = pos.start.x + col_idx;
+                let posx = rect.start_x as usize + cols[col_idx];
+                if posx > rect.end_x as usize {
                     break;
                 }
+                let w = layout.width(x).min(rect.end_x as usize + 1 - posx);
 
-                let w = std::cmp::min(9, rect.end_x - posx + 1) as usize;
-
-                if let Some(cont) = cell {
-                    let form = cont.format_to_length(w);
-                    queue!(buf, Print(&form)).unwrap();
-                } else {
-                    queue!(buf, Print(&empty_cell)).unwrap();
+                let selected = selection.is_some_and(|s| s.is_inside((x, y)));
+                if selected {
+                    queue!(buf, SetAttribute(Attribute::Reverse)).unwrap();
+                }
+                let text = match cell {
+                    Some(cont) => cont.to_string(),
+                    None if slice.table().is_pending((x, y)) => PENDING.to_owned(),
+                    None => String::new(),
                 };
+                let mut lines = text.lines();
+                for line in 0..layout.height(y) {
+                    let posy = rect.start_y as usize + rows[row_idx] + line;
+                    if posy > rect.end_y as usize {
+                        break;
+                    }
+                    queue!(
+                        buf,
+                        MoveTo(posx as u16, posy as u16),
+                        Print(fit_line(lines.next().unwrap_or(""), w))
+                    )
+                    .unwrap();
+                }
+                if selected {
+                    queue!(buf, SetAttribute(Attribute::Reset)).unwrap();
+                }
             }
-
-            posy += 2;
         }
     }
 
+    /// Draws the full value of the cell under the cursor over its neighbours
     pub fn draw_expand_cursor(
         buf: &mut impl std::io::Write,
         rect: DrawRect,
         pos: impl Into<CellPos>,
         slice: TableSlice<'_, EvaluatorTable>,
+        layout: &Layout,
     ) {
         let pos: CellPos = pos.into();
-        set_cursor(buf, rect, pos);
+        set_cursor(buf, rect, pos, slice.pos(), layout);
         if let Some(Some(cont)) = slice.get(pos) {
             queue!(buf, Print(&format!("{cont}",))).unwrap();
         }
     }
-    pub fn set_cursor(buf: &mut impl std::io::Write, rect: DrawRect, pos: impl Into<CellPos>) {
+
+    /// Places the terminal cursor at the cell at `pos` (relative to the slice)
+    pub fn set_cursor(
+        buf: &mut impl std::io::Write,
+        rect: DrawRect,
+        pos: impl Into<CellPos>,
+        slice: SlicePos,
+        layout: &Layout,
+    ) {
         let pos: CellPos = pos.into();
-        let y = rect.start_y + 1 + 2 * (pos.y as u16);
-        let x = rect.start_x + 1 + 10 * (pos.x as u16);
+        let x = column_offsets(layout, slice)
+            .get(pos.x)
+            .copied()
+            .unwrap_or(1);
+        let y = row_offsets(layout, slice).get(pos.y).copied().unwrap_or(1);
+        let x = (rect.start_x as usize + x).min(rect.end_x as usize);
+        let y = (rect.start_y as usize + y).min(rect.end_y as usize);
 
-        queue!(buf, MoveTo(x, y)).unwrap();
+        queue!(buf, MoveTo(x as u16, y as u16)).unwrap();
     }
 }
 
@@ -94,7 +232,7 @@ pub mod editor {
     use crossterm::{cursor::MoveTo, queue, style::Print};
 
     use crate::{
-        editor::{EditorState, display_sequence},
+        editor::{EditorState, display_sequence, line::LineEditor, mode::Mode},
         evaluator::EvaluatorTable,
         key::Key,
         table::slice::table::TableSlice,
@@ -102,6 +240,39 @@ pub mod editor {
 
     use super::{DrawRect, table};
 
+    /// Splits the editor's rect into the formula bar, the table and the status line
+    fn layout(rect: DrawRect) -> (DrawRect, DrawRect, DrawRect) {
+        let formula_rect = DrawRect {
+            end_y: rect.start_y,
+            ..rect
+        };
+        let table_rect = DrawRect {
+            start_y: rect.start_y + 1,
+            end_y: rect.end_y - 1,
+            ..rect
+        };
+        let status_rect = DrawRect {
+            start_y: rect.end_y,
+            ..rect
+        };
+        (formula_rect, table_rect, status_rect)
+    }
+
+    /// Fits the editor's viewport to the space the table has in the rect and scrolls it so that
+    /// the cursor is visible. Should be called before [draw]
+    pub fn update_viewport(state: &mut EditorState, rect: DrawRect) {
+        let (_, table_rect, _) = layout(rect);
+        // The column headers take the first line
+        state.view_height = (table_rect.height() as usize).saturating_sub(1);
+        state.scroll_to_cursor();
+
+        // The row headers get wider as the row numbers get longer
+        let last_row = state.viewport().end.y.saturating_sub(1);
+        state.view_width =
+            (table_rect.width() as usize).saturating_sub(table::row_header_width(last_row));
+        state.scroll_to_cursor();
+    }
+
     pub fn draw(
         buf: &mut impl std::io::Write,
         rect: DrawRect,
@@ -109,36 +280,121 @@ pub mod editor {
         seq: &[Key],
         data: TableSlice<'_, EvaluatorTable>,
     ) {
-        let mode = state.mode.to_string();
-        let seq = display_sequence(seq);
-        let width = rect.end_x - rect.start_x + 1;
-        if mode.len() + seq.len() > width as usize {
-            panic!("Not enough editor width!"); // TODO: handle this error
-        }
-        let padding_width = width as usize - mode.len() - seq.len();
+        let (formula_rect, table_rect, status_rect) = layout(rect);
 
-        let table_rect = DrawRect {
-            end_y: rect.end_y - 1,
-            ..rect
-        };
+        let pos = data.pos();
+        let grid_rect = table::grid_rect(table_rect, pos);
+        let cursor = (
+            state.cursor.x.saturating_sub(pos.start.x),
+            state.cursor.y.saturating_sub(pos.start.y),
+        );
 
-        table::draw_grid(buf, table_rect);
-        table::draw_table(buf, table_rect, data);
+        table::draw_headers(buf, table_rect, pos, &state.layout);
+        table::draw_grid(buf, grid_rect, pos, &state.layout);
+        let selection = state.mode.is_visual().then(|| state.selection());
+        table::draw_table(buf, grid_rect, data, &state.layout, selection);
         if state.expand {
-            table::draw_expand_cursor(buf, table_rect, state.cursor, data);
+            table::draw_expand_cursor(buf, grid_rect, cursor, data, &state.layout);
         }
+
+        let prefix = format!("{}: ", state.cursor);
+        match state.mode {
+            Mode::Cell => {
+                draw_status_line(buf, status_rect, state, seq);
+                draw_line(buf, formula_rect, &prefix, &state.cell_line);
+            }
+            Mode::Command => {
+                draw_formula_bar(buf, formula_rect, &prefix, state);
+                draw_line(buf, status_rect, ":", &state.command_line);
+            }
+            _ => {
+                draw_formula_bar(buf, formula_rect, &prefix, state);
+                draw_status_line(buf, status_rect, state, seq);
+                table::set_cursor(buf, grid_rect, cursor, pos, &state.layout);
+            }
+        }
+    }
+
+    fn draw_status_line(
+        buf: &mut impl std::io::Write,
+        rect: DrawRect,
+        state: &EditorState,
+        seq: &[Key],
+    ) {
+        let width = rect.width() as usize;
+        let seq = display_sequence(seq);
+        let name = state
+            .path
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| String::from("[No Name]"));
+        let dirty = if state.dirty { " [+]" } else { "" };
+        let message = state.message.as_deref().unwrap_or("");
+
+        let left = format!("{} {name}{dirty} {message}", state.mode);
+        let left_width = width.saturating_sub(seq.chars().count());
+        let left: String = left.chars().take(left_width).collect();
+        let line: String = format!("{left:-<left_width$}{seq}")
+            .chars()
+            .take(width)
+            .collect();
+
+        queue!(buf, MoveTo(rect.start_x, rect.start_y), Print(line)).unwrap();
+    }
+
+    /// Newlines can't be printed inside a single terminal line, so they are shown as a symbol
+    fn printable(text: &str) -> impl Iterator<Item = char> {
+        text.chars().map(|c| if c == '\n' { '⏎' } else { c })
+    }
+
+    fn draw_formula_bar(
+        buf: &mut impl std::io::Write,
+        rect: DrawRect,
+        prefix: &str,
+        state: &EditorState,
+    ) {
+        let width = rect.width() as usize;
+        let source = state
+            .table
+            .get_source(state.cursor)
+            .map(|s| s.as_ref())
+            .unwrap_or("");
+        let text: String = prefix
+            .chars()
+            .chain(printable(source))
+            .take(width)
+            .collect();
+
         queue!(
             buf,
-            MoveTo(rect.start_x, rect.end_y),
-            Print(format!(
-                "{mode}{:-<width$}{seq}",
-                state.expand,
-                width = padding_width
-            )),
+            MoveTo(rect.start_x, rect.start_y),
+            Print(format!("{text:<width$}"))
         )
         .unwrap();
+    }
+
+    /// Draws a [LineEditor] after a prefix, scrolling it horizontally so that the cursor is
+    /// visible, and places the terminal cursor on it
+    fn draw_line(buf: &mut impl std::io::Write, rect: DrawRect, prefix: &str, line: &LineEditor) {
+        let width = rect.width() as usize;
+        let prefix_width = prefix.chars().count();
+        let line_width = width.saturating_sub(prefix_width + 1).max(1);
+        let scroll = line.cursor().saturating_sub(line_width);
+
+        let text: String = prefix
+            .chars()
+            .chain(printable(line.text()).skip(scroll))
+            .take(width)
+            .collect();
+        let cursor_x = std::cmp::min(prefix_width + line.cursor() - scroll, width - 1) as u16;
 
-        table::set_cursor(buf, table_rect, state.cursor);
+        queue!(
+            buf,
+            MoveTo(rect.start_x, rect.start_y),
+            Print(format!("{text:<width$}")),
+            MoveTo(rect.start_x + cursor_x, rect.start_y)
+        )
+        .unwrap();
     }
 }
 
@@ -164,4 +420,8 @@ impl DrawRect {
     pub fn width(&self) -> u16 {
         self.end_x - self.start_x + 1
     }
+
+    pub fn height(&self) -> u16 {
+        self.end_y - self.start_y + 1
+    }
 }